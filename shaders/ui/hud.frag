@@ -0,0 +1,10 @@
+#version 430 core
+
+out vec4 frag_color;
+
+uniform vec3 color;
+uniform float alpha = 1.0;
+
+void main() {
+    frag_color = vec4(color, alpha);
+}
\ No newline at end of file
@@ -0,0 +1,79 @@
+//Number of frames of music that each loudness measurement covers
+pub const WINDOW_FRAMES: usize = 1024;
+
+//How loud the music is right now, for things in the world to react to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MusicEnvelope {
+    pub loudness: f32,                  //From 0 to 1, relative to the loudest the track has been recently
+    pub pulse: f32                      //Jumps to 1 on a beat and decays back to 0
+}
+
+impl MusicEnvelope {
+    pub fn silent() -> Self {
+        MusicEnvelope {
+            loudness: 0.0,
+            pulse: 0.0
+        }
+    }
+}
+
+//RMS level of each window of interleaved samples, from 0 to 1
+pub fn window_levels(samples: &[i16], channels: usize) -> Vec<f32> {
+    samples.chunks(WINDOW_FRAMES * channels).map(|window| {
+        let sum: f32 = window.iter().map(|&s| {
+            let s = s as f32 / i16::MAX as f32;
+            s * s
+        }).sum();
+        f32::sqrt(sum / window.len() as f32)
+    }).collect()
+}
+
+//Smooths window levels into an envelope and picks out beats, which are sudden jumps above the recent average
+pub struct BeatTracker {
+    envelope: f32,
+    average: f32,
+    peak: f32,
+    pulse: f32,
+    since_beat: f32
+}
+
+impl BeatTracker {
+    const ATTACK_RATE: f32 = 30.0;
+    const RELEASE_RATE: f32 = 4.0;
+    const AVERAGE_RATE: f32 = 1.0;
+    const PEAK_DECAY: f32 = 0.05;              //Fraction of the peak lost per second, so quiet tracks still use the whole range
+    const MIN_PEAK: f32 = 0.02;
+    const BEAT_THRESHOLD: f32 = 1.35;          //How far above the average a level has to be to count as a beat
+    const MIN_BEAT_LEVEL: f32 = 0.01;
+    const MIN_BEAT_INTERVAL: f32 = 0.25;
+    const PULSE_DECAY: f32 = 6.0;
+
+    pub fn new() -> Self {
+        BeatTracker {
+            envelope: 0.0,
+            average: 0.0,
+            peak: Self::MIN_PEAK,
+            pulse: 0.0,
+            since_beat: 0.0
+        }
+    }
+
+    pub fn update(&mut self, level: f32, delta_time: f32) -> MusicEnvelope {
+        let rate = if level > self.envelope { Self::ATTACK_RATE } else { Self::RELEASE_RATE };
+        self.envelope += (level - self.envelope) * f32::min(rate * delta_time, 1.0);
+        self.average += (level - self.average) * f32::min(Self::AVERAGE_RATE * delta_time, 1.0);
+        self.peak = f32::max(f32::max(self.peak * (1.0 - Self::PEAK_DECAY * delta_time), self.envelope), Self::MIN_PEAK);
+
+        self.since_beat += delta_time;
+        self.pulse *= f32::exp(-Self::PULSE_DECAY * delta_time);
+        if level > Self::MIN_BEAT_LEVEL && level > self.average * Self::BEAT_THRESHOLD && self.since_beat > Self::MIN_BEAT_INTERVAL {
+            self.pulse = 1.0;
+            self.since_beat = 0.0;
+        }
+
+        MusicEnvelope {
+            loudness: f32::min(self.envelope / self.peak, 1.0),
+            pulse: self.pulse
+        }
+    }
+}
@@ -1,14 +1,197 @@
-use alto::{sys::ALint, Source, SourceState};
-use tfd::MessageBoxIcon;
-use std::fs::File;
-use std::io::{ErrorKind, Seek, SeekFrom};
-use std::sync::mpsc::Receiver;
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{Receiver, Sender};
+use std::mem;
+use std::path::Path;
 use std::process::exit;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use strum::EnumCount;
+use crate::analysis::{window_levels, BeatTracker, MusicEnvelope, WINDOW_FRAMES};
+use crate::audio_backend::{AudioBackend, BackendChoice, OfflineBackend, OpenAlBackend, PlaybackState, BufferId, SourceId};
+use crate::decoder::{AudioDecoder, LoopPoints, Resampler};
+use crate::playlist::{track_name, Playlist, PlaylistStatus, RepeatMode};
+use crate::reverb::{blend_zones, ReverbParams, ReverbZone};
+use crate::structs::Configuration;
 
 const DEFAULT_BGM_PATH: &str = "music/ikebukuro.mp3";
-const IDEAL_FRAMES_QUEUED: ALint = 10;
+const MUSIC_DIRECTORY: &str = "music/";
+const IDEAL_FRAMES_QUEUED: usize = 10;
+const SFX_VOICE_COUNT: usize = 16;
+const POSITION_UPDATE_INTERVAL: f32 = 0.25;
+
+//Bad frames are skipped, but this many in a row means the rest of the file is probably garbage too
+const MAX_DECODE_ERRORS: usize = 8;
+
+//Source index of the background music. Every other index refers to a sound effect handle
+pub const BGM_SOURCE: usize = 0;
+
+//Seconds spent fading between background music tracks
+pub const DEFAULT_CROSSFADE: f32 = 2.0;
+
+//Sound effects used by the game. Anything that isn't synthesized is loaded from a file the first time it's played
+pub const CHIME_SOUND: &str = "chime";
+pub const SHOTGUN_SOUND: &str = "sfx/shotgun.mp3";
+pub const WATER_SPRAY_SOUND: &str = "sfx/water_spray.mp3";
+pub const LANDING_SOUND: &str = "sfx/landing.mp3";
+pub const TOTORO_SPAWN_SOUND: &str = "sfx/totoro_spawn.mp3";
+
+//Groups of sounds whose volume is set together
+//The master bus scales everything, including the other buses
+#[derive(Copy, Clone, Debug, EnumCount, PartialEq, Eq)]
+pub enum AudioBus {
+    Master,
+    Music,
+    Sfx,
+    Ui,
+    Ambience
+}
+
+impl AudioBus {
+    pub fn from_usize(i: usize) -> Self {
+        match i {
+            0 => { AudioBus::Master }
+            1 => { AudioBus::Music }
+            2 => { AudioBus::Sfx }
+            3 => { AudioBus::Ui }
+            4 => { AudioBus::Ambience }
+            _ => { panic!("{} is out of range", i); }
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AudioBus::Master => { "Master" }
+            AudioBus::Music => { "Music" }
+            AudioBus::Sfx => { "Effects" }
+            AudioBus::Ui => { "UI" }
+            AudioBus::Ambience => { "Ambience" }
+        }
+    }
+
+    //Keys of the bus's volume and mute settings in the configuration
+    fn config_keys(self) -> (&'static str, &'static str) {
+        match self {
+            AudioBus::Master => { (Configuration::MASTER_VOLUME, Configuration::MASTER_MUTED) }
+            AudioBus::Music => { (Configuration::MUSIC_VOLUME, Configuration::MUSIC_MUTED) }
+            AudioBus::Sfx => { (Configuration::SFX_VOLUME, Configuration::SFX_MUTED) }
+            AudioBus::Ui => { (Configuration::UI_VOLUME, Configuration::UI_MUTED) }
+            AudioBus::Ambience => { (Configuration::AMBIENCE_VOLUME, Configuration::AMBIENCE_MUTED) }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BusSettings {
+    pub volume: f32,                    //From 0 to 100
+    pub muted: bool
+}
+
+impl BusSettings {
+    pub fn gain(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            linearized_gain(self.volume)
+        }
+    }
+}
+
+//The settings of every bus, indexed by AudioBus
+#[derive(Clone, Copy, Debug)]
+pub struct Mixer {
+    pub buses: [BusSettings; AudioBus::COUNT]
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut buses = [BusSettings { volume: 100.0, muted: false }; AudioBus::COUNT];
+        buses[AudioBus::Master as usize].volume = 20.0;
+        buses[AudioBus::Music as usize].volume = 70.0;
+        Mixer {
+            buses
+        }
+    }
+
+    //Starts from the defaults and takes whatever settings the configuration has
+    pub fn from_config(config: &Configuration) -> Self {
+        let mut mixer = Self::new();
+        for i in 0..AudioBus::COUNT {
+            let (volume_key, muted_key) = AudioBus::from_usize(i).config_keys();
+            if let Some(volume) = config.float_options.get(volume_key) {
+                mixer.buses[i].volume = f32::max(0.0, f32::min(*volume, 100.0));
+            }
+            if let Some(muted) = config.int_options.get(muted_key) {
+                mixer.buses[i].muted = *muted != 0;
+            }
+        }
+        mixer
+    }
+
+    pub fn save_to_config(&self, config: &mut Configuration) {
+        for i in 0..AudioBus::COUNT {
+            let (volume_key, muted_key) = AudioBus::from_usize(i).config_keys();
+            config.float_options.insert(String::from(volume_key), self.buses[i].volume);
+            config.int_options.insert(String::from(muted_key), self.buses[i].muted as u32);
+        }
+    }
+
+    pub fn gain(&self, bus: AudioBus) -> f32 {
+        self.buses[bus as usize].gain()
+    }
+}
+
+//Asks the audio thread to play a sound effect
+pub struct SoundRequest {
+    pub sound: String,                  //Path of the file to play, or the name of a synthesized sound
+    pub handle: Option<usize>,          //Lets the sound be moved with SetSourcePosition or stopped with StopSound later
+    pub bus: AudioBus,
+    pub position: [f32; 3],
+    pub gain: f32,
+    pub pitch: f32,
+    pub looping: bool
+}
+
+impl SoundRequest {
+    pub fn one_shot(sound: &str, position: [f32; 3]) -> Self {
+        SoundRequest {
+            sound: String::from(sound),
+            handle: None,
+            bus: AudioBus::Sfx,
+            position,
+            gain: 1.0,
+            pitch: 1.0,
+            looping: false
+        }
+    }
+}
+
+//Hands out the handles the main thread uses to refer to sounds it asked the audio thread to play
+pub struct SoundHandles {
+    next: usize
+}
+
+impl SoundHandles {
+    pub fn new() -> Self {
+        SoundHandles {
+            next: BGM_SOURCE + 1
+        }
+    }
+
+    pub fn next(&mut self) -> usize {
+        let handle = self.next;
+        self.next += 1;
+        handle
+    }
+}
+
+//One of the sources in the sound effect pool
+struct Voice {
+    source: SourceId,
+    handle: Option<usize>,
+    bus: AudioBus,
+    gain: f32,                          //Gain of the sound itself, before the bus is applied
+    started: u64                        //When the voice was last started, used to pick which voice to steal
+}
 
 //Represents the kinds of messages the audio system can receive from the 
 pub enum AudioCommand {
@@ -16,182 +199,828 @@ pub enum AudioCommand {
     SetListenerVelocity([f32; 3]),
     SetListenerOrientation(([f32; 3], [f32; 3])),
     SetSourcePosition([f32; 3], usize),
-    SetListenerGain(f32),
+    SetBus(AudioBus, BusSettings),
+    SetReverbZones(Vec<ReverbZone>),
+    PlaySound(SoundRequest),
+    StopSound(usize),
     SelectNewBGM,
+    SetBGM(String),
+    SetPlaylist(Vec<String>),
+    NextTrack,
+    PreviousTrack,
+    PlayQueuePosition(usize),
+    SetShuffle(bool),
+    SetRepeat(RepeatMode),
+    SetCrossfade(f32),
     RestartBGM,
+    SeekBGM(f32),                               //Seconds into the current track
     PlayPause
 }
 
-//Returns an mp3 decoder given a filepath
-fn load_decoder(path: &str) -> Option<mp3::Decoder<File>> {
-    match File::open(path) {
-        Ok(f) => { 
-            Some(mp3::Decoder::new(f))
+//Represents the kinds of messages the audio system sends back to the main thread
+pub enum AudioEvent {
+    Initialized,
+    InitFailed(String),
+    TrackStarted(String, Option<f32>),          //Track name and its length in seconds, if the format says
+    TrackEnded(String),                         //Sent once the track has finished fading out, which is usually after the next one started
+    Stopped,                                    //Nothing is left to play
+    TrackLooping(LoopPoints),                   //Sent right after TrackStarted if the track has loop points
+    Position(f32),                              //Seconds into the current track
+    DecodeError(String),
+    PlaylistChanged(PlaylistStatus),
+    Envelope(MusicEnvelope)                     //Loudness of the music that's playing right now
+}
+
+//Everything the main thread has heard from the audio thread
+pub struct AudioStatus {
+    pub initialized: Option<Result<(), String>>,        //None until the audio thread reports in
+    pub track: Option<String>,
+    pub track_length: Option<f32>,
+    pub track_position: f32,
+    pub track_loop: Option<LoopPoints>,
+    pub last_error: Option<String>,
+    pub playlist: PlaylistStatus,
+    pub music: MusicEnvelope
+}
+
+impl AudioStatus {
+    pub fn new() -> Self {
+        AudioStatus {
+            initialized: None,
+            track: None,
+            track_length: None,
+            track_position: 0.0,
+            track_loop: None,
+            last_error: None,
+            playlist: PlaylistStatus::new(),
+            music: MusicEnvelope::silent()
         }
-        Err(e) => {
-            tfd::message_box_ok("Error loading mp3", &format!("Unable to open \"{}\"\n{}", path, e), MessageBoxIcon::Error);
-            None
+    }
+
+    pub fn handle_event(&mut self, event: AudioEvent) {
+        match event {
+            AudioEvent::Initialized => { self.initialized = Some(Ok(())); }
+            AudioEvent::InitFailed(reason) => { self.initialized = Some(Err(reason)); }
+            AudioEvent::TrackStarted(name, length) => {
+                self.track = Some(name);
+                self.track_length = length;
+                self.track_position = 0.0;
+                self.track_loop = None;
+            }
+            AudioEvent::TrackEnded(_) => {}
+            AudioEvent::Stopped => {
+                self.track = None;
+                self.track_length = None;
+                self.track_position = 0.0;
+                self.track_loop = None;
+            }
+            AudioEvent::TrackLooping(points) => { self.track_loop = Some(points); }
+            AudioEvent::Position(seconds) => { self.track_position = seconds; }
+            AudioEvent::DecodeError(e) => {
+                println!("{}", e);
+                self.last_error = Some(e);
+            }
+            AudioEvent::PlaylistChanged(status) => { self.playlist = status; }
+            AudioEvent::Envelope(envelope) => { self.music = envelope; }
         }
-    }    
+    }
 }
 
-fn set_linearized_gain(ctxt: &alto::Context, volume: f32) {
-    let gain_factor = (f32::exp(volume / 100.0) - 1.0) / (glm::e::<f32>() - 1.0);
-    ctxt.set_gain(gain_factor).unwrap();
+fn send_event(event_sender: &Sender<AudioEvent>, event: AudioEvent) {
+    if let Err(e) = event_sender.send(event) {
+        println!("Error sending audio event: {}", e);
+    }
 }
 
-//Main function for the audio system
-pub fn audio_main(audio_receiver: Receiver<AudioCommand>, bgm_volume: f32) {
-    thread::spawn(move || {
-        //Initializing the OpenAL context
-        //This can fail if OpenAL is not installed on the host system
-        let alto_context = match alto::Alto::load_default() {
-            Ok(a) => { 
-                let alto = a;
-                match alto.default_output() {
-                    Some(string) => {
-                        match alto.open(Some(&string)) {
-                            Ok(dev) => {
-                                match dev.new_context(None) {
-                                    Ok(ctxt) => { ctxt }
-                                    Err(e) => {
-                                        tfd::message_box_ok("OpenAL Error", &format!("Error creating OpenAL context: {}\n\nThe game will still work, but without any audio.", e), tfd::MessageBoxIcon::Warning);
-                                        return;
-                                    }
+//What the player remembers about each buffer it has queued
+struct QueuedBuffer {
+    start: f32,                                 //Seconds into the track, which jumps back whenever the track loops
+    length: f32,                                //Seconds
+    sample_rate: i32,
+    levels: Vec<f32>                            //Loudness of each analysis window
+}
+
+//A streaming source and the track being decoded onto it
+struct MusicStream {
+    source: SourceId,
+    decoder: Option<AudioDecoder>,
+    track: Option<String>,
+    queued_buffers: VecDeque<QueuedBuffer>,
+    resampler: Option<Resampler>,               //Converts every frame to the format of the buffers already on the source
+    finishing: bool                             //Whether the track is being replaced because it ran out
+}
+
+impl MusicStream {
+    fn new(backend: &mut dyn AudioBackend, position: [f32; 3], gain: f32) -> Self {
+        let source = backend.new_streaming_source().unwrap();
+        backend.set_position(source, position);
+        backend.set_gain(source, gain);
+        MusicStream {
+            source,
+            decoder: None,
+            track: None,
+            queued_buffers: VecDeque::new(),
+            resampler: None,
+            finishing: false
+        }
+    }
+
+    //Queues another frame if there are fewer than the ideal number queued, returning false once the track has run out
+    //Frames that fail to decode are skipped, giving up on the track if too many fail in a row
+    fn fill(&mut self, backend: &mut dyn AudioBackend, event_sender: &Sender<AudioEvent>) -> bool {
+        if backend.buffers_queued(self.source) >= IDEAL_FRAMES_QUEUED {
+            return true;
+        }
+        let decoder = match &mut self.decoder {
+            Some(decoder) => { decoder }
+            None => { return true; }
+        };
+
+        let mut errors = 0;
+        loop {
+            match decoder.next_frame() {
+                Ok(Some(frame)) => {
+                    if frame.channels == 0 {
+                        continue;
+                    }
+
+                    //Every buffer on a source has to match, so convert to the format of the track's first frame
+                    let resampler = self.resampler.get_or_insert_with(|| Resampler::new(frame.channels, frame.sample_rate));
+                    let (channels, sample_rate) = (resampler.channels(), resampler.sample_rate());
+                    let frame = resampler.convert(frame);
+                    let length = frame.samples.len() as f32 / (channels * sample_rate.max(1) as usize) as f32;
+                    let queued = QueuedBuffer {
+                        start: decoder.position() - length,
+                        length,
+                        sample_rate,
+                        levels: window_levels(&frame.samples, channels)
+                    };
+                    match backend.queue_samples(self.source, &frame.samples, channels, sample_rate) {
+                        Ok(_) => { self.queued_buffers.push_back(queued); }
+                        Err(e) => { println!("Error queueing audio frame: {}", e); }
+                    }
+                    return true;
+                }
+                Ok(None) => { return false; }
+                Err(e) => {
+                    send_event(event_sender, AudioEvent::DecodeError(format!("Skipping bad audio frame: {}", e)));
+                    errors += 1;
+                    if errors >= MAX_DECODE_ERRORS {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+
+    fn unqueue_processed(&mut self, backend: &mut dyn AudioBackend) {
+        for _ in 0..backend.unqueue_processed(self.source) {
+            self.queued_buffers.pop_front();
+        }
+    }
+
+    //The queued buffer that's playing right now and how many seconds into it playback is
+    fn current_buffer(&self, backend: &dyn AudioBackend) -> Option<(&QueuedBuffer, f32)> {
+        let mut offset = backend.sec_offset(self.source);
+        for buffer in self.queued_buffers.iter() {
+            if offset < buffer.length {
+                return Some((buffer, offset));
+            }
+            offset -= buffer.length;
+        }
+        None
+    }
+
+    //Seconds of the track left to be heard, or None if it loops forever or its length isn't known
+    fn remaining(&self, backend: &dyn AudioBackend) -> Option<f32> {
+        let decoder = self.decoder.as_ref()?;
+        if decoder.loops() {
+            return None;
+        }
+        let (buffer, offset) = self.current_buffer(backend)?;
+        Some(decoder.length()? - (buffer.start + offset))
+    }
+}
+
+//Streams the background music, holding on to the previous track's stream while it fades out
+struct BgmPlayer {
+    stream: MusicStream,
+    fading: Option<MusicStream>,
+    fade_start: Instant,
+    crossfade: f32,
+    volume: f32,                                //Gain of the music bus
+    position: [f32; 3],
+    kickstart: bool,
+    last_position_update: Instant,
+    beat_tracker: BeatTracker,
+    last_envelope_update: Instant,
+    envelope_silent: bool,                      //Whether the last envelope sent was silence, so silence is only sent once
+    event_sender: Sender<AudioEvent>
+}
+
+impl BgmPlayer {
+    fn new(backend: &mut dyn AudioBackend, event_sender: Sender<AudioEvent>, volume: f32) -> Self {
+        BgmPlayer {
+            stream: MusicStream::new(backend, [0.0; 3], volume),
+            fading: None,
+            fade_start: Instant::now(),
+            crossfade: DEFAULT_CROSSFADE,
+            volume,
+            position: [0.0; 3],
+            kickstart: true,
+            last_position_update: Instant::now(),
+            beat_tracker: BeatTracker::new(),
+            last_envelope_update: Instant::now(),
+            envelope_silent: false,
+            event_sender
+        }
+    }
+
+    //Gets rid of a stream that's done playing, reporting its track as ended if it ran out
+    fn retire(&mut self, backend: &mut dyn AudioBackend, stream: MusicStream) {
+        backend.delete_source(stream.source);
+        if stream.finishing {
+            if let Some(track) = stream.track {
+                send_event(&self.event_sender, AudioEvent::TrackEnded(track));
+            }
+        }
+    }
+
+    //Switches to a new track, or to silence if there isn't one, fading out whatever was playing
+    //The outgoing track keeps being decoded for as long as the fade lasts
+    //Returns false if the track couldn't be opened
+    fn start_track(&mut self, backend: &mut dyn AudioBackend, path: Option<&str>) -> bool {
+        let old_stream = mem::replace(&mut self.stream, MusicStream::new(backend, self.position, self.volume));
+        if let Some(fading) = self.fading.take() {
+            self.retire(backend, fading);
+        }
+        if self.crossfade > 0.0 && backend.state(old_stream.source) == PlaybackState::Playing {
+            backend.set_gain(self.stream.source, 0.0);
+            self.fading = Some(old_stream);
+        } else {
+            self.retire(backend, old_stream);
+        }
+        self.fade_start = Instant::now();
+        self.kickstart = true;
+
+        match path {
+            Some(path) => {
+                match AudioDecoder::open(path) {
+                    Ok(mut decoder) => {
+                        let name = track_name(path);
+                        send_event(&self.event_sender, AudioEvent::TrackStarted(name.clone(), decoder.length()));
+                        match LoopPoints::load(path) {
+                            Ok(points) => {
+                                decoder.set_loop_points(points);
+                                if let Some(points) = points {
+                                    send_event(&self.event_sender, AudioEvent::TrackLooping(points));
                                 }
                             }
-                            Err(e) => {
-                                tfd::message_box_ok("OpenAL Error", &format!("Error opening default audio device: {}\n\nThe game will still work, but without any audio.", e), tfd::MessageBoxIcon::Warning);
-                                return;
-                            }
+                            Err(e) => { send_event(&self.event_sender, AudioEvent::DecodeError(format!("Ignoring loop points for \"{}\": {}", path, e))); }
                         }
+                        self.stream.decoder = Some(decoder);
+                        self.stream.track = Some(name);
+                        true
                     }
-                    None => {
-                        tfd::message_box_ok("OpenAL Error", "No default audio output device found\n\nThe game will still work, but without any audio.", tfd::MessageBoxIcon::Warning);
-                        return;
+                    Err(e) => {
+                        send_event(&self.event_sender, AudioEvent::DecodeError(format!("Unable to open \"{}\": {}", path, e)));
+                        false
                     }
                 }
             }
+            None => {
+                send_event(&self.event_sender, AudioEvent::Stopped);
+                true
+            }
+        }
+    }
+
+    //Starts the playlist's current track, moving on past any that can't be opened
+    fn play_current(&mut self, backend: &mut dyn AudioBackend, playlist: &mut Playlist) {
+        let mut started = false;
+        for _ in 0..playlist.track_count() {
+            let path = match playlist.current() {
+                Some(path) => { String::from(path) }
+                None => { break; }
+            };
+            if self.start_track(backend, Some(&path)) {
+                started = true;
+                break;
+            }
+            playlist.advance(true);
+        }
+        if !started {
+            self.start_track(backend, None);
+        }
+        send_event(&self.event_sender, AudioEvent::PlaylistChanged(playlist.status()));
+    }
+
+    //Keeps both streams decoding, returning true when it's time to move on to the next track
+    //With a crossfade the next track starts early, so that the fade is over right as the current one runs out
+    //The last track of the playlist has nothing to fade into, so it plays right to the end
+    fn update_streams(&mut self, backend: &mut dyn AudioBackend, has_next: bool) -> bool {
+        let mut track_ended = !self.stream.fill(backend, &self.event_sender);
+        if self.crossfade > 0.0 && self.fading.is_none() && has_next {
+            if let Some(remaining) = self.stream.remaining(backend) {
+                track_ended |= remaining <= self.crossfade;
+            }
+        }
+        if let Some(fading) = &mut self.fading {
+            fading.fill(backend, &self.event_sender);
+            fading.unqueue_processed(backend);
+        }
+        self.stream.finishing = track_ended;
+        track_ended
+    }
+
+    //Jumps to a point in the current track, throwing away whatever was already queued
+    fn seek(&mut self, backend: &mut dyn AudioBackend, seconds: f32) {
+        if self.stream.decoder.is_none() {
+            return;
+        }
+        let new_source = backend.new_streaming_source().unwrap();
+        backend.delete_source(mem::replace(&mut self.stream.source, new_source));
+        backend.set_position(self.stream.source, self.position);
+        backend.set_gain(self.stream.source, self.volume);
+        self.kickstart = true;
+        self.stream.queued_buffers.clear();
+        self.stream.resampler = None;
+        if let Some(decoder) = &mut self.stream.decoder {
+            match decoder.seek(seconds) {
+                Ok(_) => { send_event(&self.event_sender, AudioEvent::Position(seconds)); }
+                Err(e) => { send_event(&self.event_sender, AudioEvent::DecodeError(format!("Error seeking the bgm: {}", e))); }
+            }
+        }
+    }
+
+    //Sends the playback position of the current track every so often
+    fn report_position(&mut self, backend: &dyn AudioBackend) {
+        if self.stream.track.is_some() && self.last_position_update.elapsed().as_secs_f32() >= POSITION_UPDATE_INTERVAL {
+            if let Some((buffer, offset)) = self.stream.current_buffer(backend) {
+                send_event(&self.event_sender, AudioEvent::Position(buffer.start + offset));
+            }
+            self.last_position_update = Instant::now();
+        }
+    }
+
+    //Follows the loudness of whichever window of the current buffer is playing
+    fn report_envelope(&mut self, backend: &dyn AudioBackend) {
+        let delta_time = self.last_envelope_update.elapsed().as_secs_f32();
+        self.last_envelope_update = Instant::now();
+
+        let level = match self.stream.current_buffer(backend) {
+            Some((buffer, offset)) if backend.state(self.stream.source) == PlaybackState::Playing => {
+                let window = (offset * buffer.sample_rate as f32) as usize / WINDOW_FRAMES;
+                buffer.levels.get(window).or(buffer.levels.last()).copied().unwrap_or(0.0)
+            }
+            _ => { 0.0 }
+        };
+
+        let envelope = self.beat_tracker.update(level, delta_time);
+        let silent = envelope.loudness < 0.001 && envelope.pulse < 0.001;
+        if !(silent && self.envelope_silent) {
+            let envelope = if silent { MusicEnvelope::silent() } else { envelope };
+            send_event(&self.event_sender, AudioEvent::Envelope(envelope));
+        }
+        self.envelope_silent = silent;
+    }
+
+    //Ramps the volume of the incoming and outgoing tracks
+    fn update_fade(&mut self, backend: &mut dyn AudioBackend) {
+        let t = if self.crossfade > 0.0 { self.fade_start.elapsed().as_secs_f32() / self.crossfade } else { 1.0 };
+        if t >= 1.0 {
+            if let Some(fading) = self.fading.take() {
+                self.retire(backend, fading);
+                backend.set_gain(self.stream.source, self.volume);
+            }
+        } else if let Some(fading) = &self.fading {
+            backend.set_gain(fading.source, (1.0 - t) * self.volume);
+            backend.set_gain(self.stream.source, t * self.volume);
+        }
+    }
+
+    //Fades in progress pick up the new volume the next time they're updated
+    fn set_volume(&mut self, backend: &mut dyn AudioBackend, volume: f32) {
+        self.volume = volume;
+        if self.fading.is_none() {
+            backend.set_gain(self.stream.source, volume);
+        }
+    }
+
+    //Stops the outgoing track right away
+    fn cancel_fade(&mut self, backend: &mut dyn AudioBackend) {
+        if let Some(fading) = self.fading.take() {
+            self.retire(backend, fading);
+        }
+    }
+
+    fn set_position(&mut self, backend: &mut dyn AudioBackend, position: [f32; 3]) {
+        self.position = position;
+        backend.set_position(self.stream.source, position);
+        if let Some(fading) = &self.fading {
+            backend.set_position(fading.source, position);
+        }
+    }
+}
+
+//Maps a volume from 0 to 100 onto a gain along an exponential curve, which sounds closer to linear
+fn linearized_gain(volume: f32) -> f32 {
+    (f32::exp(volume / 100.0) - 1.0) / (glm::e::<f32>() - 1.0)
+}
+
+//Decodes a whole audio file for use as a sound effect
+//Sound effects are mixed down to mono because OpenAL only positions mono sounds
+fn load_sound(path: &str) -> Result<(Vec<i16>, i32), String> {
+    let mut decoder = AudioDecoder::open(path).map_err(|e| format!("Unable to open sound \"{}\": {}", path, e))?;
+
+    let mut samples = Vec::new();
+    let mut resampler: Option<Resampler> = None;
+    let mut errors = 0;
+    loop {
+        match decoder.next_frame() {
+            Ok(Some(frame)) => {
+                errors = 0;
+                if frame.channels == 0 {
+                    continue;
+                }
+
+                //Everything gets resampled to the rate of the first frame
+                let resampler = resampler.get_or_insert_with(|| Resampler::new(1, frame.sample_rate));
+                samples.extend(resampler.convert(frame).samples);
+            }
+            Ok(None) => { break; }
+            Err(e) => {
+                errors += 1;
+                println!("Skipping bad frame in sound \"{}\": {}", path, e);
+                if errors >= MAX_DECODE_ERRORS {
+                    return Err(format!("Too many errors decoding sound \"{}\"", path));
+                }
+            }
+        }
+    }
+
+    match resampler {
+        Some(resampler) => { Ok((samples, resampler.sample_rate())) }
+        None => { Err(format!("Sound \"{}\" has no audio in it", path)) }
+    }
+}
+
+//Synthesizes a short two-note chime, used as feedback for collecting things
+fn chime_samples(sample_rate: i32) -> Vec<i16> {
+    const NOTE_LENGTH: f32 = 0.12;
+    const DECAY: f32 = 6.0;
+    let notes = [1318.5, 1975.5];       //E6 and B6
+    let note_samples = (NOTE_LENGTH * sample_rate as f32) as usize;
+    let total_samples = note_samples * (notes.len() + 2);
+    let mut samples = Vec::with_capacity(total_samples);
+    for i in 0..total_samples {
+        let t = i as f32 / sample_rate as f32;
+        let mut value = 0.0;
+
+        //Each note keeps ringing after the next one starts
+        for n in 0..notes.len() {
+            let start = n as f32 * NOTE_LENGTH;
+            if t >= start {
+                let local_t = t - start;
+                value += f32::sin(glm::two_pi::<f32>() * notes[n] * local_t) * f32::exp(-DECAY * local_t);
+            }
+        }
+        samples.push((value * 0.3 * i16::MAX as f32) as i16);
+    }
+    samples
+}
+
+//Main function for the audio system
+pub fn audio_main(audio_receiver: Receiver<AudioCommand>, event_sender: Sender<AudioEvent>, backend_choice: BackendChoice, mut mixer: Mixer) {
+    thread::spawn(move || {
+        //Without a working device, carry on with the null backend so the playlist still behaves
+        let backend_result: Result<Box<dyn AudioBackend>, String> = match backend_choice {
+            BackendChoice::OpenAl => { OpenAlBackend::new().map(|backend| Box::new(backend) as Box<dyn AudioBackend>) }
+            BackendChoice::Null => { Ok(Box::new(OfflineBackend::null())) }
+            BackendChoice::Wav(path) => { OfflineBackend::to_wav(&path).map(|backend| Box::new(backend) as Box<dyn AudioBackend>) }
+        };
+        let mut backend: Box<dyn AudioBackend> = match backend_result {
+            Ok(backend) => {
+                send_event(&event_sender, AudioEvent::Initialized);
+                backend
+            }
             Err(e) => {
-                tfd::message_box_ok("OpenAL Error", &format!("Error initializing OpenAL: {}\n\nThe game will still work, but without any audio.", e), tfd::MessageBoxIcon::Warning);
-                return;
+                send_event(&event_sender, AudioEvent::InitFailed(e));
+                Box::new(OfflineBackend::null())
             }
         };
-        set_linearized_gain(&alto_context, bgm_volume);
+        let backend = backend.as_mut();
+        backend.set_listener_gain(mixer.gain(AudioBus::Master));
 
-        //Initialize the mp3 decoder with the default bgm
-        let mut decoder = load_decoder(DEFAULT_BGM_PATH);
+        //Start the playlist with the default bgm if it's there
+        let mut playlist = Playlist::from_directory(MUSIC_DIRECTORY);
+        let mut bgm = BgmPlayer::new(backend, event_sender.clone(), mixer.gain(AudioBus::Music));
+        if Path::new(DEFAULT_BGM_PATH).is_file() {
+            playlist.play_path(DEFAULT_BGM_PATH);
+        } else {
+            playlist.advance(false);
+        }
+        bgm.play_current(backend, &mut playlist);
 
-        let mut kanye_source = alto_context.new_streaming_source().unwrap();
-        let mut kickstart_bgm = true;
+        //Pool of sources for sound effects
+        let mut voices = Vec::with_capacity(SFX_VOICE_COUNT);
+        for _ in 0..SFX_VOICE_COUNT {
+            match backend.new_static_source() {
+                Ok(source) => { voices.push(Voice { source, handle: None, bus: AudioBus::Sfx, gain: 1.0, started: 0 }); }
+                Err(e) => {
+                    println!("Only able to create {} sound effect sources: {}", voices.len(), e);
+                    break;
+                }
+            }
+        }
+        let mut voices_started = 0;
+
+        //Decoded sound effects by name. Sounds that failed to load are remembered so they aren't retried every time
+        let mut sound_cache: HashMap<String, Option<BufferId>> = HashMap::new();
+        const CHIME_SAMPLE_RATE: i32 = 44100;
+        match backend.new_buffer(&chime_samples(CHIME_SAMPLE_RATE), 1, CHIME_SAMPLE_RATE) {
+            Ok(buffer) => { sound_cache.insert(String::from(CHIME_SOUND), Some(buffer)); }
+            Err(e) => { println!("Error creating chime buffer: {}", e); }
+        }
+        //The reverb is blended from the zones around the listener, and only sent to the backend when it changes
+        let mut reverb_zones: Vec<ReverbZone> = Vec::new();
+        let mut listener_position = glm::zero();
+        let mut current_reverb = ReverbParams::dry();
+        backend.set_reverb(&current_reverb);
         loop {
             //Process all commands from the main thread
             while let Ok(command) = audio_receiver.try_recv() {
                 match command {
-                    AudioCommand::SetListenerPosition(pos) => { alto_context.set_position(pos).unwrap(); }
-                    AudioCommand::SetListenerVelocity(vel) => { alto_context.set_velocity(vel).unwrap(); }
-                    AudioCommand::SetListenerOrientation(ori) => { alto_context.set_orientation(ori).unwrap(); }
-                    AudioCommand::SetSourcePosition(pos, i) => { kanye_source.set_position(pos).unwrap(); }
-                    AudioCommand::SetListenerGain(volume) => { set_linearized_gain(&alto_context, volume); }
-                    AudioCommand::SelectNewBGM => {
-                        kanye_source.pause();
-                        match tfd::open_file_dialog("Choose bgm", "music/", Some((&["*.mp3"], "mp3 files (*.mp3)"))) {
-                            Some(res) => {
-                                kanye_source.stop();
-                                decoder = load_decoder(&res);
-                            
-                                //Clear out any residual sound data from the old mp3
-                                kanye_source = alto_context.new_streaming_source().unwrap();
-                                kickstart_bgm = true;
+                    AudioCommand::SetListenerPosition(pos) => {
+                        backend.set_listener_position(pos);
+                        listener_position = glm::make_vec3(&pos);
+                    }
+                    AudioCommand::SetListenerVelocity(vel) => { backend.set_listener_velocity(vel); }
+                    AudioCommand::SetListenerOrientation(ori) => { backend.set_listener_orientation(ori); }
+                    AudioCommand::SetSourcePosition(pos, i) => {
+                        if i == BGM_SOURCE {
+                            bgm.set_position(backend, pos);
+                        } else {
+                            for voice in voices.iter().filter(|voice| voice.handle == Some(i)) {
+                                backend.set_position(voice.source, pos);
                             }
-                            None => { kanye_source.play(); }
                         }
                     }
-                    AudioCommand::RestartBGM => {
-                        println!("Looping the mp3");
-                        //kanye_source.pause();
-                        if let Some(decoder) = &mut decoder {
-                            kanye_source = alto_context.new_streaming_source().unwrap();
-                            decoder.reader_mut().seek(SeekFrom::Start(0)).unwrap();
+                    AudioCommand::SetReverbZones(zones) => { reverb_zones = zones; }
+                    AudioCommand::SetBus(bus, settings) => {
+                        mixer.buses[bus as usize] = settings;
+                        match bus {
+                            AudioBus::Master => { backend.set_listener_gain(mixer.gain(bus)); }
+                            AudioBus::Music => { bgm.set_volume(backend, mixer.gain(bus)); }
+                            _ => {
+                                for voice in voices.iter().filter(|voice| voice.bus == bus) {
+                                    backend.set_gain(voice.source, voice.gain * mixer.gain(bus));
+                                }
+                            }
                         }
                     }
-                    AudioCommand::PlayPause => {
-                        kickstart_bgm = !kickstart_bgm;
-                        match kanye_source.state() {
-                            SourceState::Playing | SourceState::Initial => {
-                                kanye_source.pause();
+                    AudioCommand::PlaySound(request) => {
+                        let sound_buffer = match sound_cache.get(&request.sound) {
+                            Some(buffer) => { *buffer }
+                            None => {
+                                let loaded = load_sound(&request.sound).and_then(|(samples, sample_rate)| {
+                                    backend.new_buffer(&samples, 1, sample_rate).map_err(|e| format!("Error creating buffer for sound \"{}\": {}", request.sound, e))
+                                });
+                                let buffer = match loaded {
+                                    Ok(buffer) => { Some(buffer) }
+                                    Err(e) => {
+                                        send_event(&event_sender, AudioEvent::DecodeError(e));
+                                        None
+                                    }
+                                };
+                                sound_cache.insert(request.sound.clone(), buffer);
+                                buffer
                             }
-                            SourceState::Paused | SourceState::Stopped => {
-                                kanye_source.play();
+                        };
+                        let sound_buffer = match sound_buffer {
+                            Some(buffer) => { buffer }
+                            None => { continue; }
+                        };
+
+                        //Use an idle voice, or else cut off the one that's been playing the longest, preferring one-shots over loops
+                        let voice_index = match voices.iter().position(|voice| backend.state(voice.source) != PlaybackState::Playing) {
+                            Some(index) => { Some(index) }
+                            None => {
+                                (0..voices.len()).min_by_key(|&index| {
+                                    let voice = &voices[index];
+                                    (backend.looping(voice.source), voice.started)
+                                })
                             }
-                            SourceState::Unknown(code) => { println!("Source is in an unknown state: {}", code); }
+                        };
+                        let voice = match voice_index {
+                            Some(index) => { &mut voices[index] }
+                            None => { continue; }
+                        };
+
+                        backend.stop(voice.source);
+                        if let Err(e) = backend.set_buffer(voice.source, sound_buffer) {
+                            println!("Error attaching buffer for sound \"{}\": {}", request.sound, e);
+                            continue;
                         }
+                        backend.set_looping(voice.source, request.looping);
+                        backend.set_position(voice.source, request.position);
+                        backend.set_gain(voice.source, request.gain * mixer.gain(request.bus));
+                        backend.set_pitch(voice.source, request.pitch);
+                        backend.set_reverb_send(voice.source, request.bus != AudioBus::Ui);
+                        backend.play(voice.source);
+                        voice.handle = request.handle;
+                        voice.bus = request.bus;
+                        voice.gain = request.gain;
+                        voices_started += 1;
+                        voice.started = voices_started;
                     }
-                }
-            }
-
-            //If there are fewer than the ideal frames queued, prepare and queue a frame
-            if kanye_source.buffers_queued() < IDEAL_FRAMES_QUEUED {
-                if let Some(decoder) = &mut decoder {
-                    match decoder.next_frame() {
-                        Ok(frame) => {
-                            if frame.channels == 1 {
-                                let mut mono_samples = Vec::with_capacity(frame.data.len());
-                                for sample in frame.data {
-                                    mono_samples.push(
-                                        alto::Mono {
-                                            center: sample
-                                        }
-                                    );
-                                }
-
-                                if let Ok(sample_buffer) = alto_context.new_buffer(mono_samples, frame.sample_rate) {
-                                    kanye_source.queue_buffer(sample_buffer).unwrap();
-                                }
-                            } else if frame.channels == 2 {
-                                let mut stereo_samples = Vec::with_capacity(frame.data.len());
-                                for i in (0..frame.data.len()).step_by(2) {
-                                    stereo_samples.push(
-                                        alto::Stereo {
-                                            left: frame.data[i],
-                                            right: frame.data[i + 1]
-                                        }
-                                    );
-                                }
-
-                                if let Ok(sample_buffer) = alto_context.new_buffer(stereo_samples, frame.sample_rate) {
-                                    kanye_source.queue_buffer(sample_buffer).unwrap();
-                                }
-                            } else {
-                                println!("Audio file must have one or two channels.");
-                                return;
+                    AudioCommand::StopSound(handle) => {
+                        for voice in voices.iter_mut().filter(|voice| voice.handle == Some(handle)) {
+                            backend.stop(voice.source);
+                            voice.handle = None;
+                        }
+                    }
+                    AudioCommand::SelectNewBGM => {
+                        backend.pause(bgm.stream.source);
+                        match tfd::open_file_dialog("Choose bgm", MUSIC_DIRECTORY, Some((&AudioDecoder::FILE_PATTERNS, "Audio files (*.mp3, *.wav, *.ogg, *.flac)"))) {
+                            Some(res) => {
+                                playlist.play_path(&res);
+                                bgm.play_current(backend, &mut playlist);
                             }
+                            None => { backend.play(bgm.stream.source); }
                         }
-                        Err(e) => {
-                            match e {
-                                mp3::Error::Eof => {
-                                    println!("Looping the mp3");
-                                    decoder.reader_mut().seek(SeekFrom::Start(0)).unwrap();
-                                }
-                                _ => { println!("Error decoding mp3 frame: {}", e); }
+                    }
+                    AudioCommand::SetBGM(path) => {
+                        playlist.play_path(&path);
+                        bgm.play_current(backend, &mut playlist);
+                    }
+                    AudioCommand::SetPlaylist(tracks) => {
+                        playlist.set_tracks(tracks);
+                        playlist.advance(false);
+                        bgm.play_current(backend, &mut playlist);
+                    }
+                    AudioCommand::NextTrack => {
+                        playlist.advance(true);
+                        bgm.play_current(backend, &mut playlist);
+                    }
+                    AudioCommand::PreviousTrack => {
+                        playlist.previous();
+                        bgm.play_current(backend, &mut playlist);
+                    }
+                    AudioCommand::PlayQueuePosition(position) => {
+                        playlist.jump_to(position);
+                        bgm.play_current(backend, &mut playlist);
+                    }
+                    AudioCommand::SetShuffle(shuffle) => {
+                        playlist.set_shuffle(shuffle);
+                        send_event(&event_sender, AudioEvent::PlaylistChanged(playlist.status()));
+                    }
+                    AudioCommand::SetRepeat(repeat) => {
+                        playlist.repeat = repeat;
+                        send_event(&event_sender, AudioEvent::PlaylistChanged(playlist.status()));
+                    }
+                    AudioCommand::SetCrossfade(seconds) => { bgm.crossfade = seconds; }
+                    AudioCommand::RestartBGM => {
+                        println!("Restarting the bgm");
+                        bgm.seek(backend, 0.0);
+                    }
+                    AudioCommand::SeekBGM(seconds) => { bgm.seek(backend, seconds); }
+                    AudioCommand::PlayPause => {
+                        //Whichever way it goes, the source shouldn't be started again behind the user's back
+                        bgm.kickstart = false;
+                        bgm.cancel_fade(backend);
+                        match backend.state(bgm.stream.source) {
+                            PlaybackState::Playing | PlaybackState::Initial => {
+                                backend.pause(bgm.stream.source);
+                            }
+                            PlaybackState::Paused | PlaybackState::Stopped => {
+                                backend.play(bgm.stream.source);
                             }
                         }
                     }
                 }
             }
 
-            //Unqueue any processed buffers
-            while kanye_source.buffers_processed() > 0 {
-                kanye_source.unqueue_buffer().unwrap();
+            let reverb = blend_zones(&reverb_zones, &listener_position);
+            if reverb != current_reverb {
+                backend.set_reverb(&reverb);
+                current_reverb = reverb;
             }
 
-            if kanye_source.state() != SourceState::Playing && kickstart_bgm && kanye_source.buffers_queued() == IDEAL_FRAMES_QUEUED {
-                kanye_source.play();
-                kickstart_bgm = false;
+            //Move on through the playlist once the current track runs out
+            if bgm.update_streams(backend, playlist.has_next()) {
+                playlist.advance(false);
+                bgm.play_current(backend, &mut playlist);
             }
+            bgm.update_fade(backend);
+            bgm.stream.unqueue_processed(backend);
+            bgm.report_position(backend);
+            bgm.report_envelope(backend);
+
+            //A source that stopped with audio still queued ran dry before the next frames were decoded, so it's started again once it's refilled
+            //Only stopped and never started sources get started, so a source the user paused stays paused
+            let state = backend.state(bgm.stream.source);
+            if state == PlaybackState::Stopped && backend.buffers_queued(bgm.stream.source) > 0 {
+                bgm.kickstart = true;
+            }
+            if (state == PlaybackState::Stopped || state == PlaybackState::Initial) && bgm.kickstart && backend.buffers_queued(bgm.stream.source) == IDEAL_FRAMES_QUEUED {
+                backend.play(bgm.stream.source);
+                bgm.kickstart = false;
+            }
+            backend.update();
 
             //Sleeping to avoid throttling a CPU core
             thread::sleep(Duration::from_millis(10));
         }
     });
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::mpsc;
+
+    const TEST_SAMPLE_RATE: u32 = 44100;
+    const WAV_FRAME_LENGTH: f32 = 4096.0 / TEST_SAMPLE_RATE as f32;      //Length of each frame the decoder reads out of a WAV
+
+    //Writes a mono sawtooth of the given length to a temporary WAV file
+    fn write_test_wav(name: &str, seconds: f32) -> String {
+        let path = std::env::temp_dir().join(format!("hot_chickens_{}.wav", name)).to_string_lossy().into_owned();
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: TEST_SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..((seconds * TEST_SAMPLE_RATE as f32) as usize) {
+            writer.write_sample((i % 200) as i16 * 100).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    fn open_stream(backend: &mut OfflineBackend, path: &str) -> MusicStream {
+        let mut stream = MusicStream::new(backend, [0.0; 3], 1.0);
+        stream.decoder = Some(AudioDecoder::open(path).unwrap());
+        stream
+    }
+
+    #[test]
+    fn stream_refills_its_queue_as_buffers_play() {
+        let path = write_test_wav("refill", 2.0);
+        let mut backend = OfflineBackend::in_memory();
+        let (event_sender, _event_receiver) = mpsc::channel();
+        let mut stream = open_stream(&mut backend, &path);
+
+        //Each fill queues at most one frame, and a full queue is left alone
+        for queued in 1..=IDEAL_FRAMES_QUEUED {
+            assert!(stream.fill(&mut backend, &event_sender));
+            assert_eq!(backend.buffers_queued(stream.source), queued);
+        }
+        assert!(stream.fill(&mut backend, &event_sender));
+        assert_eq!(backend.buffers_queued(stream.source), IDEAL_FRAMES_QUEUED);
+
+        backend.play(stream.source);
+        backend.mix(3 * 4096);
+        stream.unqueue_processed(&mut backend);
+        assert_eq!(backend.buffers_queued(stream.source), IDEAL_FRAMES_QUEUED - 3);
+        assert_eq!(stream.queued_buffers.len(), IDEAL_FRAMES_QUEUED - 3);
+
+        for _ in 0..3 {
+            assert!(stream.fill(&mut backend, &event_sender));
+        }
+        assert_eq!(backend.buffers_queued(stream.source), IDEAL_FRAMES_QUEUED);
+        assert_eq!(stream.queued_buffers.len(), IDEAL_FRAMES_QUEUED);
+
+        //The queue picks up where the buffers that already played left off
+        for (i, buffer) in stream.queued_buffers.iter().enumerate() {
+            assert!((buffer.start - (i + 3) as f32 * WAV_FRAME_LENGTH).abs() < 0.001);
+            assert!((buffer.length - WAV_FRAME_LENGTH).abs() < 0.001);
+        }
+        let (buffer, offset) = stream.current_buffer(&backend).unwrap();
+        assert!((buffer.start + offset - 3.0 * WAV_FRAME_LENGTH).abs() < 0.001);
+        assert!((stream.remaining(&backend).unwrap() - (2.0 - 3.0 * WAV_FRAME_LENGTH)).abs() < 0.001);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stream_reports_the_end_of_the_track() {
+        let path = write_test_wav("end", 0.5);
+        let mut backend = OfflineBackend::in_memory();
+        let (event_sender, _event_receiver) = mpsc::channel();
+        let mut stream = open_stream(&mut backend, &path);
+
+        //Half a second is six frames, so the track runs out before the queue is full
+        let mut fills = 0;
+        while stream.fill(&mut backend, &event_sender) {
+            fills += 1;
+            assert!(fills < IDEAL_FRAMES_QUEUED);
+        }
+        assert_eq!(backend.buffers_queued(stream.source), 6);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,740 @@
+use alto::{Source, SourceState};
+use ozy::structs::OptionVec;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::reverb::ReverbParams;
+
+//Sample rate the offline backend mixes at
+const OFFLINE_SAMPLE_RATE: u32 = 44100;
+
+//Sources and buffers are referred to by index so each backend can keep them however it likes
+pub type SourceId = usize;
+pub type BufferId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackState {
+    Initial,
+    Playing,
+    Paused,
+    Stopped
+}
+
+//Which backend the audio thread should use, from the audio_backend config option
+pub enum BackendChoice {
+    OpenAl,
+    Null,               //Plays everything silently, so the playlist still advances without an audio device
+    Wav(String)         //Mixes everything into a WAV file at the given path
+}
+
+impl BackendChoice {
+    pub fn from_config_value(value: Option<&String>) -> Self {
+        match value.map(|s| s.as_str()) {
+            None | Some("openal") => { BackendChoice::OpenAl }
+            Some("null") => { BackendChoice::Null }
+            Some(path) if path.ends_with(".wav") => { BackendChoice::Wav(String::from(path)) }
+            Some(other) => {
+                println!("Unknown audio backend \"{}\", using OpenAL", other);
+                BackendChoice::OpenAl
+            }
+        }
+    }
+}
+
+//Everything the audio thread needs from the thing actually making sound
+//Samples are always interleaved 16-bit with one or two channels
+pub trait AudioBackend {
+    fn set_listener_position(&mut self, position: [f32; 3]);
+    fn set_listener_velocity(&mut self, velocity: [f32; 3]);
+    fn set_listener_orientation(&mut self, orientation: ([f32; 3], [f32; 3]));
+    fn set_listener_gain(&mut self, gain: f32);
+
+    fn new_buffer(&mut self, samples: &[i16], channels: usize, sample_rate: i32) -> Result<BufferId, String>;
+    fn new_static_source(&mut self) -> Result<SourceId, String>;
+    fn new_streaming_source(&mut self) -> Result<SourceId, String>;
+    fn delete_source(&mut self, source: SourceId);
+
+    //Static sources play a single buffer
+    fn set_buffer(&mut self, source: SourceId, buffer: BufferId) -> Result<(), String>;
+
+    //Streaming sources play through a queue of buffers
+    fn queue_samples(&mut self, source: SourceId, samples: &[i16], channels: usize, sample_rate: i32) -> Result<(), String>;
+    fn buffers_queued(&self, source: SourceId) -> usize;
+
+    //Removes the buffers that have finished playing from the front of the queue, returning how many there were
+    fn unqueue_processed(&mut self, source: SourceId) -> usize;
+
+    fn set_gain(&mut self, source: SourceId, gain: f32);
+    fn set_pitch(&mut self, source: SourceId, pitch: f32);
+    fn set_position(&mut self, source: SourceId, position: [f32; 3]);
+    fn set_looping(&mut self, source: SourceId, looping: bool);
+    fn looping(&self, source: SourceId) -> bool;
+    fn play(&mut self, source: SourceId);
+    fn pause(&mut self, source: SourceId);
+    fn stop(&mut self, source: SourceId);
+    fn state(&self, source: SourceId) -> PlaybackState;
+
+    //Seconds into the source's buffer, or into its queue for streaming sources
+    fn sec_offset(&self, source: SourceId) -> f32;
+
+    //Sets the environmental reverb, which is only heard on sources that send to it
+    fn set_reverb(&mut self, _params: &ReverbParams) {}
+    fn set_reverb_send(&mut self, _source: SourceId, _enabled: bool) {}
+
+    //Called once per pass of the audio thread's loop
+    fn update(&mut self) {}
+}
+
+enum AlSource {
+    Static(alto::StaticSource),
+    Streaming(alto::StreamingSource)
+}
+
+//Calls a Source trait method on whichever kind of source this is
+macro_rules! with_source {
+    ($source:expr, $s:ident => $body:expr) => {
+        match $source {
+            AlSource::Static($s) => { $body }
+            AlSource::Streaming($s) => { $body }
+        }
+    };
+}
+
+pub struct OpenAlBackend {
+    context: alto::Context,
+    buffers: Vec<Arc<alto::Buffer>>,
+    sources: OptionVec<AlSource>,
+    reverb_slot: Option<alto::efx::AuxEffectSlot>,          //None when the EFX extension isn't available
+    reverb_effect: Option<alto::efx::ReverbEffect>
+}
+
+impl OpenAlBackend {
+    //This can fail if OpenAL is not installed on the host system
+    pub fn new() -> Result<Self, String> {
+        let alto = alto::Alto::load_default().map_err(|e| format!("Error initializing OpenAL: {}", e))?;
+        let device_name = alto.default_output().ok_or_else(|| String::from("No default audio output device found"))?;
+        let device = alto.open(Some(&device_name)).map_err(|e| format!("Error opening default audio device: {}", e))?;
+        let context = device.new_context(None).map_err(|e| format!("Error creating OpenAL context: {}", e))?;
+
+        //Reverb is nice to have, so audio carries on without it
+        let (reverb_slot, reverb_effect) = match (context.new_aux_effect_slot(), context.new_effect::<alto::efx::ReverbEffect>()) {
+            (Ok(slot), Ok(effect)) => { (Some(slot), Some(effect)) }
+            (Err(e), _) | (_, Err(e)) => {
+                println!("Reverb is unavailable: {}", e);
+                (None, None)
+            }
+        };
+
+        Ok(OpenAlBackend {
+            context,
+            buffers: Vec::new(),
+            sources: OptionVec::with_capacity(32),
+            reverb_slot,
+            reverb_effect
+        })
+    }
+
+    fn new_al_buffer(&self, samples: &[i16], channels: usize, sample_rate: i32) -> Result<alto::Buffer, String> {
+        let buffer = match channels {
+            1 => {
+                let mono_samples: Vec<alto::Mono<i16>> = samples.iter().map(|&center| alto::Mono { center }).collect();
+                self.context.new_buffer(mono_samples, sample_rate)
+            }
+            2 => {
+                let stereo_samples: Vec<alto::Stereo<i16>> = samples.chunks_exact(2).map(|pair| alto::Stereo { left: pair[0], right: pair[1] }).collect();
+                self.context.new_buffer(stereo_samples, sample_rate)
+            }
+            _ => { return Err(format!("OpenAL buffers must have one or two channels, not {}", channels)); }
+        };
+        buffer.map_err(|e| e.to_string())
+    }
+}
+
+impl AudioBackend for OpenAlBackend {
+    fn set_listener_position(&mut self, position: [f32; 3]) { self.context.set_position(position).unwrap(); }
+    fn set_listener_velocity(&mut self, velocity: [f32; 3]) { self.context.set_velocity(velocity).unwrap(); }
+    fn set_listener_orientation(&mut self, orientation: ([f32; 3], [f32; 3])) { self.context.set_orientation(orientation).unwrap(); }
+    fn set_listener_gain(&mut self, gain: f32) { self.context.set_gain(gain).unwrap(); }
+
+    fn new_buffer(&mut self, samples: &[i16], channels: usize, sample_rate: i32) -> Result<BufferId, String> {
+        let buffer = self.new_al_buffer(samples, channels, sample_rate)?;
+        self.buffers.push(Arc::new(buffer));
+        Ok(self.buffers.len() - 1)
+    }
+
+    fn new_static_source(&mut self) -> Result<SourceId, String> {
+        let source = self.context.new_static_source().map_err(|e| e.to_string())?;
+        Ok(self.sources.insert(AlSource::Static(source)))
+    }
+
+    fn new_streaming_source(&mut self) -> Result<SourceId, String> {
+        let source = self.context.new_streaming_source().map_err(|e| e.to_string())?;
+        Ok(self.sources.insert(AlSource::Streaming(source)))
+    }
+
+    fn delete_source(&mut self, source: SourceId) {
+        self.sources.delete(source);
+    }
+
+    fn set_buffer(&mut self, source: SourceId, buffer: BufferId) -> Result<(), String> {
+        match (self.sources.get_mut_element(source), self.buffers.get(buffer)) {
+            (Some(AlSource::Static(s)), Some(buffer)) => { s.set_buffer(buffer.clone()).map_err(|e| e.to_string()) }
+            _ => { Err(String::from("Only static sources can be given a buffer")) }
+        }
+    }
+
+    fn queue_samples(&mut self, source: SourceId, samples: &[i16], channels: usize, sample_rate: i32) -> Result<(), String> {
+        let buffer = self.new_al_buffer(samples, channels, sample_rate)?;
+        match self.sources.get_mut_element(source) {
+            Some(AlSource::Streaming(s)) => { s.queue_buffer(buffer).map_err(|(e, _)| e.to_string()) }
+            _ => { Err(String::from("Only streaming sources can queue buffers")) }
+        }
+    }
+
+    fn buffers_queued(&self, source: SourceId) -> usize {
+        match &self.sources[source] {
+            Some(AlSource::Streaming(s)) => { s.buffers_queued() as usize }
+            _ => { 0 }
+        }
+    }
+
+    fn unqueue_processed(&mut self, source: SourceId) -> usize {
+        let mut count = 0;
+        if let Some(AlSource::Streaming(s)) = self.sources.get_mut_element(source) {
+            while s.buffers_processed() > 0 {
+                s.unqueue_buffer().unwrap();
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn set_gain(&mut self, source: SourceId, gain: f32) {
+        if let Some(source) = self.sources.get_mut_element(source) { with_source!(source, s => s.set_gain(gain).unwrap()); }
+    }
+
+    fn set_pitch(&mut self, source: SourceId, pitch: f32) {
+        if let Some(source) = self.sources.get_mut_element(source) { with_source!(source, s => s.set_pitch(pitch).unwrap()); }
+    }
+
+    fn set_position(&mut self, source: SourceId, position: [f32; 3]) {
+        if let Some(source) = self.sources.get_mut_element(source) { with_source!(source, s => s.set_position(position).unwrap()); }
+    }
+
+    fn set_looping(&mut self, source: SourceId, looping: bool) {
+        if let Some(AlSource::Static(s)) = self.sources.get_mut_element(source) {
+            s.set_looping(looping);
+        }
+    }
+
+    fn looping(&self, source: SourceId) -> bool {
+        match &self.sources[source] {
+            Some(AlSource::Static(s)) => { s.looping() }
+            _ => { false }
+        }
+    }
+
+    fn play(&mut self, source: SourceId) {
+        if let Some(source) = self.sources.get_mut_element(source) { with_source!(source, s => s.play()); }
+    }
+
+    fn pause(&mut self, source: SourceId) {
+        if let Some(source) = self.sources.get_mut_element(source) { with_source!(source, s => s.pause()); }
+    }
+
+    fn stop(&mut self, source: SourceId) {
+        if let Some(source) = self.sources.get_mut_element(source) { with_source!(source, s => s.stop()); }
+    }
+
+    fn state(&self, source: SourceId) -> PlaybackState {
+        let state = match &self.sources[source] {
+            Some(source) => { with_source!(source, s => s.state()) }
+            None => { return PlaybackState::Stopped; }
+        };
+        match state {
+            SourceState::Initial => { PlaybackState::Initial }
+            SourceState::Playing => { PlaybackState::Playing }
+            SourceState::Paused => { PlaybackState::Paused }
+            SourceState::Stopped => { PlaybackState::Stopped }
+            SourceState::Unknown(code) => {
+                println!("Source is in an unknown state: {}", code);
+                PlaybackState::Stopped
+            }
+        }
+    }
+
+    fn sec_offset(&self, source: SourceId) -> f32 {
+        match &self.sources[source] {
+            Some(source) => { with_source!(source, s => s.sec_offset() as f32) }
+            None => { 0.0 }
+        }
+    }
+
+    fn set_reverb(&mut self, params: &ReverbParams) {
+        if let (Some(slot), Some(effect)) = (&mut self.reverb_slot, &mut self.reverb_effect) {
+            if let Err(e) = apply_reverb(slot, effect, params) {
+                println!("Error setting reverb: {}", e);
+            }
+        }
+    }
+
+    fn set_reverb_send(&mut self, source: SourceId, enabled: bool) {
+        if let (Some(slot), Some(source)) = (&mut self.reverb_slot, self.sources.get_mut_element(source)) {
+            let result = with_source!(source, s => {
+                if enabled { s.set_aux_send(0, slot) } else { s.clear_aux_send(0) }
+            });
+            if let Err(e) = result {
+                println!("Error setting reverb send: {}", e);
+            }
+        }
+    }
+}
+
+fn apply_reverb(slot: &mut alto::efx::AuxEffectSlot, effect: &mut alto::efx::ReverbEffect, params: &ReverbParams) -> alto::AltoResult<()> {
+    effect.set_density(params.density)?;
+    effect.set_diffusion(params.diffusion)?;
+    effect.set_gainhf(params.gain_hf)?;
+    effect.set_decay_time(params.decay_time)?;
+    effect.set_decay_hfratio(params.decay_hf_ratio)?;
+    effect.set_reflections_gain(params.reflections_gain)?;
+    effect.set_reflections_delay(params.reflections_delay)?;
+    effect.set_late_reverb_gain(params.late_reverb_gain)?;
+    effect.set_late_reverb_delay(params.late_reverb_delay)?;
+
+    //The slot takes a copy of the effect's settings, so it has to be reattached after every change
+    slot.set_effect(&*effect)?;
+    slot.set_gain(params.wet)
+}
+
+//Decoded audio held by the offline backend
+struct OfflineBuffer {
+    samples: Vec<i16>,
+    channels: usize,
+    sample_rate: i32
+}
+
+impl OfflineBuffer {
+    fn frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    //The left and right values of a frame, with mono buffers returning the same value twice
+    fn frame(&self, index: usize) -> (f32, f32) {
+        let i = index * self.channels;
+        let left = self.samples[i] as f32 / i16::MAX as f32;
+        if self.channels == 1 {
+            (left, left)
+        } else {
+            (left, self.samples[i + 1] as f32 / i16::MAX as f32)
+        }
+    }
+}
+
+struct OfflineSource {
+    queue: VecDeque<Arc<OfflineBuffer>>,    //Static sources have at most one buffer here
+    current: usize,                         //Index into the queue of the buffer being played. Everything before it has been processed
+    cursor: f64,                            //Frame position in the current buffer
+    streaming: bool,
+    gain: f32,
+    pitch: f32,
+    position: glm::TVec3<f32>,
+    looping: bool,
+    state: PlaybackState
+}
+
+impl OfflineSource {
+    fn new(streaming: bool) -> Self {
+        OfflineSource {
+            queue: VecDeque::new(),
+            current: 0,
+            cursor: 0.0,
+            streaming,
+            gain: 1.0,
+            pitch: 1.0,
+            position: glm::zero(),
+            looping: false,
+            state: PlaybackState::Initial
+        }
+    }
+}
+
+//Where the offline backend's mix ends up
+enum MixTarget {
+    Discard,
+    Memory(Vec<i16>),
+    Wav(hound::WavWriter<BufWriter<File>>)
+}
+
+//Plays sources in software instead of on an audio device, for headless runs and testing
+//Mono sources are attenuated and panned like OpenAL's default inverse distance clamped model
+pub struct OfflineBackend {
+    buffers: Vec<Arc<OfflineBuffer>>,
+    sources: OptionVec<OfflineSource>,
+    listener_position: glm::TVec3<f32>,
+    listener_forward: glm::TVec3<f32>,
+    listener_up: glm::TVec3<f32>,
+    listener_gain: f32,
+    target: MixTarget,
+    last_update: Instant,
+    unmixed_frames: f64                     //Fraction of an output frame carried over between updates
+}
+
+impl OfflineBackend {
+    fn new(target: MixTarget) -> Self {
+        OfflineBackend {
+            buffers: Vec::new(),
+            sources: OptionVec::with_capacity(32),
+            listener_position: glm::zero(),
+            listener_forward: glm::vec3(0.0, 0.0, -1.0),
+            listener_up: glm::vec3(0.0, 1.0, 0.0),
+            listener_gain: 1.0,
+            target,
+            last_update: Instant::now(),
+            unmixed_frames: 0.0
+        }
+    }
+
+    //Keeps time without producing any output
+    pub fn null() -> Self {
+        Self::new(MixTarget::Discard)
+    }
+
+    //Keeps the whole mix, which can be read back with mixed_samples()
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self::new(MixTarget::Memory(Vec::new()))
+    }
+
+    pub fn to_wav(path: &str) -> Result<Self, String> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: OFFLINE_SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int
+        };
+        let writer = hound::WavWriter::create(path, spec).map_err(|e| format!("Unable to create \"{}\": {}", path, e))?;
+        Ok(Self::new(MixTarget::Wav(writer)))
+    }
+
+    //Interleaved stereo output of an in-memory backend
+    #[cfg(test)]
+    pub fn mixed_samples(&self) -> &[i16] {
+        match &self.target {
+            MixTarget::Memory(samples) => { samples }
+            _ => { &[] }
+        }
+    }
+
+    //How much of a mono source at this position goes to the left and right channels
+    fn spatialize(&self, position: &glm::TVec3<f32>) -> (f32, f32) {
+        let offset = position - self.listener_position;
+        let distance = glm::length(&offset);
+        let attenuation = 1.0 / f32::max(distance, 1.0);
+        let pan = if distance > 0.0 {
+            let right = glm::normalize(&glm::cross(&self.listener_forward, &self.listener_up));
+            glm::dot(&(offset / distance), &right)
+        } else {
+            0.0
+        };
+
+        //Equal power panning
+        let angle = (pan + 1.0) * glm::quarter_pi::<f32>();
+        (attenuation * f32::cos(angle), attenuation * f32::sin(angle))
+    }
+
+    //Advances every playing source by the given number of output frames, mixing them into the target
+    pub fn mix(&mut self, frame_count: usize) {
+        let mut output = vec![0.0f32; frame_count * 2];
+        for i in 0..self.sources.len() {
+            let (left_gain, right_gain) = match &self.sources[i] {
+                Some(source) if source.state == PlaybackState::Playing => {
+                    match source.queue.get(source.current) {
+                        Some(buffer) if buffer.channels == 1 => { self.spatialize(&source.position) }
+                        _ => { (1.0, 1.0) }
+                    }
+                }
+                _ => { continue; }
+            };
+
+            let source = self.sources.get_mut_element(i).unwrap();
+            for frame in 0..frame_count {
+                let buffer = match source.queue.get(source.current) {
+                    Some(buffer) => { buffer.clone() }
+                    None => {
+                        source.state = PlaybackState::Stopped;
+                        break;
+                    }
+                };
+                if buffer.frames() == 0 {
+                    source.current += 1;
+                    continue;
+                }
+
+                let (left, right) = buffer.frame(source.cursor as usize);
+                output[frame * 2] += left * left_gain * source.gain;
+                output[frame * 2 + 1] += right * right_gain * source.gain;
+
+                source.cursor += buffer.sample_rate as f64 * source.pitch as f64 / OFFLINE_SAMPLE_RATE as f64;
+                if source.cursor >= buffer.frames() as f64 {
+                    source.cursor -= buffer.frames() as f64;
+                    if !source.looping || source.streaming {
+                        source.current += 1;
+                    }
+                }
+            }
+            if source.current >= source.queue.len() {
+                source.state = PlaybackState::Stopped;
+            }
+        }
+
+        let listener_gain = self.listener_gain;
+        let samples = output.iter().map(|s| (f32::max(-1.0, f32::min(s * listener_gain, 1.0)) * i16::MAX as f32) as i16);
+        let mut write_failed = false;
+        match &mut self.target {
+            MixTarget::Discard => {}
+            MixTarget::Memory(mixed) => { mixed.extend(samples); }
+            MixTarget::Wav(writer) => {
+                for sample in samples {
+                    if let Err(e) = writer.write_sample(sample) {
+                        println!("Error writing audio: {}", e);
+                        write_failed = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        //Give up on the file rather than reporting the same error every frame
+        if write_failed {
+            self.target = MixTarget::Discard;
+        }
+    }
+}
+
+impl AudioBackend for OfflineBackend {
+    fn set_listener_position(&mut self, position: [f32; 3]) { self.listener_position = glm::make_vec3(&position); }
+    fn set_listener_velocity(&mut self, _: [f32; 3]) {}
+    fn set_listener_orientation(&mut self, orientation: ([f32; 3], [f32; 3])) {
+        self.listener_forward = glm::make_vec3(&orientation.0);
+        self.listener_up = glm::make_vec3(&orientation.1);
+    }
+    fn set_listener_gain(&mut self, gain: f32) { self.listener_gain = gain; }
+
+    fn new_buffer(&mut self, samples: &[i16], channels: usize, sample_rate: i32) -> Result<BufferId, String> {
+        if channels != 1 && channels != 2 {
+            return Err(format!("Buffers must have one or two channels, not {}", channels));
+        }
+        self.buffers.push(Arc::new(OfflineBuffer { samples: samples.to_vec(), channels, sample_rate }));
+        Ok(self.buffers.len() - 1)
+    }
+
+    fn new_static_source(&mut self) -> Result<SourceId, String> {
+        Ok(self.sources.insert(OfflineSource::new(false)))
+    }
+
+    fn new_streaming_source(&mut self) -> Result<SourceId, String> {
+        Ok(self.sources.insert(OfflineSource::new(true)))
+    }
+
+    fn delete_source(&mut self, source: SourceId) {
+        self.sources.delete(source);
+    }
+
+    fn set_buffer(&mut self, source: SourceId, buffer: BufferId) -> Result<(), String> {
+        match (self.sources.get_mut_element(source), self.buffers.get(buffer)) {
+            (Some(s), Some(buffer)) if !s.streaming => {
+                s.queue.clear();
+                s.queue.push_back(buffer.clone());
+                s.current = 0;
+                s.cursor = 0.0;
+                s.state = PlaybackState::Initial;
+                Ok(())
+            }
+            _ => { Err(String::from("Only static sources can be given a buffer")) }
+        }
+    }
+
+    fn queue_samples(&mut self, source: SourceId, samples: &[i16], channels: usize, sample_rate: i32) -> Result<(), String> {
+        if channels != 1 && channels != 2 {
+            return Err(format!("Buffers must have one or two channels, not {}", channels));
+        }
+        match self.sources.get_mut_element(source) {
+            Some(s) if s.streaming => {
+                s.queue.push_back(Arc::new(OfflineBuffer { samples: samples.to_vec(), channels, sample_rate }));
+                Ok(())
+            }
+            _ => { Err(String::from("Only streaming sources can queue buffers")) }
+        }
+    }
+
+    fn buffers_queued(&self, source: SourceId) -> usize {
+        match &self.sources[source] {
+            Some(s) if s.streaming => { s.queue.len() }
+            _ => { 0 }
+        }
+    }
+
+    fn unqueue_processed(&mut self, source: SourceId) -> usize {
+        match self.sources.get_mut_element(source) {
+            Some(s) if s.streaming => {
+                let processed = usize::min(s.current, s.queue.len());
+                s.queue.drain(0..processed);
+                s.current -= processed;
+                processed
+            }
+            _ => { 0 }
+        }
+    }
+
+    fn set_gain(&mut self, source: SourceId, gain: f32) {
+        if let Some(s) = self.sources.get_mut_element(source) { s.gain = gain; }
+    }
+
+    fn set_pitch(&mut self, source: SourceId, pitch: f32) {
+        if let Some(s) = self.sources.get_mut_element(source) { s.pitch = pitch; }
+    }
+
+    fn set_position(&mut self, source: SourceId, position: [f32; 3]) {
+        if let Some(s) = self.sources.get_mut_element(source) { s.position = glm::make_vec3(&position); }
+    }
+
+    fn set_looping(&mut self, source: SourceId, looping: bool) {
+        if let Some(s) = self.sources.get_mut_element(source) { s.looping = looping; }
+    }
+
+    fn looping(&self, source: SourceId) -> bool {
+        match &self.sources[source] {
+            Some(s) => { s.looping }
+            None => { false }
+        }
+    }
+
+    fn play(&mut self, source: SourceId) {
+        if let Some(s) = self.sources.get_mut_element(source) {
+            //Like OpenAL, playing a stopped source starts it over from the beginning of its queue
+            if s.state != PlaybackState::Paused {
+                s.current = 0;
+                s.cursor = 0.0;
+            }
+            s.state = PlaybackState::Playing;
+        }
+    }
+
+    fn pause(&mut self, source: SourceId) {
+        if let Some(s) = self.sources.get_mut_element(source) {
+            if s.state == PlaybackState::Playing {
+                s.state = PlaybackState::Paused;
+            }
+        }
+    }
+
+    fn stop(&mut self, source: SourceId) {
+        if let Some(s) = self.sources.get_mut_element(source) {
+            s.state = PlaybackState::Stopped;
+            s.current = s.queue.len();
+            s.cursor = 0.0;
+        }
+    }
+
+    fn state(&self, source: SourceId) -> PlaybackState {
+        match &self.sources[source] {
+            Some(s) => { s.state }
+            None => { PlaybackState::Stopped }
+        }
+    }
+
+    fn sec_offset(&self, source: SourceId) -> f32 {
+        match &self.sources[source] {
+            Some(s) => {
+                let finished: f32 = s.queue.iter().take(s.current).map(|b| b.frames() as f32 / b.sample_rate as f32).sum();
+                let current = match s.queue.get(s.current) {
+                    Some(buffer) => { s.cursor as f32 / buffer.sample_rate as f32 }
+                    None => { 0.0 }
+                };
+                finished + current
+            }
+            None => { 0.0 }
+        }
+    }
+
+    //Mixes however much time has passed since the last update
+    fn update(&mut self) {
+        self.unmixed_frames += self.last_update.elapsed().as_secs_f64() * OFFLINE_SAMPLE_RATE as f64;
+        self.last_update = Instant::now();
+        let frame_count = self.unmixed_frames as usize;
+        self.unmixed_frames -= frame_count as f64;
+        self.mix(frame_count);
+
+        if let MixTarget::Wav(writer) = &mut self.target {
+            if let Err(e) = writer.flush() {
+                println!("Error writing audio: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Plays a constant mono tone from the given position and returns the first mixed frame
+    fn mix_tone_from(position: [f32; 3]) -> (f32, f32) {
+        let mut backend = OfflineBackend::in_memory();
+        let buffer = backend.new_buffer(&[i16::MAX / 2; 64], 1, OFFLINE_SAMPLE_RATE as i32).unwrap();
+        let source = backend.new_static_source().unwrap();
+        backend.set_buffer(source, buffer).unwrap();
+        backend.set_position(source, position);
+        backend.play(source);
+        backend.mix(32);
+
+        let samples = backend.mixed_samples();
+        assert_eq!(samples.len(), 64);
+        (samples[0] as f32 / i16::MAX as f32, samples[1] as f32 / i16::MAX as f32)
+    }
+
+    #[test]
+    fn mono_sources_fall_off_with_distance() {
+        //Straight ahead of the listener the tone is split evenly between both channels
+        let centered = 0.5 * f32::cos(glm::quarter_pi::<f32>());
+        for &(distance, attenuation) in [(0.5, 1.0), (1.0, 1.0), (2.0, 0.5), (4.0, 0.25)].iter() {
+            let (left, right) = mix_tone_from([0.0, 0.0, -distance]);
+            assert!((left - centered * attenuation).abs() < 0.001, "left channel at distance {} was {}", distance, left);
+            assert!((right - centered * attenuation).abs() < 0.001, "right channel at distance {} was {}", distance, right);
+        }
+    }
+
+    #[test]
+    fn mono_sources_pan_toward_their_side() {
+        let (left, right) = mix_tone_from([2.0, 0.0, 0.0]);
+        assert!(left.abs() < 0.001);
+        assert!((right - 0.25).abs() < 0.001);
+
+        let (left, right) = mix_tone_from([-2.0, 0.0, 0.0]);
+        assert!((left - 0.25).abs() < 0.001);
+        assert!(right.abs() < 0.001);
+    }
+
+    #[test]
+    fn streaming_sources_play_through_their_queue() {
+        let mut backend = OfflineBackend::in_memory();
+        let source = backend.new_streaming_source().unwrap();
+        for _ in 0..3 {
+            backend.queue_samples(source, &[1000; 100], 1, OFFLINE_SAMPLE_RATE as i32).unwrap();
+        }
+        backend.play(source);
+
+        backend.mix(250);
+        assert_eq!(backend.state(source), PlaybackState::Playing);
+        assert_eq!(backend.unqueue_processed(source), 2);
+        assert_eq!(backend.buffers_queued(source), 1);
+        assert!((backend.sec_offset(source) - 50.0 / OFFLINE_SAMPLE_RATE as f32).abs() < 0.0001);
+
+        //Running out of buffers stops the source
+        backend.mix(100);
+        assert_eq!(backend.state(source), PlaybackState::Stopped);
+        assert_eq!(backend.unqueue_processed(source), 1);
+        assert_eq!(backend.buffers_queued(source), 0);
+    }
+}
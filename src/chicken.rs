@@ -0,0 +1,219 @@
+use ozy::collision::*;
+use rand::Rng;
+use crate::ecs::{Behaviour, GameObject, ObjectHandle, RenderMesh, SphereCollider, Transform, World};
+use crate::navigation::NavGrid;
+
+pub const CHICKEN_PECK_KNOCKBACK: f32 = 6.0;
+pub const CHICKEN_PECK_ENERGY_DRAIN: f32 = 15.0;
+pub const CHICKEN_PECK_DAMAGE: f32 = 10.0;
+
+//An angry chicken that runs at the player
+//Like the Totoro, its position lives in the transform of the object that owns this behaviour
+#[derive(Clone, Debug)]
+pub struct Chicken {
+    pub velocity: glm::TVec3<f32>,          //Velocity from external forces like knockback, which decays over time
+    pub nest: glm::TVec3<f32>,
+    pub heading: f32,
+    pub chasing: bool,
+    pub path: Vec<glm::TVec3<f32>>,         //Waypoints toward the player planned by the nest, with the next one last
+    pub last_peck_time: f32,
+    pub creation_time: f32
+}
+
+impl Chicken {
+    pub const HIT_RADIUS: f32 = 0.4;
+    pub const FRICTION: f32 = 2.0;
+    pub const RUN_SPEED: f32 = 4.0;
+    pub const WANDER_SPEED: f32 = 1.0;
+    pub const SIGHT_DISTANCE: f32 = 25.0;
+    pub const GIVE_UP_DISTANCE: f32 = 40.0;     //How far from the nest a chicken will chase the player
+    pub const PECK_COOLDOWN: f32 = 1.0;
+    pub const SHOVE_SPEED: f32 = 8.0;
+    const GROUND_PROBE_HEIGHT: f32 = 2.0;
+    const MAX_STEP_HEIGHT: f32 = 0.4;
+    const LOOKAHEAD: f32 = 1.0;
+    const WAYPOINT_RADIUS: f32 = 0.5;
+
+    pub fn new(nest: glm::TVec3<f32>, creation_time: f32) -> Self {
+        Chicken {
+            velocity: glm::zero(),
+            nest,
+            heading: 0.0,
+            chasing: false,
+            path: Vec::new(),
+            last_peck_time: 0.0,
+            creation_time
+        }
+    }
+
+    //Pushes the chicken away from a point at no less than the given speed
+    pub fn shove(&mut self, position: &glm::TVec3<f32>, source: &glm::TVec3<f32>, speed: f32) {
+        let away = position - source;
+        let away = glm::vec3(away.x, away.y, 0.0);
+        let direction = if away == glm::zero() { glm::vec3(1.0, 0.0, 0.0) } else { glm::normalize(&away) };
+        let current = glm::dot(&self.velocity, &direction);
+        if current < speed {
+            self.velocity += direction * (speed - current);
+        }
+    }
+
+    //Returns the height of the ground under a point, if it can be walked onto from the given height
+    fn walkable_ground(terrain: &Terrain, point: &glm::TVec3<f32>, current_height: f32) -> Option<glm::TVec3<f32>> {
+        let probe_origin = point + glm::vec3(0.0, 0.0, Self::GROUND_PROBE_HEIGHT);
+        match ray_hit_terrain(terrain, &probe_origin, &glm::vec3(0.0, 0.0, -1.0)) {
+            Some((_, ground)) if ground.z - current_height < Self::MAX_STEP_HEIGHT => { Some(ground) }
+            _ => { None }
+        }
+    }
+
+    //Picks a walkable direction as close as possible to the desired one by feeling out the terrain ahead
+    //This only avoids what's right in front of the chicken, so longer routes come from the nest's nav grid
+    fn steer(terrain: &Terrain, position: &glm::TVec3<f32>, desired: &glm::TVec3<f32>) -> Option<glm::TVec3<f32>> {
+        const FEELER_ANGLES: [f32; 7] = [0.0, 0.5, -0.5, 1.0, -1.0, 1.6, -1.6];
+        for angle in FEELER_ANGLES.iter() {
+            let direction = glm::rotate_z_vec3(desired, *angle);
+            if Self::walkable_ground(terrain, &(position + direction * Self::LOOKAHEAD), position.z).is_some() {
+                return Some(direction);
+            }
+        }
+        None
+    }
+
+    pub fn update(&mut self, position: &mut glm::TVec3<f32>, terrain: &Terrain, player_position: &glm::TVec3<f32>, delta_time: f32) {
+        let to_player = glm::vec3(player_position.x - position.x, player_position.y - position.y, 0.0);
+        let player_distance = glm::length(&to_player);
+        let nest_distance = glm::distance(&glm::vec3(position.x, position.y, 0.0), &glm::vec3(self.nest.x, self.nest.y, 0.0));
+
+        //Chickens chase the player until they stray too far from home
+        self.chasing = if self.chasing {
+            nest_distance < Self::GIVE_UP_DISTANCE
+        } else {
+            player_distance < Self::SIGHT_DISTANCE && nest_distance < Self::GIVE_UP_DISTANCE * 0.5
+        };
+
+        let (target, speed) = if self.chasing {
+            //Follow the planned path, or head straight for the player when there isn't one
+            while let Some(waypoint) = self.path.last() {
+                if glm::distance(&glm::vec3(position.x, position.y, 0.0), &glm::vec3(waypoint.x, waypoint.y, 0.0)) > Self::WAYPOINT_RADIUS {
+                    break;
+                }
+                self.path.pop();
+            }
+            match self.path.last() {
+                Some(waypoint) => { (glm::vec3(waypoint.x - position.x, waypoint.y - position.y, 0.0), Self::RUN_SPEED) }
+                None => { (to_player, Self::RUN_SPEED) }
+            }
+        } else {
+            self.path.clear();
+            //Mill about near the nest
+            let mut rng = rand::thread_rng();
+            let to_nest = glm::vec3(self.nest.x - position.x, self.nest.y - position.y, 0.0);
+            let jitter = glm::vec3(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), 0.0);
+            (to_nest * 0.2 + jitter, Self::WANDER_SPEED)
+        };
+
+        let walk_velocity = if glm::length(&target) > 0.1 {
+            match Self::steer(terrain, position, &glm::normalize(&target)) {
+                Some(direction) => { direction * speed }
+                None => { glm::zero() }
+            }
+        } else {
+            glm::zero()
+        };
+
+        if walk_velocity != glm::zero() {
+            self.heading = f32::atan2(walk_velocity.y, walk_velocity.x);
+        }
+
+        let step = (walk_velocity + self.velocity) * delta_time;
+        self.velocity -= self.velocity * f32::min(Self::FRICTION * delta_time, 1.0);
+
+        let new_position = *position + glm::vec3(step.x, step.y, 0.0);
+        if let Some(ground) = Self::walkable_ground(terrain, &new_position, position.z) {
+            *position = ground;
+        } else {
+            //Whatever pushed the chicken ran it into a wall
+            self.velocity = glm::zero();
+        }
+    }
+}
+
+//A level-defined spot that keeps hatching chickens while the player is nearby
+//It also plans the paths its chickens take toward the player across the ground around it
+pub struct Nest {
+    pub position: glm::TVec3<f32>,
+    pub chickens: Vec<ObjectHandle>,        //Handles of the chickens that hatched from this nest
+    nav_grid: NavGrid,
+    next_hatch_time: f32,
+    next_plan_time: f32
+}
+
+impl Nest {
+    pub const MAX_CHICKENS: usize = 6;
+    pub const HATCH_INTERVAL: f32 = 3.0;
+    pub const ACTIVATION_DISTANCE: f32 = 35.0;
+    pub const PLAN_INTERVAL: f32 = 0.5;
+    const NAV_MARGIN: f32 = 5.0;           //Extra ground sampled beyond where chickens give up the chase
+
+    pub fn new(position: glm::TVec3<f32>, terrain: &Terrain) -> Self {
+        Nest {
+            position,
+            chickens: Vec::with_capacity(Self::MAX_CHICKENS),
+            nav_grid: NavGrid::sample(terrain, &position, Chicken::GIVE_UP_DISTANCE + Self::NAV_MARGIN, Chicken::MAX_STEP_HEIGHT),
+            next_hatch_time: 0.0,
+            next_plan_time: 0.0
+        }
+    }
+
+    //Removes every chicken that hatched from this nest
+    pub fn clear_chickens(&mut self, world: &mut World) {
+        for handle in self.chickens.drain(..) {
+            if let Some(id) = world.resolve(handle) {
+                world.despawn(id);
+            }
+        }
+    }
+
+    pub fn update(&mut self, world: &mut World, chicken_entity_index: usize, player_position: &glm::TVec3<f32>, elapsed_time: f32) {
+        //Forget about chickens that no longer exist
+        self.chickens.retain(|&handle| world.resolve(handle).is_some());
+
+        //Plan a route to the player for every chicken that's chasing them
+        if elapsed_time >= self.next_plan_time {
+            for &handle in self.chickens.iter() {
+                let object = match world.resolve(handle).and_then(|id| world.objects.get_mut_element(id)) {
+                    Some(object) => { object }
+                    None => { continue; }
+                };
+                if let Behaviour::Chicken(chicken) = &mut object.behaviour {
+                    if chicken.chasing {
+                        chicken.path = self.nav_grid.find_path(&object.transform.position, player_position).unwrap_or_default();
+                    }
+                }
+            }
+            self.next_plan_time = elapsed_time + Self::PLAN_INTERVAL;
+        }
+
+        if glm::distance(&self.position, player_position) > Self::ACTIVATION_DISTANCE {
+            return;
+        }
+
+        if self.chickens.len() < Self::MAX_CHICKENS && elapsed_time >= self.next_hatch_time {
+            let id = world.spawn(GameObject {
+                transform: Transform::from_position(self.position),
+                mesh: Some(RenderMesh::new(chicken_entity_index)),
+                collider: Some(SphereCollider {
+                    offset: glm::vec3(0.0, 0.0, Chicken::HIT_RADIUS),
+                    radius: Chicken::HIT_RADIUS
+                }),
+                audio_emitter: None,
+                behaviour: Behaviour::Chicken(Chicken::new(self.position, elapsed_time)),
+                editable: false
+            });
+            if let Some(handle) = world.handle(id) {
+                self.chickens.push(handle);
+            }
+            self.next_hatch_time = elapsed_time + Self::HATCH_INTERVAL;
+        }
+    }
+}
@@ -0,0 +1,912 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+//Number of samples per channel to decode at a time from formats that don't have natural frames
+const FRAME_LENGTH: usize = 4096;
+
+//Frames to start decoding before an MP3 seek target, so the bit reservoir is full again by the time it's reached
+const MP3_PRIMING_FRAMES: usize = 10;
+
+//Samples of delay the MP3 decoder itself adds, on top of the encoder delay recorded in the LAME header
+const MP3_DECODER_DELAY: usize = 529;
+
+//How much of the end of an Ogg file to search for the last page, which holds the length of the stream
+const OGG_TAIL_BYTES: u64 = 65536;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioFormat {
+    Mp3,
+    Wav,
+    Ogg,
+    Flac
+}
+
+impl AudioFormat {
+    //Identifies an audio file from the first few bytes of its contents
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE" {
+            Some(AudioFormat::Wav)
+        } else if header.starts_with(b"OggS") {
+            Some(AudioFormat::Ogg)
+        } else if header.starts_with(b"fLaC") {
+            Some(AudioFormat::Flac)
+        } else if header.starts_with(b"ID3") || (header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0) {
+            //Either an ID3 tag or the sync bits of an MPEG audio frame
+            Some(AudioFormat::Mp3)
+        } else {
+            None
+        }
+    }
+}
+
+//A chunk of decoded audio with the channels' samples interleaved
+pub struct AudioFrame {
+    pub samples: Vec<i16>,
+    pub channels: usize,
+    pub sample_rate: i32
+}
+
+impl AudioFrame {
+    //Mixes the channels down to mono or copies them up to stereo
+    fn remix(self, channels: usize) -> AudioFrame {
+        if self.channels == channels {
+            self
+        } else if channels == 2 && self.channels == 1 {
+            let mut samples = Vec::with_capacity(self.samples.len() * 2);
+            for &sample in self.samples.iter() {
+                samples.push(sample);
+                samples.push(sample);
+            }
+            AudioFrame { samples, channels, sample_rate: self.sample_rate }
+        } else {
+            //Average every channel together, then copy that out to each of the new channels
+            let mut samples = Vec::with_capacity(self.samples.len() / self.channels * channels);
+            for chunk in self.samples.chunks(self.channels) {
+                let sum: i32 = chunk.iter().map(|&s| s as i32).sum();
+                for _ in 0..channels {
+                    samples.push((sum / chunk.len() as i32) as i16);
+                }
+            }
+            AudioFrame { samples, channels, sample_rate: self.sample_rate }
+        }
+    }
+
+    //Folds a surround frame down to stereo given what speaker each channel is for
+    fn downmix_to_stereo(self, layout: &[Speaker]) -> AudioFrame {
+        let weights: Vec<[f32; 2]> = layout.iter().map(|speaker| speaker.stereo_weights()).collect();
+        let left_total: f32 = weights.iter().map(|w| w[0]).sum();
+        let right_total: f32 = weights.iter().map(|w| w[1]).sum();
+
+        let mut samples = Vec::with_capacity(self.samples.len() / self.channels * 2);
+        for chunk in self.samples.chunks_exact(self.channels) {
+            let mut left = 0.0;
+            let mut right = 0.0;
+            for (sample, weight) in chunk.iter().zip(weights.iter()) {
+                left += *sample as f32 * weight[0];
+                right += *sample as f32 * weight[1];
+            }
+
+            //Normalize so the fold-down can't clip
+            samples.push((left / left_total) as i16);
+            samples.push((right / right_total) as i16);
+        }
+        AudioFrame { samples, channels: 2, sample_rate: self.sample_rate }
+    }
+}
+
+//Converts each frame of a stream to a fixed channel count and sample rate
+//Resampling picks up where the previous frame left off, so frame boundaries don't cause clicks or drift
+pub struct Resampler {
+    channels: usize,
+    sample_rate: i32,
+    input_rate: i32,                        //Sample rate of the frames that last_frame and position refer to
+    position: f64,                          //Where the next output sample falls, in input frames from the start of the next frame
+    last_frame: Vec<i16>                    //The previous frame's final sample for each channel, which sits at position -1
+}
+
+impl Resampler {
+    pub fn new(channels: usize, sample_rate: i32) -> Self {
+        Resampler {
+            channels,
+            sample_rate,
+            input_rate: sample_rate,
+            position: 0.0,
+            last_frame: Vec::new()
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+
+    pub fn convert(&mut self, frame: AudioFrame) -> AudioFrame {
+        let frame = frame.remix(self.channels);
+        let channels = self.channels;
+        let in_frames = frame.samples.len() / channels;
+        if in_frames == 0 {
+            return AudioFrame { sample_rate: self.sample_rate, ..frame };
+        }
+
+        //A change of rate partway through the stream starts the interpolation over
+        if frame.sample_rate != self.input_rate {
+            self.input_rate = frame.sample_rate;
+            self.position = 0.0;
+            self.last_frame.clear();
+        }
+        if frame.sample_rate == self.sample_rate {
+            return frame;
+        }
+
+        //Linear interpolation between the nearest two input samples
+        let step = frame.sample_rate as f64 / self.sample_rate as f64;
+        let mut samples = Vec::with_capacity(((in_frames as f64 / step) as usize + 1) * channels);
+        while self.position <= (in_frames - 1) as f64 {
+            let i0 = self.position.floor() as isize;
+            let t = self.position - i0 as f64;
+            for c in 0..channels {
+                let a = if i0 < 0 {
+                    self.last_frame.get(c).copied().unwrap_or(frame.samples[c]) as f64
+                } else {
+                    frame.samples[i0 as usize * channels + c] as f64
+                };
+                let b = frame.samples[usize::min((i0 + 1) as usize, in_frames - 1) * channels + c] as f64;
+                samples.push((a + (b - a) * t) as i16);
+            }
+            self.position += step;
+        }
+        self.position -= in_frames as f64;
+        self.last_frame = frame.samples[((in_frames - 1) * channels)..].to_vec();
+        AudioFrame { samples, channels, sample_rate: self.sample_rate }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Speaker {
+    Left,
+    Right,
+    Center,
+    Lfe,
+    SurroundLeft,
+    SurroundRight,
+    SurroundCenter
+}
+
+impl Speaker {
+    //How much of the channel goes to the left and right of a stereo mix
+    fn stereo_weights(self) -> [f32; 2] {
+        match self {
+            Speaker::Left => { [1.0, 0.0] }
+            Speaker::Right => { [0.0, 1.0] }
+            Speaker::Center => { [0.707, 0.707] }
+            Speaker::Lfe => { [0.0, 0.0] }
+            Speaker::SurroundLeft => { [0.707, 0.0] }
+            Speaker::SurroundRight => { [0.0, 0.707] }
+            Speaker::SurroundCenter => { [0.5, 0.5] }
+        }
+    }
+
+    //The speaker each channel of a stream is meant for, which depends on the format
+    fn layout(format: AudioFormat, channels: usize) -> Vec<Speaker> {
+        use Speaker::*;
+        match (format, channels) {
+            (AudioFormat::Ogg, 3) => { vec![Left, Center, Right] }
+            (AudioFormat::Ogg, 4) => { vec![Left, Right, SurroundLeft, SurroundRight] }
+            (AudioFormat::Ogg, 5) => { vec![Left, Center, Right, SurroundLeft, SurroundRight] }
+            (AudioFormat::Ogg, 6) => { vec![Left, Center, Right, SurroundLeft, SurroundRight, Lfe] }
+            (AudioFormat::Ogg, 7) => { vec![Left, Center, Right, SurroundLeft, SurroundRight, SurroundCenter, Lfe] }
+            (AudioFormat::Ogg, 8) => { vec![Left, Center, Right, SurroundLeft, SurroundRight, SurroundLeft, SurroundRight, Lfe] }
+            (AudioFormat::Flac, 4) => { vec![Left, Right, SurroundLeft, SurroundRight] }
+            (AudioFormat::Flac, 5) => { vec![Left, Right, Center, SurroundLeft, SurroundRight] }
+            _ => {
+                //The default WAVE channel order, which FLAC also follows for everything else
+                let order = [Left, Right, Center, Lfe, SurroundLeft, SurroundRight, Left, Right, SurroundCenter, SurroundLeft, SurroundRight];
+                (0..channels).map(|i| if i < order.len() { order[i] } else { Center }).collect()
+            }
+        }
+    }
+}
+
+//Where a track's loop region is, read from a text sidecar next to the track with the extension .loop
+//Each line is one of:
+//  start seconds       Where the loop region begins. Everything before it is an intro that only plays once
+//  end seconds         Where the loop region ends. Without this the loop runs to the end of the track
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoopPoints {
+    pub start: f32,
+    pub end: Option<f32>
+}
+
+impl LoopPoints {
+    //Returns None if the track doesn't have a sidecar
+    pub fn load(track_path: &str) -> Result<Option<Self>, String> {
+        let contents = match fs::read_to_string(Path::new(track_path).with_extension("loop")) {
+            Ok(contents) => { contents }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => { return Ok(None); }
+            Err(e) => { return Err(e.to_string()); }
+        };
+
+        let mut start = 0.0;
+        let mut end = None;
+        for line in contents.lines() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.len() == 0 {
+                continue;
+            }
+
+            let seconds = match tokens.get(1).and_then(|token| token.parse::<f32>().ok()) {
+                Some(seconds) if tokens.len() == 2 && seconds >= 0.0 => { seconds }
+                _ => { return Err(format!("Unable to parse line \"{}\"", line)); }
+            };
+            match tokens[0] {
+                "start" => { start = seconds; }
+                "end" => { end = Some(seconds); }
+                _ => { return Err(format!("Unable to parse line \"{}\"", line)); }
+            }
+        }
+
+        match end {
+            Some(end) if end <= start => { Err(String::from("The loop ends before it starts")) }
+            _ => { Ok(Some(LoopPoints { start, end })) }
+        }
+    }
+}
+
+//Where an MP3 or FLAC frame is in the file and when it starts playing
+struct FrameInfo {
+    offset: u64,
+    start: f64
+}
+
+enum DecoderKind {
+    Mp3(mp3::Decoder<File>),
+    Wav(hound::WavReader<BufReader<File>>),
+    Ogg(lewton::inside_ogg::OggStreamReader<BufReader<File>>),
+    Flac(claxon::frame::FrameReader<claxon::input::BufferedReader<File>>, claxon::metadata::StreamInfo)
+}
+
+//Streams decoded audio out of a file in any of the supported formats
+pub struct AudioDecoder {
+    path: String,
+    format: AudioFormat,
+    kind: DecoderKind,
+    length: Option<f32>,
+    frames: Vec<FrameInfo>,                 //Only filled in for MP3s and FLACs, whose decoders have no way to seek by time
+    position: f64,                          //Seconds into the stream at the end of the last frame returned
+    end: f64,                               //Where the stream stops, which is before the end of the file for MP3s with encoder padding
+    skip_to: Option<f64>,                   //Point being seeked to. Anything decoded before it is thrown away
+    pending: Option<AudioFrame>,            //Audio that was decoded while seeking and hasn't been returned yet
+    loop_points: Option<LoopPoints>,
+    loop_ended: bool                        //Whether the last frame was cut off at the end of the loop
+}
+
+impl AudioDecoder {
+    //Pattern for file dialogs that lists every format this can decode
+    pub const FILE_PATTERNS: [&'static str; 4] = ["*.mp3", "*.wav", "*.ogg", "*.flac"];
+
+    pub fn open(path: &str) -> Result<Self, String> {
+        let mut file = File::open(path).map_err(|e| e.to_string())?;
+
+        let mut header = Vec::with_capacity(12);
+        (&mut file).take(12).read_to_end(&mut header).map_err(|e| e.to_string())?;
+        file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+
+        let format = match AudioFormat::sniff(&header) {
+            Some(format) => { format }
+            None => { return Err(String::from("Unrecognized audio format")); }
+        };
+        let kind = match format {
+            AudioFormat::Mp3 => { DecoderKind::Mp3(mp3::Decoder::new(file)) }
+            AudioFormat::Wav => { DecoderKind::Wav(hound::WavReader::new(BufReader::new(file)).map_err(|e| e.to_string())?) }
+            AudioFormat::Ogg => { DecoderKind::Ogg(lewton::inside_ogg::OggStreamReader::new(BufReader::new(file)).map_err(|e| e.to_string())?) }
+            AudioFormat::Flac => {
+                //The frames are read straight from the file so that they can be read from anywhere in it
+                let info = claxon::FlacReader::new(file).map_err(|e| e.to_string())?.streaminfo();
+                let mut file = File::open(path).map_err(|e| e.to_string())?;
+                file.seek(SeekFrom::Start(flac_audio_offset(path)?)).map_err(|e| e.to_string())?;
+                DecoderKind::Flac(claxon::frame::FrameReader::new(claxon::input::BufferedReader::new(file)), info)
+            }
+        };
+
+        //MP3 and Ogg don't record their length up front, so it's found by scanning the file
+        let mut frames = Vec::new();
+        let mut end = f64::INFINITY;
+        let length = match &kind {
+            DecoderKind::Mp3(_) => {
+                let (mp3_frames, length) = scan_mp3_frames(path)?;
+                frames = mp3_frames;
+                end = length;
+                if length > 0.0 { Some(length as f32) } else { None }
+            }
+            DecoderKind::Wav(reader) => { Some(reader.duration() as f32 / reader.spec().sample_rate as f32) }
+            DecoderKind::Ogg(reader) => {
+                let sample_rate = reader.ident_hdr.audio_sample_rate;
+                last_ogg_granule(path).map(|granule| granule as f32 / sample_rate as f32)
+            }
+            DecoderKind::Flac(_, info) => {
+                frames = scan_flac_frames(path, info)?;
+                info.samples.map(|samples| samples as f32 / info.sample_rate as f32)
+            }
+        };
+
+        let mut decoder = AudioDecoder {
+            path: String::from(path),
+            format,
+            kind,
+            length,
+            frames,
+            position: 0.0,
+            end,
+            skip_to: None,
+            pending: None,
+            loop_points: None,
+            loop_ended: false
+        };
+
+        //MP3s start at their first audio frame, past any tags and with the encoder delay cut off
+        if format == AudioFormat::Mp3 && decoder.frames.len() > 0 {
+            decoder.seek(0.0)?;
+        }
+        Ok(decoder)
+    }
+
+    //Length of the stream in seconds, if it could be worked out
+    pub fn length(&self) -> Option<f32> {
+        self.length
+    }
+
+    //Seconds into the stream at the end of the last frame returned by next_frame
+    pub fn position(&self) -> f32 {
+        self.position as f32
+    }
+
+    //Whether the stream jumps back to its loop start instead of ever ending
+    pub fn loops(&self) -> bool {
+        self.loop_points.is_some()
+    }
+
+    //Once playback reaches the end of the loop it jumps back to the start of it, forever
+    pub fn set_loop_points(&mut self, loop_points: Option<LoopPoints>) {
+        self.loop_points = loop_points;
+        self.loop_ended = false;
+    }
+
+    //Decodes the next chunk of audio, returning None at the end of the stream
+    //Frames always come out with one or two channels, with surround sound folded down to stereo
+    //Frames are trimmed so that seeks land on the exact sample asked for and loops end on the exact sample of the loop end
+    pub fn next_frame(&mut self) -> Result<Option<AudioFrame>, String> {
+        loop {
+            if self.loop_ended {
+                self.loop_ended = false;
+                if let Some(points) = self.loop_points {
+                    self.seek(points.start)?;
+                }
+            }
+
+            let frame = if self.position < self.end { self.next_raw_frame()? } else { None };
+            let frame = match frame {
+                Some(frame) => { frame }
+                None => {
+                    //A loop without an end point, or with one past the end of the stream, runs to the end of the stream
+                    match self.loop_points {
+                        Some(points) if self.position > points.start as f64 => {
+                            self.seek(points.start)?;
+                            continue;
+                        }
+                        _ => { return Ok(None); }
+                    }
+                }
+            };
+            let mut frame = if frame.channels > 2 {
+                let layout = Speaker::layout(self.format, frame.channels);
+                frame.downmix_to_stereo(&layout)
+            } else {
+                frame
+            };
+            if frame.channels == 0 || frame.sample_rate <= 0 {
+                return Ok(Some(frame));
+            }
+
+            let sample_rate = frame.sample_rate as f64;
+            let frames = frame.samples.len() / frame.channels;
+            let start = self.position;
+            let end = start + frames as f64 / sample_rate;
+            self.position = end;
+
+            //Throw away whatever comes before the point being seeked to
+            let mut first = 0;
+            if let Some(target) = self.skip_to {
+                if end <= target {
+                    continue;
+                }
+                first = usize::min(f64::max((target - start) * sample_rate, 0.0).round() as usize, frames);
+                self.skip_to = None;
+            }
+
+            //Cut the frame off at the end of the loop
+            //Seeking past the end of the loop plays the rest of the track before it loops again
+            let mut last = frames;
+            if let Some(LoopPoints { end: Some(loop_end), .. }) = self.loop_points {
+                let loop_end = loop_end as f64;
+                if start + first as f64 / sample_rate < loop_end && end >= loop_end {
+                    last = usize::max(((loop_end - start) * sample_rate).round() as usize, first);
+                    self.position = loop_end;
+                    self.loop_ended = true;
+                }
+            }
+
+            //Cut the frame off where the stream ends, dropping the encoder padding
+            if end > self.end {
+                last = usize::min(last, usize::max(((self.end - start) * sample_rate).round() as usize, first));
+                self.position = f64::min(self.position, self.end);
+            }
+
+            if first > 0 || last < frames {
+                frame.samples = frame.samples[first * frame.channels..last * frame.channels].to_vec();
+            }
+            if frame.samples.len() == 0 {
+                continue;
+            }
+            return Ok(Some(frame));
+        }
+    }
+
+    fn next_raw_frame(&mut self) -> Result<Option<AudioFrame>, String> {
+        if let Some(frame) = self.pending.take() {
+            return Ok(Some(frame));
+        }
+        match &mut self.kind {
+            DecoderKind::Mp3(decoder) => {
+                match decoder.next_frame() {
+                    Ok(frame) => {
+                        Ok(Some(AudioFrame {
+                            samples: frame.data,
+                            channels: frame.channels,
+                            sample_rate: frame.sample_rate
+                        }))
+                    }
+                    Err(mp3::Error::Eof) => { Ok(None) }
+                    Err(e) => { Err(e.to_string()) }
+                }
+            }
+            DecoderKind::Wav(reader) => {
+                let spec = reader.spec();
+                let channels = spec.channels as usize;
+                let samples: Result<Vec<i16>, hound::Error> = match spec.sample_format {
+                    hound::SampleFormat::Float => {
+                        reader.samples::<f32>().take(FRAME_LENGTH * channels).map(|s| s.map(|s| (s.max(-1.0).min(1.0) * i16::MAX as f32) as i16)).collect()
+                    }
+                    hound::SampleFormat::Int => {
+                        let bits = spec.bits_per_sample as i32;
+                        reader.samples::<i32>().take(FRAME_LENGTH * channels).map(|s| s.map(|s| to_i16(s, bits))).collect()
+                    }
+                };
+                let samples = samples.map_err(|e| e.to_string())?;
+                if samples.len() == 0 {
+                    return Ok(None);
+                }
+                Ok(Some(AudioFrame {
+                    samples,
+                    channels,
+                    sample_rate: spec.sample_rate as i32
+                }))
+            }
+            DecoderKind::Ogg(reader) => {
+                //Some packets don't contain any audio, so keep going until one does
+                loop {
+                    match reader.read_dec_packet_itl() {
+                        Ok(Some(samples)) => {
+                            if samples.len() == 0 {
+                                continue;
+                            }
+                            return Ok(Some(AudioFrame {
+                                samples,
+                                channels: reader.ident_hdr.audio_channels as usize,
+                                sample_rate: reader.ident_hdr.audio_sample_rate as i32
+                            }));
+                        }
+                        Ok(None) => { return Ok(None); }
+                        Err(e) => { return Err(e.to_string()); }
+                    }
+                }
+            }
+            DecoderKind::Flac(reader, info) => {
+                let bits = info.bits_per_sample as i32;
+                match reader.read_next_or_eof(Vec::new()) {
+                    Ok(Some(block)) => {
+                        let mut samples = Vec::with_capacity(block.len() as usize);
+                        for i in 0..block.duration() {
+                            for channel in 0..block.channels() {
+                                samples.push(to_i16(block.sample(channel, i), bits));
+                            }
+                        }
+                        Ok(Some(AudioFrame {
+                            samples,
+                            channels: block.channels() as usize,
+                            sample_rate: info.sample_rate as i32
+                        }))
+                    }
+                    Ok(None) => { Ok(None) }
+                    Err(e) => { Err(e.to_string()) }
+                }
+            }
+        }
+    }
+
+    //Moves to a point in the stream, in seconds, so the next frame starts exactly there
+    pub fn seek(&mut self, seconds: f32) -> Result<(), String> {
+        let seconds = f64::max(seconds as f64, 0.0);
+        self.pending = None;
+        match &mut self.kind {
+            DecoderKind::Wav(reader) => {
+                let sample_rate = reader.spec().sample_rate;
+                let frame = u32::min((seconds * sample_rate as f64) as u32, reader.duration());
+                reader.seek(frame).map_err(|e| e.to_string())?;
+                self.position = frame as f64 / sample_rate as f64;
+            }
+            DecoderKind::Ogg(reader) => {
+                //The seek lands on the page ending at or before the target, so the position is only known again once that page has been decoded
+                let sample_rate = reader.ident_hdr.audio_sample_rate as f64;
+                //Packets are held on to until then, and only the part before the target is thrown away
+                let target = (seconds * sample_rate) as u64;
+                let channels = reader.ident_hdr.audio_channels as usize;
+                reader.seek_absgp_pg(target).map_err(|e| e.to_string())?;
+                self.position = seconds;
+                let mut samples = Vec::new();
+                loop {
+                    match reader.read_dec_packet_itl() {
+                        Ok(Some(packet)) => {
+                            samples.extend(packet);
+                            if let Some(granule) = reader.get_last_absgp() {
+                                let start = granule.saturating_sub((samples.len() / channels) as u64);
+                                let first = u64::min(target.saturating_sub(start), (samples.len() / channels) as u64);
+                                samples.drain(..first as usize * channels);
+                                self.position = (start + first) as f64 / sample_rate;
+                                break;
+                            }
+                        }
+                        Ok(None) => { break; }
+                        Err(e) => { return Err(e.to_string()); }
+                    }
+                }
+                if samples.len() > 0 {
+                    self.pending = Some(AudioFrame {
+                        samples,
+                        channels,
+                        sample_rate: sample_rate as i32
+                    });
+                }
+            }
+            DecoderKind::Mp3(_) | DecoderKind::Flac(..) => {
+                if self.frames.len() == 0 {
+                    return Err(String::from("No frames were found to seek to"));
+                }
+
+                //Open the file again at the frame, since the decoder has bytes from the old position buffered
+                //MP3 frames depend on the ones before them, so decoding starts a few frames early
+                let target = self.frames.iter().rposition(|frame| frame.start <= seconds).unwrap_or(0);
+                let frame = match self.kind {
+                    DecoderKind::Mp3(_) => { &self.frames[target.saturating_sub(MP3_PRIMING_FRAMES)] }
+                    _ => { &self.frames[target] }
+                };
+                let mut file = File::open(&self.path).map_err(|e| e.to_string())?;
+                file.seek(SeekFrom::Start(frame.offset)).map_err(|e| e.to_string())?;
+                match &mut self.kind {
+                    DecoderKind::Flac(reader, _) => { *reader = claxon::frame::FrameReader::new(claxon::input::BufferedReader::new(file)); }
+                    kind => { *kind = DecoderKind::Mp3(mp3::Decoder::new(file)); }
+                }
+                self.position = frame.start;
+            }
+        }
+        self.skip_to = Some(seconds);
+        self.loop_ended = false;
+        Ok(())
+    }
+}
+
+//Size in bytes, samples per channel and sample rate of the MPEG audio frame with this header, if it's a valid header
+fn mp3_frame_header(header: &[u8]) -> Option<(usize, usize, usize)> {
+    //Bitrates in kbps, starting from bitrate index 1
+    const BITRATES: [[usize; 14]; 5] = [
+        [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],        //MPEG 1 layer I
+        [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],           //MPEG 1 layer II
+        [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],            //MPEG 1 layer III
+        [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],           //MPEG 2 and 2.5 layer I
+        [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160]                 //MPEG 2 and 2.5 layers II and III
+    ];
+
+    if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = (header[1] >> 3) & 3;                 //0 is MPEG 2.5, 2 is MPEG 2 and 3 is MPEG 1
+    let layer = (header[1] >> 1) & 3;                   //1 is layer III, 2 is layer II and 3 is layer I
+    let bitrate_index = (header[2] >> 4) as usize;
+    let rate_index = ((header[2] >> 2) & 3) as usize;
+    let padding = ((header[2] >> 1) & 1) as usize;
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+
+    let table = match (version, layer) {
+        (3, 3) => { 0 }
+        (3, 2) => { 1 }
+        (3, _) => { 2 }
+        (_, 3) => { 3 }
+        _ => { 4 }
+    };
+    let bitrate = BITRATES[table][bitrate_index - 1] * 1000;
+    let sample_rate = [44100, 48000, 32000][rate_index] >> match version { 3 => { 0 } 2 => { 1 } _ => { 2 } };
+    let samples = match (version, layer) {
+        (_, 3) => { 384 }
+        (3, _) | (_, 2) => { 1152 }
+        _ => { 576 }
+    };
+    let size = if layer == 3 {
+        (12 * bitrate / sample_rate + padding) * 4
+    } else {
+        samples / 8 * bitrate / sample_rate + padding
+    };
+    Some((size, samples, sample_rate))
+}
+
+//Encoder delay and padding in samples from the Xing or Info header that encoders write in place of the first frame, if this frame holds one
+//Without the LAME extension to the header there's no record of either, so nothing gets trimmed
+fn mp3_xing_header(frame: &[u8]) -> Option<(usize, usize)> {
+    let mpeg1 = (frame[1] >> 3) & 3 == 3;
+    let mono = frame[3] >> 6 == 3;
+    let side_info = match (mpeg1, mono) {
+        (true, false) => { 32 }
+        (false, true) => { 9 }
+        _ => { 17 }
+    };
+    let tag = frame.get(4 + side_info..)?;
+    if !tag.starts_with(b"Xing") && !tag.starts_with(b"Info") {
+        return None;
+    }
+
+    //The flags say which of the frame count, byte count, table of contents and quality fields come before the LAME extension
+    let flags = *tag.get(7)?;
+    let mut lame = 8;
+    for &(flag, size) in [(1, 4), (2, 4), (4, 100), (8, 4)].iter() {
+        if flags & flag != 0 {
+            lame += size;
+        }
+    }
+    match tag.get(lame..lame + 24) {
+        Some(lame) if lame.starts_with(b"LAME") || lame.starts_with(b"Lavc") || lame.starts_with(b"Lavf") => {
+            let delay = ((lame[21] as usize) << 4) | (lame[22] as usize >> 4);
+            let padding = ((lame[22] as usize & 0xF) << 8) | lame[23] as usize;
+            Some((delay + MP3_DECODER_DELAY, padding.saturating_sub(MP3_DECODER_DELAY)))
+        }
+        _ => { Some((0, 0)) }
+    }
+}
+
+//Finds every audio frame of an MP3 file from the frame headers, without decoding anything, along with the length in seconds
+//Frame start times and the length leave out the encoder delay and padding, so time 0 is the first sample of the actual audio
+fn scan_mp3_frames(path: &str) -> Result<(Vec<FrameInfo>, f64), String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+
+    //Skip the ID3v2 tag, whose size is stored seven bits to a byte
+    let mut i = 0;
+    if bytes.len() >= 10 && bytes.starts_with(b"ID3") {
+        let size = bytes[6..10].iter().fold(0, |size, &b| (size << 7) | (b & 0x7F) as usize);
+        let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+        i = 10 + size + footer;
+    }
+
+    //A header only counts if another one follows right after it, since the sync bits turn up in other data too
+    let mut frames = Vec::new();
+    let mut start = 0.0;
+    let mut trim = None;
+    while i + 4 <= bytes.len() {
+        match mp3_frame_header(&bytes[i..i + 4]) {
+            Some((size, samples, sample_rate)) if size > 4 && (i + size + 4 > bytes.len() || mp3_frame_header(&bytes[i + size..i + size + 4]).is_some()) => {
+                //The Xing or Info frame is silent, so it's left out of the stream entirely
+                if frames.len() == 0 && trim.is_none() {
+                    if let Some((delay, padding)) = mp3_xing_header(&bytes[i..usize::min(i + size, bytes.len())]) {
+                        trim = Some((delay as f64 / sample_rate as f64, padding as f64 / sample_rate as f64));
+                        i += size;
+                        continue;
+                    }
+                }
+                frames.push(FrameInfo { offset: i as u64, start });
+                start += samples as f64 / sample_rate as f64;
+                i += size;
+            }
+            _ => { i += 1; }
+        }
+    }
+
+    let (delay, padding) = trim.unwrap_or((0.0, 0.0));
+    for frame in frames.iter_mut() {
+        frame.start -= delay;
+    }
+    Ok((frames, f64::max(start - delay - padding, 0.0)))
+}
+
+//Byte offset of the first audio frame of a FLAC file, just past the last metadata block
+fn flac_audio_offset(path: &str) -> Result<u64, String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut offset = 4;
+    loop {
+        let mut header = [0; 4];
+        file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+        file.read_exact(&mut header).map_err(|e| e.to_string())?;
+        offset += 4 + ((header[1] as u64) << 16 | (header[2] as u64) << 8 | header[3] as u64);
+        if header[0] & 0x80 != 0 {
+            return Ok(offset);
+        }
+    }
+}
+
+//Finds every frame of a FLAC file from the frame headers, without decoding anything
+//A header only counts if its checksum matches and it's numbered in order after the frames found so far, since the sync bits turn up in audio data too
+fn scan_flac_frames(path: &str, info: &claxon::metadata::StreamInfo) -> Result<Vec<FrameInfo>, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let mut frames = Vec::new();
+    let mut i = flac_audio_offset(path)? as usize;
+    let mut last = None;
+    while i + 16 <= bytes.len() {
+        if let Some((header_size, number, variable_block_size)) = flac_frame_header(&bytes[i..i + 16]) {
+            //Fixed size blocks are numbered by frame and variable size ones by their first sample
+            let in_order = match last {
+                Some(last) if variable_block_size => { number > last && number - last <= info.max_block_size as u64 }
+                Some(last) => { number == last + 1 }
+                None => { true }
+            };
+            if in_order {
+                let sample = if variable_block_size { number } else { number * info.max_block_size as u64 };
+                frames.push(FrameInfo { offset: i as u64, start: sample as f64 / info.sample_rate as f64 });
+                last = Some(number);
+                i += header_size;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    Ok(frames)
+}
+
+//Size in bytes of this FLAC frame header and the frame or sample number in it, and whether that's a sample number, if it's a valid header
+fn flac_frame_header(header: &[u8]) -> Option<(usize, u64, bool)> {
+    if header[0] != 0xFF || header[1] & 0xFE != 0xF8 {
+        return None;
+    }
+    let variable_block_size = header[1] & 1 != 0;
+    let block_size_code = header[2] >> 4;
+    let rate_code = header[2] & 0xF;
+    let channels_code = header[3] >> 4;
+    let bits_code = (header[3] >> 1) & 7;
+    if block_size_code == 0 || rate_code == 15 || channels_code > 10 || bits_code == 3 || bits_code == 7 || header[3] & 1 != 0 {
+        return None;
+    }
+
+    //The frame or sample number is stored the way UTF-8 stores characters
+    let length = (!header[4]).leading_zeros() as usize;
+    let (mut number, length) = match length {
+        0 => { (header[4] as u64, 1) }
+        2..=7 => { ((header[4] & (0x7F >> length)) as u64, length) }
+        _ => { return None; }
+    };
+    for &byte in &header[5..4 + length] {
+        if byte & 0xC0 != 0x80 {
+            return None;
+        }
+        number = (number << 6) | (byte & 0x3F) as u64;
+    }
+
+    let mut size = 4 + length;
+    size += match block_size_code { 6 => { 1 } 7 => { 2 } _ => { 0 } };
+    size += match rate_code { 12 => { 1 } 13 | 14 => { 2 } _ => { 0 } };
+    let crc = header[..size].iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 })
+    });
+    if crc != header[size] {
+        return None;
+    }
+    Some((size + 1, number, variable_block_size))
+}
+
+//Granule position of the last page of an Ogg file, which is the number of samples per channel in the stream
+fn last_ogg_granule(path: &str) -> Option<u64> {
+    let mut file = File::open(path).ok()?;
+    let file_length = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(file_length.saturating_sub(OGG_TAIL_BYTES))).ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+
+    let page = (0..tail.len().saturating_sub(14)).rev().find(|&i| &tail[i..i + 4] == b"OggS")?;
+    let mut granule = [0; 8];
+    granule.copy_from_slice(&tail[page + 6..page + 14]);
+    match u64::from_le_bytes(granule) {
+        u64::MAX => { None }
+        granule => { Some(granule) }
+    }
+}
+
+//Scales an integer sample of the given bit depth to 16 bits
+fn to_i16(sample: i32, bits: i32) -> i16 {
+    if bits > 16 {
+        (sample >> (bits - 16)) as i16
+    } else {
+        (sample << (16 - bits)) as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(start: usize, count: usize, sample_rate: i32) -> AudioFrame {
+        AudioFrame {
+            samples: (start..(start + count)).map(|i| (i * 10) as i16).collect(),
+            channels: 1,
+            sample_rate
+        }
+    }
+
+    #[test]
+    fn resampling_carries_over_between_frames() {
+        let mut whole = Resampler::new(1, 48000);
+        let expected = whole.convert(ramp(0, 441, 44100)).samples;
+
+        //The same audio split into uneven frames comes out the same, without restarting at each frame
+        let mut split = Resampler::new(1, 48000);
+        let mut samples = Vec::new();
+        for &(start, count) in [(0, 100), (100, 37), (137, 304)].iter() {
+            samples.extend(split.convert(ramp(start, count, 44100)).samples);
+        }
+        assert_eq!(samples.len(), expected.len());
+        for (a, b) in samples.iter().zip(expected.iter()) {
+            assert!((a - b).abs() <= 1, "{} and {} differ", a, b);
+        }
+    }
+
+    #[test]
+    fn resampling_keeps_the_rate_over_many_frames() {
+        //Converting a second of 44.1kHz audio in small frames comes out as a second of 48kHz audio
+        let mut resampler = Resampler::new(1, 48000);
+        let mut output_frames = 0;
+        for i in 0..(44100 / 147) {
+            output_frames += resampler.convert(ramp(i * 147, 147, 44100)).samples.len();
+        }
+        assert!((output_frames as i32 - 48000).abs() <= 1);
+    }
+
+    #[test]
+    fn xing_header_gives_the_lame_delay_and_padding() {
+        //A 128kbps stereo MPEG 1 layer III frame holding an Info tag with no optional fields, then a LAME extension
+        let mut frame = vec![0; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+        frame[36..40].copy_from_slice(b"Info");
+        frame[44..53].copy_from_slice(b"LAME3.100");
+        frame[65..68].copy_from_slice(&[0x24, 0x03, 0xE8]);        //576 samples of delay and 1000 of padding
+        assert_eq!(mp3_xing_header(&frame), Some((576 + MP3_DECODER_DELAY, 1000 - MP3_DECODER_DELAY)));
+
+        //An ordinary audio frame isn't mistaken for one
+        frame[36..40].copy_from_slice(b"\0\0\0\0");
+        assert_eq!(mp3_xing_header(&frame), None);
+    }
+
+    #[test]
+    fn flac_frame_headers_are_checked() {
+        let mut header = [0; 16];
+
+        //Fixed size blocks of 4096 samples, numbered by frame
+        header[..6].copy_from_slice(&[0xFF, 0xF8, 0xC9, 0x18, 0x00, 0xC2]);
+        assert_eq!(flac_frame_header(&header), Some((6, 0, false)));
+
+        //Variable size blocks, numbered by sample, with the block size stored after the number
+        header[..9].copy_from_slice(&[0xFF, 0xF9, 0x69, 0x18, 0xE2, 0x82, 0x80, 0xFF, 0x83]);
+        assert_eq!(flac_frame_header(&header), Some((9, 0x2080, true)));
+
+        //Anything that doesn't match the checksum is audio data that happens to look like a header
+        header[8] ^= 1;
+        assert_eq!(flac_frame_header(&header), None);
+    }
+}
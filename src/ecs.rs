@@ -0,0 +1,489 @@
+use std::mem;
+use std::sync::mpsc::Sender;
+use ozy::collision::*;
+use ozy::structs::OptionVec;
+use crate::analysis::MusicEnvelope;
+use crate::audio::AudioCommand;
+use crate::chicken::Chicken;
+use crate::render::SceneData;
+use crate::structs::{ray_hit_sphere, HitMarker};
+use crate::totoro::Totoro;
+
+#[derive(Clone, Debug)]
+pub struct Transform {
+    pub position: glm::TVec3<f32>,
+    pub rotation: glm::Qua<f32>,
+    pub scale: glm::TVec3<f32>
+}
+
+impl Transform {
+    pub fn from_position(position: glm::TVec3<f32>) -> Self {
+        Transform {
+            position,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_matrix(&self) -> glm::TMat4<f32> {
+        glm::translation(&self.position) * glm::quat_to_mat4(&self.rotation) * glm::scaling(&self.scale)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            position: glm::zero(),
+            rotation: glm::quat_identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0)
+        }
+    }
+}
+
+//Links an object to one instance of a RenderEntity
+#[derive(Clone, Debug)]
+pub struct RenderMesh {
+    pub entity_index: usize,
+    pub instance_index: usize,                  //Assigned when the instance buffer is rebuilt
+    pub local_transform: glm::TMat4<f32>        //Applied on top of the object's transform, for purely visual motion
+}
+
+impl RenderMesh {
+    pub fn new(entity_index: usize) -> Self {
+        RenderMesh {
+            entity_index,
+            instance_index: 0,
+            local_transform: glm::identity()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SphereCollider {
+    pub offset: glm::TVec3<f32>,
+    pub radius: f32
+}
+
+//Keeps audio sources positioned on the object. Each one is a sound effect handle or BGM_SOURCE
+#[derive(Clone, Debug)]
+pub struct AudioEmitter {
+    pub sources: Vec<usize>
+}
+
+#[derive(Clone, Debug)]
+pub enum Behaviour {
+    Static,
+    Totoro(Totoro),
+    Chicken(Chicken),
+    HitMarker(HitMarker)
+}
+
+#[derive(Clone, Debug)]
+pub struct GameObject {
+    pub transform: Transform,
+    pub mesh: Option<RenderMesh>,
+    pub collider: Option<SphereCollider>,
+    pub audio_emitter: Option<AudioEmitter>,
+    pub behaviour: Behaviour,
+    pub editable: bool                          //Whether the prop editor can select the object. Only level props are editable
+}
+
+impl GameObject {
+    pub fn new(transform: Transform) -> Self {
+        GameObject {
+            transform,
+            mesh: None,
+            collider: None,
+            audio_emitter: None,
+            behaviour: Behaviour::Static,
+            editable: false
+        }
+    }
+
+    pub fn collision_sphere(&self) -> Option<Sphere> {
+        match &self.collider {
+            Some(collider) => {
+                Some(Sphere {
+                    focus: self.transform.position + collider.offset,
+                    radius: collider.radius
+                })
+            }
+            None => { None }
+        }
+    }
+
+    //Checks if a ray from the origin is pointed at the object within the given cone and range
+    pub fn is_targeted_by(&self, origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>, cone_radians: f32, range: f32) -> bool {
+        match self.collision_sphere() {
+            Some(sphere) => {
+                let to_object = sphere.focus - origin;
+                let distance = glm::length(&to_object);
+                distance < range && (distance < sphere.radius || glm::dot(&(to_object / distance), direction) > f32::cos(cone_radians))
+            }
+            None => { false }
+        }
+    }
+}
+
+//A RenderEntity whose instance buffer is filled from the objects in the world
+struct ManagedMesh {
+    entity_index: usize,
+    dirty: bool
+}
+
+//Refers to one particular object, and stops resolving once it has been despawned even if its slot gets reused
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectHandle {
+    pub id: usize,
+    generation: u32
+}
+
+pub struct World {
+    pub objects: OptionVec<GameObject>,
+    generations: Vec<u32>,                  //Number of times each object slot has been emptied
+    managed_meshes: Vec<ManagedMesh>
+}
+
+impl World {
+    pub fn new() -> Self {
+        World {
+            objects: OptionVec::with_capacity(64),
+            generations: Vec::with_capacity(64),
+            managed_meshes: Vec::new()
+        }
+    }
+
+    //Hands ownership of a RenderEntity's instance buffer over to the world
+    pub fn manage_mesh(&mut self, entity_index: usize) {
+        self.managed_meshes.push(ManagedMesh {
+            entity_index,
+            dirty: true
+        });
+    }
+
+    //Flags a mesh's instance buffer as needing to be rebuilt
+    pub fn mark_dirty(&mut self, entity_index: usize) {
+        for mesh in self.managed_meshes.iter_mut() {
+            if mesh.entity_index == entity_index {
+                mesh.dirty = true;
+            }
+        }
+    }
+
+    pub fn spawn(&mut self, object: GameObject) -> usize {
+        if let Some(mesh) = &object.mesh {
+            self.mark_dirty(mesh.entity_index);
+        }
+        self.objects.insert(object)
+    }
+
+    pub fn despawn(&mut self, id: usize) {
+        let mesh_index = match self.objects.get_mut_element(id) {
+            Some(object) => { object.mesh.as_ref().map(|mesh| mesh.entity_index) }
+            None => { return; }
+        };
+        if let Some(entity_index) = mesh_index {
+            self.mark_dirty(entity_index);
+        }
+        self.objects.delete(id);
+        if id >= self.generations.len() {
+            self.generations.resize(id + 1, 0);
+        }
+        self.generations[id] += 1;
+    }
+
+    //Returns a handle to the object currently in the given slot
+    pub fn handle(&self, id: usize) -> Option<ObjectHandle> {
+        match &self.objects[id] {
+            Some(_) => {
+                Some(ObjectHandle {
+                    id,
+                    generation: self.generations.get(id).copied().unwrap_or(0)
+                })
+            }
+            None => { None }
+        }
+    }
+
+    //Returns the id of the object a handle refers to, if that object still exists
+    pub fn resolve(&self, handle: ObjectHandle) -> Option<usize> {
+        match self.handle(handle.id) {
+            Some(current) if current == handle => { Some(handle.id) }
+            _ => { None }
+        }
+    }
+
+    //Marks where a shotgun pellet landed, making room by removing the oldest marker if there are already too many
+    pub fn spawn_hit_marker(&mut self, entity_index: usize, position: &glm::TVec3<f32>, normal: &glm::TVec3<f32>, elapsed_time: f32) -> usize {
+        let mut markers = Vec::new();
+        for i in 0..self.objects.len() {
+            if let Some(GameObject { behaviour: Behaviour::HitMarker(marker), .. }) = &self.objects[i] {
+                markers.push((marker.spawn_time, i));
+            }
+        }
+        if markers.len() >= HitMarker::MAX_COUNT {
+            markers.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            for &(_, id) in markers[..=(markers.len() - HitMarker::MAX_COUNT)].iter() {
+                self.despawn(id);
+            }
+        }
+
+        //The marker is a flattened cylinder, which extends along its local y axis
+        let transform = Transform {
+            position: *position,
+            rotation: glm::quat_rotation(&glm::vec3(0.0, 1.0, 0.0), normal),
+            scale: HitMarker::full_scale()
+        };
+        self.spawn(GameObject {
+            mesh: Some(RenderMesh::new(entity_index)),
+            behaviour: Behaviour::HitMarker(HitMarker { spawn_time: elapsed_time }),
+            ..GameObject::new(transform)
+        })
+    }
+
+    //Puts a previously despawned object back under its old id, or under a new one if that slot has been reused since
+    pub fn restore(&mut self, id: usize, object: GameObject) -> usize {
+        if let Some(mesh) = &object.mesh {
+            self.mark_dirty(mesh.entity_index);
+        }
+        if id < self.objects.len() && self.objects[id].is_some() {
+            return self.objects.insert(object);
+        }
+        self.objects.replace(id, object);
+        id
+    }
+
+    //Moves an object, writing its new transform straight into its instance of the RenderEntity
+    pub fn set_transform(&mut self, id: usize, transform: Transform, scene_data: &mut SceneData) {
+        if let Some(object) = self.objects.get_mut_element(id) {
+            object.transform = transform;
+            if let Some(mesh) = &object.mesh {
+                if let Some(entity) = scene_data.entities.get_mut_element(mesh.entity_index) {
+                    unsafe { entity.update_single_transform(mesh.instance_index, &(object.transform.to_matrix() * mesh.local_transform)); }
+                }
+            }
+        }
+    }
+
+    //Moves an object, leaving its instance to be uploaded by the next sync
+    pub fn place(&mut self, id: usize, transform: Transform) {
+        let mesh_index = match self.objects.get_mut_element(id) {
+            Some(object) => {
+                object.transform = transform;
+                object.mesh.as_ref().map(|mesh| mesh.entity_index)
+            }
+            None => { return; }
+        };
+        if let Some(entity_index) = mesh_index {
+            self.mark_dirty(entity_index);
+        }
+    }
+
+    //Swaps the model an object is drawn with, or hides it if there isn't one
+    pub fn set_mesh(&mut self, id: usize, mesh: Option<RenderMesh>) {
+        let old_mesh = match self.objects.get_mut_element(id) {
+            Some(object) => { mem::replace(&mut object.mesh, mesh.clone()) }
+            None => { return; }
+        };
+        for changed in old_mesh.iter().chain(mesh.iter()) {
+            self.mark_dirty(changed.entity_index);
+        }
+    }
+
+    //Makes a sound follow an object around, along with any other sounds it's already carrying
+    pub fn attach_sound(&mut self, id: usize, source_index: usize) {
+        if let Some(object) = self.objects.get_mut_element(id) {
+            match &mut object.audio_emitter {
+                Some(emitter) => {
+                    if !emitter.sources.contains(&source_index) {
+                        emitter.sources.push(source_index);
+                    }
+                }
+                None => { object.audio_emitter = Some(AudioEmitter { sources: vec![source_index] }); }
+            }
+        }
+    }
+
+    //Returns the id of the object carrying the sound, if any
+    pub fn sound_carrier(&self, source_index: usize) -> Option<usize> {
+        for i in 0..self.objects.len() {
+            if let Some(GameObject { audio_emitter: Some(emitter), .. }) = &self.objects[i] {
+                if emitter.sources.contains(&source_index) {
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+
+    //Returns the id of and distance to the closest object whose collider is hit by the ray
+    pub fn ray_hit_objects(&self, origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>, max_distance: f32) -> Option<(usize, f32)> {
+        let mut closest = None;
+        let mut closest_distance = max_distance;
+        for i in 0..self.objects.len() {
+            if let Some(object) = &self.objects[i] {
+                if let Some(sphere) = object.collision_sphere() {
+                    if let Some(distance) = ray_hit_sphere(origin, direction, &sphere) {
+                        if distance < closest_distance {
+                            closest_distance = distance;
+                            closest = Some((i, distance));
+                        }
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    //Applies an impulse to an object, if its behaviour can be pushed around
+    pub fn knock_back(&mut self, id: usize, impulse: &glm::TVec3<f32>) {
+        if let Some(object) = self.objects.get_mut_element(id) {
+            match &mut object.behaviour {
+                Behaviour::Static | Behaviour::HitMarker(_) => {}
+                Behaviour::Totoro(totoro) => { totoro.velocity += glm::vec3(impulse.x, impulse.y, 0.0); }
+                Behaviour::Chicken(chicken) => { chicken.velocity += glm::vec3(impulse.x, impulse.y, 0.0); }
+            }
+        }
+    }
+
+    //Makes every object that reacts to being aimed at flee if it's inside the cone
+    pub fn scare_targeted(&mut self, origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>, cone_radians: f32, range: f32, elapsed_time: f32) {
+        for i in 0..self.objects.len() {
+            if let Some(object) = self.objects.get_mut_element(i) {
+                if !object.is_targeted_by(origin, direction, cone_radians, range) {
+                    continue;
+                }
+                match &mut object.behaviour {
+                    Behaviour::Static | Behaviour::HitMarker(_) => {}
+                    Behaviour::Totoro(totoro) => { totoro.scare(&object.transform.position, origin, elapsed_time); }
+                    Behaviour::Chicken(chicken) => { chicken.shove(&object.transform.position, origin, Chicken::SHOVE_SPEED); }
+                }
+            }
+        }
+    }
+
+    //Returns the positions of the chickens that are touching the capsule and ready to peck again
+    pub fn peck_player(&mut self, player_capsule: &Capsule, elapsed_time: f32) -> Vec<glm::TVec3<f32>> {
+        let mut pecks = Vec::new();
+        for i in 0..self.objects.len() {
+            if let Some(object) = self.objects.get_mut_element(i) {
+                let sphere = match object.collision_sphere() {
+                    Some(sphere) => { sphere }
+                    None => { continue; }
+                };
+                if let Behaviour::Chicken(chicken) = &mut object.behaviour {
+                    let closest = closest_point_on_line_segment(&sphere.focus, &player_capsule.segment.p0, &player_capsule.segment.p1);
+                    if glm::distance(&closest, &sphere.focus) < sphere.radius + player_capsule.radius && elapsed_time - chicken.last_peck_time > Chicken::PECK_COOLDOWN {
+                        chicken.last_peck_time = elapsed_time;
+                        pecks.push(sphere.focus);
+                    }
+                }
+            }
+        }
+        pecks
+    }
+
+    //Runs the per-object behaviours for this frame
+    pub fn update_behaviours(&mut self, terrain: &Terrain, attention_point: &glm::TVec3<f32>, music: &MusicEnvelope, delta_time: f32, elapsed_time: f32) {
+        let mut moved_meshes = Vec::new();
+        let mut expired = Vec::new();
+        for i in 0..self.objects.len() {
+            if let Some(object) = self.objects.get_mut_element(i) {
+                match &mut object.behaviour {
+                    Behaviour::Static => {}
+                    Behaviour::Totoro(totoro) => {
+                        const HOVER_HEIGHT: f32 = 0.5;
+                        const CALM_EXCITEMENT: f32 = 6.0;           //Speed of the bobbing with no music
+                        const LOUD_EXCITEMENT: f32 = 14.0;          //Speed of the bobbing when the music is at its loudest
+                        const BEAT_HOP: f32 = 0.3;                  //Extra height on each beat
+
+                        totoro.update(&mut object.transform.position, terrain, attention_point, delta_time, elapsed_time);
+                        object.transform.rotation = glm::quat_angle_axis(totoro.heading, &glm::vec3(0.0, 0.0, 1.0));
+
+                        let excitement = CALM_EXCITEMENT + (LOUD_EXCITEMENT - CALM_EXCITEMENT) * music.loudness;
+                        totoro.hover_phase = (totoro.hover_phase + excitement * delta_time) % glm::two_pi::<f32>();
+                        if let Some(mesh) = &mut object.mesh {
+                            let height = HOVER_HEIGHT * f32::sin(totoro.hover_phase) + HOVER_HEIGHT + BEAT_HOP * music.pulse;
+                            mesh.local_transform = glm::translation(&glm::vec3(0.0, 0.0, height));
+                            moved_meshes.push(mesh.entity_index);
+                        }
+                    }
+                    Behaviour::Chicken(chicken) => {
+                        chicken.update(&mut object.transform.position, terrain, attention_point, delta_time);
+                        object.transform.rotation = glm::quat_angle_axis(chicken.heading, &glm::vec3(0.0, 0.0, 1.0));
+                        if let Some(mesh) = &object.mesh {
+                            moved_meshes.push(mesh.entity_index);
+                        }
+                    }
+                    Behaviour::HitMarker(marker) => {
+                        //Markers shrink away over their lifetime
+                        let age = elapsed_time - marker.spawn_time;
+                        if age >= HitMarker::LIFETIME {
+                            expired.push(i);
+                            continue;
+                        }
+                        object.transform.scale = HitMarker::full_scale() * (1.0 - age / HitMarker::LIFETIME);
+                        if let Some(mesh) = &object.mesh {
+                            moved_meshes.push(mesh.entity_index);
+                        }
+                    }
+                }
+            }
+        }
+
+        for entity_index in moved_meshes {
+            self.mark_dirty(entity_index);
+        }
+        for id in expired {
+            self.despawn(id);
+        }
+    }
+
+    //Rebuilds the instance buffers of any meshes that have changed since the last sync
+    pub fn sync_render_entities(&mut self, scene_data: &mut SceneData) {
+        for managed in self.managed_meshes.iter_mut() {
+            if !managed.dirty {
+                continue;
+            }
+
+            let mut transform_buffer = Vec::new();
+            for i in 0..self.objects.len() {
+                if let Some(object) = self.objects.get_mut_element(i) {
+                    let world_matrix = object.transform.to_matrix();
+                    if let Some(mesh) = &mut object.mesh {
+                        if mesh.entity_index == managed.entity_index {
+                            mesh.instance_index = transform_buffer.len() / 16;
+                            let mm = world_matrix * mesh.local_transform;
+                            transform_buffer.extend_from_slice(mm.as_slice());
+                        }
+                    }
+                }
+            }
+
+            if let Some(entity) = scene_data.entities.get_mut_element(managed.entity_index) {
+                entity.update_buffer(&transform_buffer);
+            }
+            managed.dirty = false;
+        }
+    }
+
+    //Tells the audio thread where each of the audio emitters is
+    pub fn sync_audio_emitters(&self, audio_sender: &Sender<AudioCommand>) {
+        for opt_object in self.objects.iter() {
+            if let Some(object) = opt_object {
+                if let Some(emitter) = &object.audio_emitter {
+                    let local_transform = match &object.mesh {
+                        Some(mesh) => { mesh.local_transform }
+                        None => { glm::identity() }
+                    };
+                    let mm = object.transform.to_matrix() * local_transform;
+                    let pos = [mm[12], mm[13], mm[14]];
+                    for &source_index in emitter.sources.iter() {
+                        if let Err(e) = audio_sender.send(AudioCommand::SetSourcePosition(pos, source_index)) {
+                            println!("Error sending message to thread: {}", e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
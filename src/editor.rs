@@ -0,0 +1,298 @@
+use std::fs;
+use gl::types::*;
+use ozy::collision::Terrain;
+use ozy::render::TextureKeeper;
+use crate::ecs::{Behaviour, GameObject, Transform, World};
+use crate::history::{EditCommand, EditHistory, MaterialParams};
+use crate::hud::{HudQuad, HudRenderer};
+use crate::picking::{pick, PickMeshCache, PickTarget};
+use crate::render::{RenderEntity, SceneData};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale
+}
+
+impl Default for GizmoMode {
+    fn default() -> Self { GizmoMode::Translate }
+}
+
+//The state of a gizmo handle that is being dragged with the mouse
+struct GizmoDrag {
+    axis: usize,
+    start_transform: Transform,
+    start_param: f32,                       //Position along the axis where the mouse ray first grabbed it
+    start_mouse: glm::TVec2<f32>
+}
+
+//In-game editor for placing and manipulating level props
+pub struct PropEditor {
+    pub models: Vec<String>,                //Every .ozy in models/
+    pub selected_model: usize,
+    pub placement_rotation: f32,
+    pub selected_object: Option<usize>,
+    pub gizmo_mode: GizmoMode,
+    pub material_edit_start: Option<(usize, MaterialParams)>,     //Entity and material from before the current material edit
+    preview: Option<(usize, usize)>,        //Entity index of the translucent preview and the model it was loaded from
+    drag: Option<GizmoDrag>
+}
+
+impl PropEditor {
+    pub const GIZMO_SCREEN_SIZE: f32 = 0.15;        //Length of a gizmo handle relative to its distance from the camera
+    pub const HANDLE_THICKNESS: f32 = 0.08;         //Relative to the handle's length
+    pub const ROTATE_SENSITIVITY: f32 = 0.01;       //Radians per pixel
+    const MIN_SCALE: f32 = 0.05;
+
+    pub fn new() -> Self {
+        let mut models = Vec::new();
+        match fs::read_dir("models") {
+            Ok(entries) => {
+                for entry in entries {
+                    if let Ok(entry) = entry {
+                        let path = entry.path();
+                        if let (Some(ext), Some(name)) = (path.extension(), path.file_name()) {
+                            if ext == "ozy" {
+                                models.push(name.to_string_lossy().into_owned());
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => { println!("Unable to list the models directory: {}", e); }
+        }
+        models.sort();
+
+        PropEditor {
+            models,
+            selected_model: 0,
+            placement_rotation: 0.0,
+            selected_object: None,
+            gizmo_mode: GizmoMode::Translate,
+            material_edit_start: None,
+            preview: None,
+            drag: None
+        }
+    }
+
+    pub fn selected_model_name(&self) -> Option<&str> {
+        self.models.get(self.selected_model).map(|s| s.as_str())
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    //Returns the index of the translucent preview of the selected model, reloading it if the selection has changed
+    pub fn preview_entity(&mut self, scene_data: &mut SceneData, program: GLuint, texture_keeper: &mut TextureKeeper, tex_params: &[(GLenum, GLenum)]) -> Option<usize> {
+        let model_name = match self.selected_model_name() {
+            Some(name) => { String::from(name) }
+            None => { return None; }
+        };
+
+        match self.preview {
+            Some((entity_index, model)) if model == self.selected_model => { Some(entity_index) }
+            _ => {
+                let mut entity = RenderEntity::from_ozy(&format!("models/{}", model_name), program, 1, texture_keeper, tex_params);
+                entity.should_be_rendered = false;
+                entity.casts_shadow = false;            //The preview is translucent, so a solid shadow under it would look wrong
+                entity.color = glm::vec3(0.3, 0.8, 1.0);
+                let entity_index = match self.preview {
+                    Some((entity_index, _)) => {
+                        scene_data.entities.replace(entity_index, entity);
+                        entity_index
+                    }
+                    None => { scene_data.entities.insert(entity) }
+                };
+                self.preview = Some((entity_index, self.selected_model));
+                Some(entity_index)
+            }
+        }
+    }
+
+    //Hides the preview while the editor isn't placing props
+    pub fn hide_preview(&self, scene_data: &mut SceneData) {
+        if let Some((entity_index, _)) = self.preview {
+            if let Some(entity) = scene_data.entities.get_mut_element(entity_index) {
+                entity.should_be_rendered = false;
+            }
+        }
+    }
+
+    //Returns the id of the level prop under the ray, if it isn't hidden behind something else
+    //The terrain and anything else that isn't an editable prop can't be selected
+    pub fn pick_prop(world: &World, scene_data: &SceneData, terrain: &Terrain, pick_meshes: &mut PickMeshCache, origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>) -> Option<usize> {
+        let id = match pick(world, &scene_data.entities, terrain, pick_meshes, origin, direction)?.target {
+            PickTarget::Object(id) => { id }
+            PickTarget::Terrain => { return None; }
+        };
+        match &world.objects[id] {
+            Some(GameObject { behaviour: Behaviour::Static, editable: true, .. }) => { Some(id) }
+            _ => { None }
+        }
+    }
+
+    fn gizmo_length(transform: &Transform, camera_position: &glm::TVec3<f32>) -> f32 {
+        glm::distance(&transform.position, camera_position) * Self::GIZMO_SCREEN_SIZE
+    }
+
+    //Translation and rotation happen along the world axes, but scaling happens along the object's own axes
+    fn gizmo_axes(&self, transform: &Transform) -> [glm::TVec3<f32>; 3] {
+        let mut axes = [glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, 1.0)];
+        if self.gizmo_mode == GizmoMode::Scale {
+            for axis in axes.iter_mut() {
+                *axis = glm::quat_rotate_vec3(&transform.rotation, axis);
+            }
+        }
+        axes
+    }
+
+    //Returns the index of the gizmo handle under the ray, if any
+    fn pick_handle(&self, transform: &Transform, camera_position: &glm::TVec3<f32>, origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>) -> Option<usize> {
+        let length = Self::gizmo_length(transform, camera_position);
+        let axes = self.gizmo_axes(transform);
+        let mut closest = None;
+        let mut closest_distance = length * Self::HANDLE_THICKNESS;
+        for i in 0..axes.len() {
+            if let Some((s, t)) = closest_approach(&transform.position, &axes[i], origin, direction) {
+                if s < 0.0 || s > length || t < 0.0 {
+                    continue;
+                }
+                let distance = glm::distance(&(transform.position + axes[i] * s), &(origin + direction * t));
+                if distance < closest_distance {
+                    closest_distance = distance;
+                    closest = Some(i);
+                }
+            }
+        }
+        closest
+    }
+
+    //Starts dragging a gizmo handle of the selected object. Returns false if the ray missed every handle
+    pub fn begin_drag(&mut self, world: &World, camera_position: &glm::TVec3<f32>, origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>, mouse: &glm::TVec2<f32>) -> bool {
+        let transform = match self.selected_object.and_then(|id| world.objects[id].as_ref()) {
+            Some(object) => { object.transform.clone() }
+            None => { return false; }
+        };
+
+        match self.pick_handle(&transform, camera_position, origin, direction) {
+            Some(axis) => {
+                let axes = self.gizmo_axes(&transform);
+                let start_param = match closest_approach(&transform.position, &axes[axis], origin, direction) {
+                    Some((s, _)) => { s }
+                    None => { 0.0 }
+                };
+                self.drag = Some(GizmoDrag {
+                    axis,
+                    start_transform: transform,
+                    start_param,
+                    start_mouse: *mouse
+                });
+                true
+            }
+            None => { false }
+        }
+    }
+
+    //Computes the dragged object's new transform from the current mouse ray
+    pub fn drag(&self, camera_position: &glm::TVec3<f32>, origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>, mouse: &glm::TVec2<f32>) -> Option<Transform> {
+        let drag = self.drag.as_ref()?;
+        let start = &drag.start_transform;
+        let axis = self.gizmo_axes(start)[drag.axis];
+        let mut transform = start.clone();
+        match self.gizmo_mode {
+            GizmoMode::Translate => {
+                let (s, _) = closest_approach(&start.position, &axis, origin, direction)?;
+                transform.position = start.position + axis * (s - drag.start_param);
+            }
+            GizmoMode::Rotate => {
+                let angle = (mouse.x - drag.start_mouse.x) * Self::ROTATE_SENSITIVITY;
+                transform.rotation = glm::quat_angle_axis(angle, &axis) * start.rotation;
+            }
+            GizmoMode::Scale => {
+                let (s, _) = closest_approach(&start.position, &axis, origin, direction)?;
+                let length = Self::gizmo_length(start, camera_position);
+                let factor = f32::max(1.0 + (s - drag.start_param) / length, Self::MIN_SCALE);
+                transform.scale[drag.axis] = f32::max(start.scale[drag.axis] * factor, Self::MIN_SCALE);
+            }
+        }
+        Some(transform)
+    }
+
+    //Stops dragging, returning the transform the object had before the drag started
+    pub fn end_drag(&mut self) -> Option<Transform> {
+        self.drag.take().map(|drag| drag.start_transform)
+    }
+
+    //Ends any drag in progress, recording the transform it made in the edit history
+    pub fn finish_drag(&mut self, world: &World, history: &mut EditHistory) {
+        if let (Some(before), Some(id)) = (self.end_drag(), self.selected_object) {
+            if let (Some(handle), Some(object)) = (world.handle(id), &world.objects[id]) {
+                history.push(EditCommand::Transform {
+                    handle,
+                    before,
+                    after: object.transform.clone()
+                });
+            }
+        }
+    }
+
+    //Removes the selected prop from the world
+    pub fn delete_selected(&mut self, world: &mut World, history: &mut EditHistory) {
+        self.finish_drag(world, history);
+        if let Some(id) = self.selected_object.take() {
+            if let (Some(handle), Some(object)) = (world.handle(id), world.objects[id].clone()) {
+                world.despawn(id);
+                history.push(EditCommand::Delete { handle, object });
+            }
+        }
+    }
+
+    //Draws the gizmo handles of the selected object on top of the scene
+    pub unsafe fn draw_gizmo(&self, world: &World, hud_renderer: &HudRenderer, view_projection: &glm::TMat4<f32>, camera_position: &glm::TVec3<f32>) {
+        let transform = match self.selected_object.and_then(|id| world.objects[id].as_ref()) {
+            Some(object) => { &object.transform }
+            None => { return; }
+        };
+
+        let length = Self::gizmo_length(transform, camera_position);
+        let axes = self.gizmo_axes(transform);
+        let colors = [glm::vec3(1.0, 0.2, 0.2), glm::vec3(0.2, 1.0, 0.2), glm::vec3(0.2, 0.4, 1.0)];
+        for i in 0..axes.len() {
+            let color = match &self.drag {
+                Some(drag) if drag.axis == i => { glm::vec3(1.0, 1.0, 0.2) }
+                _ => { colors[i] }
+            };
+            let quad = [HudQuad::new(0.0, -Self::HANDLE_THICKNESS / 2.0, 1.0, Self::HANDLE_THICKNESS, color, 1.0)];
+
+            //Each handle is a pair of crossed quads so that it's visible from any angle
+            let side = axes[(i + 1) % 3];
+            let up = axes[(i + 2) % 3];
+            for normal in [up, side].iter() {
+                let across = glm::cross(normal, &axes[i]);
+                let frame = glm::mat4(
+                    axes[i].x, across.x, normal.x, transform.position.x,
+                    axes[i].y, across.y, normal.y, transform.position.y,
+                    axes[i].z, across.z, normal.z, transform.position.z,
+                    0.0, 0.0, 0.0, 1.0
+                ) * glm::scaling(&glm::vec3(length, length, length));
+                hud_renderer.draw(&quad, &(view_projection * frame));
+            }
+        }
+    }
+}
+
+//Finds the parameters of the closest points between the line p + s*a and the line o + t*d
+//Both directions are expected to be normalized
+fn closest_approach(p: &glm::TVec3<f32>, a: &glm::TVec3<f32>, o: &glm::TVec3<f32>, d: &glm::TVec3<f32>) -> Option<(f32, f32)> {
+    let w = p - o;
+    let b = glm::dot(a, d);
+    let denominator = 1.0 - b * b;
+    if denominator < 0.0001 {
+        return None;
+    }
+    let s = (b * glm::dot(d, &w) - glm::dot(a, &w)) / denominator;
+    let t = glm::dot(d, &w) + s * b;
+    Some((s, t))
+}
@@ -1,5 +1,6 @@
 use strum::EnumCount;
 use xr::Posef;
+use crate::particles::{random_cone_direction, SplashSettings};
 
 pub struct Gadget {
     pub energy_remaining: f32,
@@ -12,11 +13,101 @@ impl Gadget {
     pub const MAX_ENERGY: f32 = 100.0;
 }
 
+//Shotgun tuning values
+pub const SHOTGUN_PELLET_COUNT: usize = 12;
+pub const SHOTGUN_SPREAD_RADIANS: f32 = 0.12;
+pub const SHOTGUN_RANGE: f32 = 60.0;
+pub const SHOTGUN_RECOIL: f32 = 20.0;
+pub const SHOTGUN_KNOCKBACK: f32 = 4.0;
+pub const SHOTGUN_SCARE_RADIANS: f32 = 0.35;       //Totoros inside this cone around the barrel will scatter when it's fired
+pub const SHOTGUN_IMPACT_BURST: SplashSettings = SplashSettings { count: 4, speed: 3.0, lifetime: 0.4, scale: 0.03 };
+
+//Water cannon spray tuning values
+pub const WATER_SPRAY_SPEED: f32 = 12.0;
+pub const WATER_SPRAY_RATE: f32 = 120.0;
+pub const WATER_SPRAY_LIFETIME: f32 = 1.5;
+pub const WATER_SPRAY_SCALE: f32 = 0.05;
+pub const WATER_SPRAY_RANGE: f32 = 15.0;
+pub const WATER_SPRAY_SCARE_RADIANS: f32 = 0.25;
+pub const WATER_SPLASH: SplashSettings = SplashSettings { count: 3, speed: 2.5, lifetime: 0.5, scale: 0.03 };
+
+//Glider tuning values
+pub const GLIDER_MIN_ARM_SPAN: f32 = 1.1;          //Distance in meters between the grips for the arms to count as spread
+pub const GLIDER_SINK_SPEED: f32 = 1.5;            //Fastest the player can fall while gliding
+pub const GLIDER_EFFICIENCY: f32 = 0.8;            //Fraction of the cancelled fall speed that becomes forward speed
+pub const GLIDER_MAX_SPEED: f32 = 14.0;
+pub const GLIDER_ENERGY_DRAIN: f32 = 8.0;          //Energy per second
+
 #[derive(Copy, Clone, Debug, Hash, EnumCount, PartialEq, Eq)]
 pub enum GadgetType {
     Shotgun,
     StickyHand,
-    WaterCannon
+    WaterCannon,
+    Glider
+}
+
+//Radial menu for picking a gadget. It opens around the hand while the switch button is held
+pub struct GadgetMenu {
+    pub is_open: bool,
+    pub anchor: glm::TMat4<f32>,            //Tracking space transform of the menu's center, with x pointing right and z pointing up
+    pub highlighted: GadgetType
+}
+
+impl GadgetMenu {
+    pub const RADIUS: f32 = 0.15;
+    pub const HAND_DEADZONE: f32 = 0.04;
+    pub const STICK_DEADZONE: f32 = 0.5;
+
+    pub fn new() -> Self {
+        GadgetMenu {
+            is_open: false,
+            anchor: glm::identity(),
+            highlighted: GadgetType::Shotgun
+        }
+    }
+
+    //Opens the menu centered on the hand, facing back toward the head
+    pub fn open(&mut self, hand_position: &glm::TVec3<f32>, head_forward: &glm::TVec3<f32>, current: GadgetType) {
+        let up = glm::vec3(0.0, 0.0, 1.0);
+        let mut forward = glm::vec3(head_forward.x, head_forward.y, 0.0);
+        if forward == glm::zero() {
+            forward = glm::vec3(0.0, 1.0, 0.0);
+        }
+        let forward = glm::normalize(&forward);
+        let right = glm::cross(&forward, &up);
+        self.anchor = glm::mat4(
+            right.x, forward.x, up.x, hand_position.x,
+            right.y, forward.y, up.y, hand_position.y,
+            right.z, forward.z, up.z, hand_position.z,
+            0.0, 0.0, 0.0, 1.0
+        );
+        self.highlighted = current;
+        self.is_open = true;
+    }
+
+    //Position of a gadget's slot relative to the menu's center
+    pub fn slot_offset(i: usize) -> glm::TVec3<f32> {
+        let angle = glm::half_pi::<f32>() - i as f32 * glm::two_pi::<f32>() / GadgetType::COUNT as f32;
+        glm::vec3(f32::cos(angle), 0.0, f32::sin(angle)) * Self::RADIUS
+    }
+
+    //Takes a tracking space position and returns its offset in the plane of the menu
+    pub fn local_offset(&self, position: &glm::TVec3<f32>) -> glm::TVec2<f32> {
+        let local = glm::affine_inverse(self.anchor) * glm::vec4(position.x, position.y, position.z, 1.0);
+        glm::vec2(local.x, local.z)
+    }
+
+    //Highlights the slot closest to the given direction in the plane of the menu
+    pub fn highlight_from_direction(&mut self, direction: &glm::TVec2<f32>, deadzone: f32) {
+        if glm::length(direction) < deadzone {
+            return;
+        }
+
+        let slot_angle = glm::two_pi::<f32>() / GadgetType::COUNT as f32;
+        let angle = (glm::half_pi::<f32>() - f32::atan2(direction.y, direction.x)).rem_euclid(glm::two_pi());
+        let i = f32::round(angle / slot_angle) as usize % GadgetType::COUNT;
+        self.highlighted = GadgetType::from_usize(i);
+    }
 }
 
 impl GadgetType {
@@ -26,7 +117,28 @@ impl GadgetType {
             0 => { GadgetType::Shotgun }
             1 => { GadgetType::StickyHand }
             2 => { GadgetType::WaterCannon }
+            3 => { GadgetType::Glider }
             _ => { panic!("{} is out of range", i); }
         }
     }
-}
\ No newline at end of file
+
+    //Each gadget that's held in the hand has its own model
+    pub fn model_path(self) -> Option<&'static str> {
+        match self {
+            GadgetType::Shotgun => { Some("models/wand.ozy") }
+            GadgetType::StickyHand => { None }
+            GadgetType::WaterCannon => { Some("models/stick.ozy") }
+            GadgetType::Glider => { Some("models/glider.ozy") }
+        }
+    }
+}
+
+//Returns a set of unit vectors randomly spread within a cone around the forward direction
+pub fn shotgun_pellet_directions(forward: &glm::TVec3<f32>, count: usize, spread_radians: f32) -> Vec<glm::TVec3<f32>> {
+    let mut rng = rand::thread_rng();
+    let mut directions = Vec::with_capacity(count);
+    for _ in 0..count {
+        directions.push(random_cone_direction(&mut rng, forward, spread_radians));
+    }
+    directions
+}
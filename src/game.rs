@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use ozy::collision::*;
+use crate::ecs::{Behaviour, GameObject, ObjectHandle, RenderMesh, SphereCollider, Transform, World};
+use crate::particles::SplashSettings;
+use crate::totoro::Totoro;
+
+pub const COLLECT_BURST: SplashSettings = SplashSettings {
+    count: 48,
+    speed: 6.0,
+    lifetime: 0.8,
+    scale: 0.08
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameState {
+    Inactive,
+    Running { start_time: f32 },
+    Finished { time: f32, previous_best: Option<f32> }
+}
+
+//The fastest completion time of the collection mode for each level
+pub struct BestTimes {
+    times: HashMap<String, f32>
+}
+
+impl BestTimes {
+    pub const FILEPATH: &'static str = "best_times.txt";
+
+    //Each line of the file is a level name followed by a time in seconds
+    pub fn load() -> Self {
+        let mut times = HashMap::new();
+        if let Ok(file) = File::open(Self::FILEPATH) {
+            for line in BufReader::new(file).lines() {
+                if let Ok(line) = line {
+                    let mut tokens = line.split_whitespace();
+                    if let (Some(level), Some(Ok(time))) = (tokens.next(), tokens.next().map(|t| t.parse::<f32>())) {
+                        times.insert(String::from(level), time);
+                    }
+                }
+            }
+        }
+        BestTimes { times }
+    }
+
+    pub fn get(&self, level: &str) -> Option<f32> {
+        self.times.get(level).copied()
+    }
+
+    //Records a completion time, saving it if it beats the level's best. Returns the previous best
+    pub fn record(&mut self, level: &str, time: f32) -> Option<f32> {
+        let previous = self.get(level);
+        let is_best = match previous {
+            Some(best) => { time < best }
+            None => { true }
+        };
+        if is_best {
+            self.times.insert(String::from(level), time);
+            if let Err(e) = self.save() {
+                println!("Error saving best times: {}", e);
+            }
+        }
+        previous
+    }
+
+    fn save(&self) -> Result<(), std::io::Error> {
+        let mut file = File::create(Self::FILEPATH)?;
+        for (level, time) in self.times.iter() {
+            writeln!(file, "{} {}", level, time)?;
+        }
+        Ok(())
+    }
+}
+
+//Game mode where the player races to touch every Totoro in the level
+pub struct CollectionGame {
+    pub state: GameState,
+    pub collected: usize,
+    pub total: usize,
+    pub best_times: BestTimes,
+    collectibles: Vec<ObjectHandle>         //Handles of the Totoros that haven't been collected yet
+}
+
+impl CollectionGame {
+    pub fn new() -> Self {
+        CollectionGame {
+            state: GameState::Inactive,
+            collected: 0,
+            total: 0,
+            best_times: BestTimes::load(),
+            collectibles: Vec::new()
+        }
+    }
+
+    //Places a Totoro at each of the spawn points and starts the timer
+    pub fn start(&mut self, world: &mut World, spawn_points: &[glm::TVec3<f32>], totoro_entity_index: usize, elapsed_time: f32) {
+        self.stop(world);
+        if spawn_points.len() == 0 {
+            println!("This level doesn't have any Totoro spots to collect from");
+            return;
+        }
+
+        for point in spawn_points.iter() {
+            let id = world.spawn(GameObject {
+                transform: Transform::from_position(*point),
+                mesh: Some(RenderMesh::new(totoro_entity_index)),
+                collider: Some(SphereCollider {
+                    offset: glm::vec3(0.0, 0.0, Totoro::HIT_RADIUS),
+                    radius: Totoro::HIT_RADIUS
+                }),
+                audio_emitter: None,
+                behaviour: Behaviour::Totoro(Totoro::new(*point, elapsed_time)),
+                editable: false
+            });
+            if let Some(handle) = world.handle(id) {
+                self.collectibles.push(handle);
+            }
+        }
+        self.collected = 0;
+        self.total = spawn_points.len();
+        self.state = GameState::Running { start_time: elapsed_time };
+    }
+
+    //Ends the run early, removing any Totoros that are left
+    pub fn stop(&mut self, world: &mut World) {
+        for handle in self.collectibles.drain(..) {
+            //Totoros that were already removed might have had their slots taken by something else
+            if let Some(id) = world.resolve(handle) {
+                world.despawn(id);
+            }
+        }
+        self.state = GameState::Inactive;
+    }
+
+    pub fn run_time(&self, elapsed_time: f32) -> f32 {
+        match self.state {
+            GameState::Inactive => { 0.0 }
+            GameState::Running { start_time } => { elapsed_time - start_time }
+            GameState::Finished { time, .. } => { time }
+        }
+    }
+
+    //Collects every Totoro touching the player's capsule, returning the positions they were collected at
+    pub fn update(&mut self, world: &mut World, player_capsule: &Capsule, level_name: &str, elapsed_time: f32) -> Vec<glm::TVec3<f32>> {
+        let start_time = match self.state {
+            GameState::Running { start_time } => { start_time }
+            _ => { return Vec::new(); }
+        };
+
+        let mut collected_positions = Vec::new();
+        let mut i = 0;
+        while i < self.collectibles.len() {
+            let id = world.resolve(self.collectibles[i]);
+            let sphere = match id.and_then(|id| world.objects[id].as_ref()) {
+                Some(object) => { object.collision_sphere() }
+                None => { None }
+            };
+            match (id, sphere) {
+                (Some(id), Some(sphere)) => {
+                    let closest = closest_point_on_line_segment(&sphere.focus, &player_capsule.segment.p0, &player_capsule.segment.p1);
+                    if glm::distance(&closest, &sphere.focus) < sphere.radius + player_capsule.radius {
+                        collected_positions.push(sphere.focus);
+                        world.despawn(id);
+                        self.collectibles.swap_remove(i);
+                        self.collected += 1;
+                    } else {
+                        i += 1;
+                    }
+                }
+                _ => {
+                    //The Totoro was removed by something else, so it can't be collected any more
+                    self.collectibles.swap_remove(i);
+                }
+            }
+        }
+
+        //Only a run where every Totoro was collected counts towards the best time
+        if self.collectibles.len() == 0 {
+            if self.total > 0 && self.collected == self.total {
+                let time = elapsed_time - start_time;
+                let previous_best = self.best_times.record(level_name, time);
+                self.state = GameState::Finished { time, previous_best };
+            } else {
+                println!("Some of the Totoros were removed before they were collected, so the run doesn't count");
+                self.state = GameState::Inactive;
+            }
+        }
+        collected_positions
+    }
+}
@@ -0,0 +1,75 @@
+use crate::structs::Player;
+use crate::volume::Volume;
+
+//A level-authored region that hurts the player
+#[derive(Clone, Debug)]
+pub struct DamageVolume {
+    pub volume: Volume,
+    pub damage: f32                 //Dealt each time the player's invulnerability window runs out
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LifeState {
+    Alive,
+    Dying { since: f32 },
+    Respawning { since: f32 }
+}
+
+//Drives the fade to black and back when the player dies
+pub struct DeathSequence {
+    pub state: LifeState
+}
+
+impl DeathSequence {
+    pub const FADE_OUT_DURATION: f32 = 1.5;
+    pub const FADE_IN_DURATION: f32 = 1.0;
+    const DAMAGE_FLASH_ALPHA: f32 = 0.35;
+
+    pub fn new() -> Self {
+        DeathSequence {
+            state: LifeState::Alive
+        }
+    }
+
+    //Advances the sequence, returning true on the frame the player should be respawned
+    pub fn update(&mut self, player: &Player, elapsed_time: f32) -> bool {
+        let mut respawn = false;
+        self.state = match self.state {
+            LifeState::Alive => {
+                if player.is_dead() { LifeState::Dying { since: elapsed_time } }
+                else { self.state }
+            }
+            LifeState::Dying { since } => {
+                if elapsed_time - since > Self::FADE_OUT_DURATION {
+                    respawn = true;
+                    LifeState::Respawning { since: elapsed_time }
+                } else {
+                    self.state
+                }
+            }
+            LifeState::Respawning { since } => {
+                if elapsed_time - since > Self::FADE_IN_DURATION { LifeState::Alive }
+                else { self.state }
+            }
+        };
+        respawn
+    }
+
+    //Returns the color and opacity to cover the screen with this frame, if any
+    pub fn screen_fade(&self, player: &Player, elapsed_time: f32) -> Option<(glm::TVec3<f32>, f32)> {
+        match self.state {
+            LifeState::Alive => {
+                //Flash red while the player is invulnerable from being hurt
+                let since_damage = elapsed_time - player.last_damage_time;
+                if since_damage < Player::INVULNERABILITY_DURATION {
+                    let alpha = Self::DAMAGE_FLASH_ALPHA * (1.0 - since_damage / Player::INVULNERABILITY_DURATION);
+                    Some((glm::vec3(0.8, 0.0, 0.0), alpha))
+                } else {
+                    None
+                }
+            }
+            LifeState::Dying { since } => { Some((glm::zero(), f32::min((elapsed_time - since) / Self::FADE_OUT_DURATION, 1.0))) }
+            LifeState::Respawning { since } => { Some((glm::zero(), f32::max(1.0 - (elapsed_time - since) / Self::FADE_IN_DURATION, 0.0))) }
+        }
+    }
+}
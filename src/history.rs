@@ -0,0 +1,200 @@
+use crate::ecs::{Behaviour, GameObject, ObjectHandle, Transform, World};
+use crate::render::{RenderEntity, SceneData};
+
+//The editable material parameters of a RenderEntity
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialParams {
+    pub uv_scale: glm::TVec2<f32>,
+    pub uv_offset: glm::TVec2<f32>
+}
+
+impl MaterialParams {
+    pub fn from_entity(entity: &RenderEntity) -> Self {
+        MaterialParams {
+            uv_scale: entity.uv_scale,
+            uv_offset: entity.uv_offset
+        }
+    }
+
+    pub fn apply(&self, entity: &mut RenderEntity) {
+        entity.uv_scale = self.uv_scale;
+        entity.uv_offset = self.uv_offset;
+    }
+}
+
+//A reversible edit to the scene
+//Objects are referred to by handle, so a slot that something else has reused in the meantime is never touched
+pub enum EditCommand {
+    Spawn { handle: ObjectHandle, object: GameObject },
+    Delete { handle: ObjectHandle, object: GameObject },
+    Transform { handle: ObjectHandle, before: Transform, after: Transform },
+    Material { entity_index: usize, before: MaterialParams, after: MaterialParams }
+}
+
+impl EditCommand {
+    pub fn description(&self) -> String {
+        fn object_name(object: &GameObject) -> &'static str {
+            match object.behaviour {
+                Behaviour::Static => { "prop" }
+                Behaviour::Totoro(_) => { "Totoro" }
+                Behaviour::Chicken(_) => { "chicken" }
+                Behaviour::HitMarker(_) => { "hit marker" }
+            }
+        }
+
+        match self {
+            EditCommand::Spawn { handle, object } => { format!("Spawn {} #{}", object_name(object), handle.id) }
+            EditCommand::Delete { handle, object } => { format!("Delete {} #{}", object_name(object), handle.id) }
+            EditCommand::Transform { handle, .. } => { format!("Transform object #{}", handle.id) }
+            EditCommand::Material { entity_index, .. } => { format!("Edit material of entity #{}", entity_index) }
+        }
+    }
+
+    fn handle_mut(&mut self) -> Option<&mut ObjectHandle> {
+        match self {
+            EditCommand::Spawn { handle, .. } => { Some(handle) }
+            EditCommand::Delete { handle, .. } => { Some(handle) }
+            EditCommand::Transform { handle, .. } => { Some(handle) }
+            EditCommand::Material { .. } => { None }
+        }
+    }
+
+    //Brings a removed object back, returning its old and new handles so the rest of the history can follow it
+    fn restore(world: &mut World, handle: ObjectHandle, object: &GameObject) -> Option<(ObjectHandle, ObjectHandle)> {
+        let id = world.restore(handle.id, object.clone());
+        if id != handle.id {
+            println!("Slot #{} has been reused, so the object was restored as #{}", handle.id, id);
+        }
+        world.handle(id).map(|restored| (handle, restored))
+    }
+
+    fn remove(world: &mut World, handle: ObjectHandle) {
+        if let Some(id) = world.resolve(handle) {
+            world.despawn(id);
+        }
+    }
+
+    fn undo(&mut self, world: &mut World, scene_data: &mut SceneData) -> Option<(ObjectHandle, ObjectHandle)> {
+        match self {
+            EditCommand::Spawn { handle, object } => {
+                //Remember the object as it is now so that redoing brings back the same state
+                if let Some(id) = world.resolve(*handle) {
+                    if let Some(current) = &world.objects[id] {
+                        *object = current.clone();
+                    }
+                }
+                Self::remove(world, *handle);
+                None
+            }
+            EditCommand::Delete { handle, object } => { Self::restore(world, *handle, object) }
+            EditCommand::Transform { handle, before, .. } => {
+                if let Some(id) = world.resolve(*handle) {
+                    world.set_transform(id, before.clone(), scene_data);
+                }
+                None
+            }
+            EditCommand::Material { entity_index, before, .. } => {
+                if let Some(entity) = scene_data.entities.get_mut_element(*entity_index) {
+                    before.apply(entity);
+                }
+                None
+            }
+        }
+    }
+
+    fn redo(&mut self, world: &mut World, scene_data: &mut SceneData) -> Option<(ObjectHandle, ObjectHandle)> {
+        match self {
+            EditCommand::Spawn { handle, object } => { Self::restore(world, *handle, object) }
+            EditCommand::Delete { handle, .. } => {
+                Self::remove(world, *handle);
+                None
+            }
+            EditCommand::Transform { handle, after, .. } => {
+                if let Some(id) = world.resolve(*handle) {
+                    world.set_transform(id, after.clone(), scene_data);
+                }
+                None
+            }
+            EditCommand::Material { entity_index, after, .. } => {
+                if let Some(entity) = scene_data.entities.get_mut_element(*entity_index) {
+                    after.apply(entity);
+                }
+                None
+            }
+        }
+    }
+}
+
+//Linear undo/redo stack of scene edits
+pub struct EditHistory {
+    commands: Vec<EditCommand>,
+    position: usize                 //Number of commands currently applied
+}
+
+impl EditHistory {
+    pub const MAX_COMMANDS: usize = 128;
+
+    pub fn new() -> Self {
+        EditHistory {
+            commands: Vec::with_capacity(Self::MAX_COMMANDS),
+            position: 0
+        }
+    }
+
+    pub fn commands(&self) -> &[EditCommand] {
+        &self.commands
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    //Records a command that has already been applied, discarding anything that could have been redone
+    pub fn push(&mut self, command: EditCommand) {
+        self.commands.truncate(self.position);
+        if self.commands.len() == Self::MAX_COMMANDS {
+            self.commands.remove(0);
+        }
+        self.commands.push(command);
+        self.position = self.commands.len();
+    }
+
+    pub fn undo(&mut self, world: &mut World, scene_data: &mut SceneData) {
+        if self.position > 0 {
+            self.position -= 1;
+            let restored = self.commands[self.position].undo(world, scene_data);
+            self.follow(restored);
+        }
+    }
+
+    pub fn redo(&mut self, world: &mut World, scene_data: &mut SceneData) {
+        if self.position < self.commands.len() {
+            let restored = self.commands[self.position].redo(world, scene_data);
+            self.follow(restored);
+            self.position += 1;
+        }
+    }
+
+    //Points every command about a restored object at its new handle
+    fn follow(&mut self, restored: Option<(ObjectHandle, ObjectHandle)>) {
+        if let Some((old, new)) = restored {
+            for command in self.commands.iter_mut() {
+                if let Some(handle) = command.handle_mut() {
+                    if *handle == old {
+                        *handle = new;
+                    }
+                }
+            }
+        }
+    }
+
+    //Undoes or redoes commands until exactly the given number of them are applied
+    pub fn jump_to(&mut self, position: usize, world: &mut World, scene_data: &mut SceneData) {
+        while self.position > position {
+            self.undo(world, scene_data);
+        }
+        while self.position < usize::min(position, self.commands.len()) {
+            self.redo(world, scene_data);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::ptr;
+use ozy::glutil;
+use gl::types::*;
+use strum::EnumCount;
+use crate::gadget::{Gadget, GadgetType};
+use crate::structs::Player;
+
+//HUD layouts are expressed in a unit space where the panel spans [0, 1] horizontally and [0, PANEL_HEIGHT] vertically, with y pointing up
+pub const PANEL_HEIGHT: f32 = 0.7;
+pub const LOW_ENERGY_FRACTION: f32 = 0.25;
+const SPEED_DISPLAY_MAX: f32 = 20.0;
+
+//A solid colored rectangle in HUD space
+pub struct HudQuad {
+    pub position: glm::TVec2<f32>,
+    pub size: glm::TVec2<f32>,
+    pub color: glm::TVec3<f32>,
+    pub alpha: f32
+}
+
+impl HudQuad {
+    pub fn new(x: f32, y: f32, width: f32, height: f32, color: glm::TVec3<f32>, alpha: f32) -> Self {
+        HudQuad {
+            position: glm::vec2(x, y),
+            size: glm::vec2(width, height),
+            color,
+            alpha
+        }
+    }
+}
+
+//Everything the status HUD displays
+pub struct PlayerStatus<'a> {
+    pub gadget_energy: &'a [f32],
+    pub active_gadgets: [GadgetType; 2],
+    pub jumps_remaining: usize,
+    pub health: f32,
+    pub speed: f32,
+    pub collection_progress: Option<(usize, usize)>     //Totoros collected out of the total, while a collection run is going
+}
+
+pub struct HudRenderer {
+    pub vao: GLuint,
+    pub program: GLuint
+}
+
+impl HudRenderer {
+    pub fn new(program: GLuint) -> Self {
+        let vertices = [
+            0.0f32, 0.0,
+            1.0, 0.0,
+            1.0, 1.0,
+            0.0, 1.0
+        ];
+        let indices = [0u16, 1, 2, 0, 2, 3];
+        let vao = glutil::create_vertex_array_object(&vertices, &indices, &[2]);
+        HudRenderer {
+            vao,
+            program
+        }
+    }
+
+    //Draws the quads with the given transform from HUD space to clip space
+    pub unsafe fn draw(&self, quads: &[HudQuad], clipping_from_hud: &glm::TMat4<f32>) {
+        gl::UseProgram(self.program);
+        gl::BindVertexArray(self.vao);
+        for quad in quads.iter() {
+            let transform = clipping_from_hud *
+                            glm::translation(&glm::vec3(quad.position.x, quad.position.y, 0.0)) *
+                            glm::scaling(&glm::vec3(quad.size.x, quad.size.y, 1.0));
+            glutil::bind_matrix4(self.program, "transform", &transform);
+            glutil::bind_vector3(self.program, "color", &quad.color);
+            glutil::bind_float(self.program, "alpha", quad.alpha);
+            gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_SHORT, ptr::null());
+        }
+    }
+
+    //Covers the whole viewport with a color
+    pub unsafe fn draw_fade(&self, color: &glm::TVec3<f32>, alpha: f32) {
+        let clipping_from_hud = glm::translation(&glm::vec3(-1.0, -1.0, 0.0)) * glm::scaling(&glm::vec3(2.0, 2.0, 1.0));
+        self.draw(&[HudQuad::new(0.0, 0.0, 1.0, 1.0, *color, alpha)], &clipping_from_hud);
+    }
+}
+
+//Lays out the player status panel: one energy bar per gadget, a speed bar, a pip per remaining jump, a health bar, and the collection progress
+pub fn status_quads(status: &PlayerStatus, elapsed_time: f32) -> Vec<HudQuad> {
+    let mut quads = Vec::with_capacity(2 * GadgetType::COUNT + Player::MAX_JUMPS + 8);
+    let flash_on = f32::sin(elapsed_time * 10.0) > 0.0;
+
+    //Background panel
+    quads.push(HudQuad::new(0.0, 0.0, 1.0, PANEL_HEIGHT, glm::vec3(0.0, 0.0, 0.0), 0.5));
+
+    //Energy bars
+    for i in 0..GadgetType::COUNT {
+        let y = 0.55 - i as f32 * 0.1;
+        let gadget = GadgetType::from_usize(i);
+        let fraction = f32::max(0.0, f32::min(status.gadget_energy[i] / Gadget::MAX_ENERGY, 1.0));
+        let is_active = status.active_gadgets.contains(&gadget);
+
+        //Outline the gadgets that are currently in the player's hands
+        if is_active {
+            quads.push(HudQuad::new(0.04, y - 0.01, 0.92, 0.09, glm::vec3(1.0, 1.0, 1.0), 0.8));
+        }
+        quads.push(HudQuad::new(0.05, y, 0.9, 0.07, glm::vec3(0.15, 0.15, 0.15), 1.0));
+
+        let fill_color = if is_active && fraction < LOW_ENERGY_FRACTION && flash_on {
+            glm::vec3(1.0, 0.1, 0.1)
+        } else {
+            gadget_color(gadget)
+        };
+        quads.push(HudQuad::new(0.05, y, 0.9 * fraction, 0.07, fill_color, 1.0));
+    }
+
+    //Collection progress along the top edge
+    if let Some((collected, total)) = status.collection_progress {
+        let fraction = if total > 0 { collected as f32 / total as f32 } else { 0.0 };
+        quads.push(HudQuad::new(0.05, 0.635, 0.9, 0.04, glm::vec3(0.15, 0.15, 0.15), 1.0));
+        quads.push(HudQuad::new(0.05, 0.635, 0.9 * fraction, 0.04, glm::vec3(0.6, 0.6, 0.65), 1.0));
+    }
+
+    //Speed bar
+    let speed_fraction = f32::min(status.speed / SPEED_DISPLAY_MAX, 1.0);
+    quads.push(HudQuad::new(0.05, 0.14, 0.9, 0.05, glm::vec3(0.15, 0.15, 0.15), 1.0));
+    quads.push(HudQuad::new(0.05, 0.14, 0.9 * speed_fraction, 0.05, glm::vec3(1.0, 1.0, 1.0), 1.0));
+
+    //Health bar, next to the jump pips
+    let health_fraction = f32::max(0.0, f32::min(status.health / Player::MAX_HEALTH, 1.0));
+    quads.push(HudQuad::new(0.4, 0.03, 0.55, 0.07, glm::vec3(0.15, 0.15, 0.15), 1.0));
+    quads.push(HudQuad::new(0.4, 0.03, 0.55 * health_fraction, 0.07, glm::vec3(0.9, 0.15, 0.2), 1.0));
+
+    //Jump pips
+    for i in 0..Player::MAX_JUMPS {
+        let color = if i < status.jumps_remaining { glm::vec3(1.0, 0.85, 0.1) } else { glm::vec3(0.3, 0.3, 0.3) };
+        quads.push(HudQuad::new(0.05 + i as f32 * 0.12, 0.03, 0.09, 0.07, color, 1.0));
+    }
+
+    quads
+}
+
+fn gadget_color(gadget: GadgetType) -> glm::TVec3<f32> {
+    match gadget {
+        GadgetType::Shotgun => { glm::vec3(1.0, 0.55, 0.1) }
+        GadgetType::StickyHand => { glm::vec3(0.8, 0.2, 0.8) }
+        GadgetType::WaterCannon => { glm::vec3(0.2, 0.5, 1.0) }
+        GadgetType::Glider => { glm::vec3(0.3, 0.9, 0.4) }
+    }
+}
@@ -0,0 +1,303 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use gl::types::*;
+use ozy::io;
+use ozy::render::TextureKeeper;
+use crate::ecs::{Behaviour, GameObject, RenderMesh, Transform, World};
+use crate::health::DamageVolume;
+use crate::render::{RenderEntity, SceneData};
+use crate::reverb::{ReverbPreset, ReverbZone};
+use crate::trigger::{Trigger, TriggerAction};
+use crate::volume::Volume;
+
+//A model used by the level, along with the RenderEntity that draws all of its instances
+pub struct LevelMesh {
+    pub ozy_name: String,
+    pub entity_index: usize
+}
+
+impl LevelMesh {
+    //The ground is exported as meshes named <level>_<material>_terrain.ozy, which the prop editor leaves alone
+    pub fn is_terrain(&self) -> bool {
+        self.ozy_name.ends_with("_terrain.ozy")
+    }
+}
+
+//The level's static geometry. Each instance of a level mesh is a static object in the world
+//
+//The .lvl format is a sequence of blocks, each of which is:
+//  u32 length + bytes: the name of the .ozy file in models/
+//  u32: the number of instances
+//  16 f32s per instance: the column-major model matrix
+//
+//Gameplay data lives in a text sidecar next to it, with one entry per line:
+//  totoro x y z    A spot where a Totoro is placed in the collection game mode
+//  nest x y z      A chicken nest
+//  damage <volume> amount      A region that hurts the player, where <volume> is one of:
+//      box cx cy cz hx hy hz
+//      sphere cx cy cz r
+//  trigger <volume>            A region that fires events when the player enters or leaves it
+//  enter <action>              An action fired when the player enters the trigger declared above
+//  exit <action>               An action fired when the player leaves the trigger declared above
+//  music path                  A track for the level's playlist, used instead of everything in music/
+//  reverb preset <volume> size wet     A region that gives sounds a reverb, where preset is room, hall, cave, forest or plain
+//
+//Levels saved before the gameplay sidecar existed have a .spots file instead, with a bare "x y z" Totoro spot per line
+pub struct Level {
+    pub name: String,
+    pub meshes: Vec<LevelMesh>,
+    pub totoro_spots: Vec<glm::TVec3<f32>>,
+    pub nest_spots: Vec<glm::TVec3<f32>>,
+    pub damage_volumes: Vec<DamageVolume>,
+    pub triggers: Vec<Trigger>,
+    pub music: Vec<String>,
+    pub reverb_zones: Vec<ReverbZone>
+}
+
+impl Level {
+    pub fn path(name: &str) -> String {
+        format!("maps/{}.lvl", name)
+    }
+
+    pub fn gameplay_path(name: &str) -> String {
+        format!("maps/{}.gameplay", name)
+    }
+
+    pub fn spots_path(name: &str) -> String {
+        format!("maps/{}.spots", name)
+    }
+
+    pub fn load(name: &str, world: &mut World, scene_data: &mut SceneData, program: GLuint, texture_keeper: &mut TextureKeeper, tex_params: &[(GLenum, GLenum)]) -> Result<Self, std::io::Error> {
+        let mut level = Level {
+            name: String::from(name),
+            meshes: Vec::new(),
+            totoro_spots: Vec::new(),
+            nest_spots: Vec::new(),
+            damage_volumes: Vec::new(),
+            triggers: Vec::new(),
+            music: Vec::new(),
+            reverb_zones: Vec::new()
+        };
+
+        //The gameplay file is optional
+        if let Ok(gameplay_file) = File::open(&Self::gameplay_path(name)) {
+            for line in BufReader::new(gameplay_file).lines() {
+                let line = match line {
+                    Ok(line) => { line }
+                    Err(e) => {
+                        //A line that isn't valid UTF-8 has still been consumed, but any other error would just repeat
+                        println!("Skipping unreadable line in {}: {}", Self::gameplay_path(name), e);
+                        if e.kind() == ErrorKind::InvalidData {
+                            continue;
+                        }
+                        break;
+                    }
+                };
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                if tokens.len() == 0 {
+                    continue;
+                }
+
+                let parsed = match (tokens[0], parse_numbers(&tokens[1..])) {
+                    ("totoro", Some(numbers)) if numbers.len() == 3 => { level.totoro_spots.push(glm::vec3(numbers[0], numbers[1], numbers[2])); true }
+                    ("nest", Some(numbers)) if numbers.len() == 3 => { level.nest_spots.push(glm::vec3(numbers[0], numbers[1], numbers[2])); true }
+                    ("damage", _) => {
+                        match parse_volume(&tokens[1..]) {
+                            Some((volume, rest)) if rest.len() == 1 => { level.damage_volumes.push(DamageVolume { volume, damage: rest[0] }); true }
+                            _ => { false }
+                        }
+                    }
+                    ("trigger", _) => {
+                        match parse_volume(&tokens[1..]) {
+                            Some((volume, rest)) if rest.len() == 0 => { level.triggers.push(Trigger::new(volume)); true }
+                            _ => { false }
+                        }
+                    }
+                    ("music", _) if tokens.len() == 2 => { level.music.push(String::from(tokens[1])); true }
+                    ("reverb", _) if tokens.len() > 2 => {
+                        match (ReverbPreset::from_name(tokens[1]), parse_volume(&tokens[2..])) {
+                            (Some(preset), Some((volume, rest))) if rest.len() == 2 => {
+                                level.reverb_zones.push(ReverbZone { volume, preset, size: rest[0], wet: rest[1] });
+                                true
+                            }
+                            _ => { false }
+                        }
+                    }
+                    ("enter", _) | ("exit", _) => {
+                        match (level.triggers.last_mut(), TriggerAction::parse(&tokens[1..])) {
+                            (Some(trigger), Some(action)) => {
+                                if tokens[0] == "enter" { trigger.on_enter.push(action); }
+                                else { trigger.on_exit.push(action); }
+                                true
+                            }
+                            _ => { false }
+                        }
+                    }
+                    _ => { false }
+                };
+                if !parsed {
+                    println!("Skipping malformed line in {}: \"{}\"", Self::gameplay_path(name), line);
+                }
+            }
+        } else if let Ok(spots_file) = File::open(&Self::spots_path(name)) {
+            for line in BufReader::new(spots_file).lines() {
+                let line = match line {
+                    Ok(line) => { line }
+                    Err(e) => {
+                        println!("Skipping unreadable line in {}: {}", Self::spots_path(name), e);
+                        if e.kind() == ErrorKind::InvalidData {
+                            continue;
+                        }
+                        break;
+                    }
+                };
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                match parse_numbers(&tokens) {
+                    Some(numbers) if numbers.len() == 3 => { level.totoro_spots.push(glm::vec3(numbers[0], numbers[1], numbers[2])); }
+                    _ if tokens.len() == 0 => {}
+                    _ => { println!("Skipping malformed line in {}: \"{}\"", Self::spots_path(name), line); }
+                }
+            }
+        }
+
+        let mut file = File::open(&Self::path(name))?;
+        loop {
+            //Read ozy name
+            let ozy_name = match io::read_pascal_strings(&mut file, 1) {
+                Ok(v) => { v[0].clone() }
+                Err(e) => {
+                    //We expect this call to eventually return EOF
+                    if e.kind() == ErrorKind::UnexpectedEof {
+                        break;
+                    }
+                    return Err(e);
+                }
+            };
+
+            //Read number of matrices
+            let matrices_count = io::read_u32(&mut file)? as usize;
+            let matrix_floats = io::read_f32_data(&mut file, matrices_count * 16)?;
+
+            let entity_index = level.mesh_index(&ozy_name, scene_data, world, program, texture_keeper, tex_params);
+            let editable = !level.meshes.iter().any(|mesh| mesh.entity_index == entity_index && mesh.is_terrain());
+            for i in 0..matrices_count {
+                let matrix = glm::make_mat4(&matrix_floats[(16 * i)..(16 * (i + 1))]);
+                world.spawn(GameObject {
+                    mesh: Some(RenderMesh::new(entity_index)),
+                    editable,
+                    ..GameObject::new(decompose_matrix(&matrix))
+                });
+            }
+        }
+
+        Ok(level)
+    }
+
+    //Returns the index of the RenderEntity for the given model, loading it if the level isn't using it yet
+    pub fn mesh_index(&mut self, ozy_name: &str, scene_data: &mut SceneData, world: &mut World, program: GLuint, texture_keeper: &mut TextureKeeper, tex_params: &[(GLenum, GLenum)]) -> usize {
+        for mesh in self.meshes.iter() {
+            if mesh.ozy_name == ozy_name {
+                return mesh.entity_index;
+            }
+        }
+
+        let entity = RenderEntity::from_ozy(&format!("models/{}", ozy_name), program, 1, texture_keeper, tex_params);
+        let entity_index = scene_data.entities.insert(entity);
+        world.manage_mesh(entity_index);
+        self.meshes.push(LevelMesh {
+            ozy_name: String::from(ozy_name),
+            entity_index
+        });
+        entity_index
+    }
+
+    //Writes every static instance of the level's meshes back out to the .lvl file, along with the gameplay file
+    pub fn save(&self, world: &World) -> Result<(), std::io::Error> {
+        let mut gameplay_file = File::create(&Self::gameplay_path(&self.name))?;
+        for spot in self.totoro_spots.iter() {
+            writeln!(gameplay_file, "totoro {} {} {}", spot.x, spot.y, spot.z)?;
+        }
+        for spot in self.nest_spots.iter() {
+            writeln!(gameplay_file, "nest {} {} {}", spot.x, spot.y, spot.z)?;
+        }
+        for damage_volume in self.damage_volumes.iter() {
+            writeln!(gameplay_file, "damage {} {}", damage_volume.volume.to_gameplay_string(), damage_volume.damage)?;
+        }
+        for track in self.music.iter() {
+            writeln!(gameplay_file, "music {}", track)?;
+        }
+        for zone in self.reverb_zones.iter() {
+            writeln!(gameplay_file, "reverb {} {} {} {}", zone.preset.name(), zone.volume.to_gameplay_string(), zone.size, zone.wet)?;
+        }
+        for trigger in self.triggers.iter() {
+            writeln!(gameplay_file, "trigger {}", trigger.volume.to_gameplay_string())?;
+            for action in trigger.on_enter.iter() {
+                writeln!(gameplay_file, "enter {}", action.to_gameplay_string())?;
+            }
+            for action in trigger.on_exit.iter() {
+                writeln!(gameplay_file, "exit {}", action.to_gameplay_string())?;
+            }
+        }
+
+        let mut file = File::create(&Self::path(&self.name))?;
+        for mesh in self.meshes.iter() {
+            let mut matrix_floats: Vec<f32> = Vec::new();
+            for opt_object in world.objects.iter() {
+                if let Some(object) = opt_object {
+                    if let (Some(render_mesh), Behaviour::Static) = (&object.mesh, &object.behaviour) {
+                        if render_mesh.entity_index == mesh.entity_index {
+                            matrix_floats.extend_from_slice(object.transform.to_matrix().as_slice());
+                        }
+                    }
+                }
+            }
+
+            //Don't bother writing out meshes that no longer have any instances
+            if matrix_floats.len() == 0 {
+                continue;
+            }
+
+            file.write_all(&(mesh.ozy_name.len() as u32).to_le_bytes())?;
+            file.write_all(mesh.ozy_name.as_bytes())?;
+            file.write_all(&((matrix_floats.len() / 16) as u32).to_le_bytes())?;
+            for f in matrix_floats.iter() {
+                file.write_all(&f.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+//Parses every token as a number, failing if any of them isn't one
+fn parse_numbers(tokens: &[&str]) -> Option<Vec<f32>> {
+    tokens.iter().map(|token| token.parse().ok()).collect()
+}
+
+//Parses a volume's shape name and numbers, returning the volume and whatever numbers follow it
+fn parse_volume(tokens: &[&str]) -> Option<(Volume, Vec<f32>)> {
+    let numbers = parse_numbers(tokens.get(1..)?)?;
+    let (volume, rest) = Volume::parse(tokens[0], &numbers)?;
+    Some((volume, rest.to_vec()))
+}
+
+//Splits a model matrix into translation, rotation, and scale. Any shear is lost
+pub fn decompose_matrix(matrix: &glm::TMat4<f32>) -> Transform {
+    let position = glm::vec3(matrix[12], matrix[13], matrix[14]);
+    let scale = glm::vec3(
+        glm::length(&glm::vec3(matrix[0], matrix[1], matrix[2])),
+        glm::length(&glm::vec3(matrix[4], matrix[5], matrix[6])),
+        glm::length(&glm::vec3(matrix[8], matrix[9], matrix[10]))
+    );
+    let rotation_matrix = glm::mat4(
+        matrix[0] / scale.x, matrix[4] / scale.y, matrix[8] / scale.z, 0.0,
+        matrix[1] / scale.x, matrix[5] / scale.y, matrix[9] / scale.z, 0.0,
+        matrix[2] / scale.x, matrix[6] / scale.y, matrix[10] / scale.z, 0.0,
+        0.0, 0.0, 0.0, 1.0
+    );
+
+    Transform {
+        position,
+        rotation: glm::to_quat(&rotation_matrix),
+        scale
+    }
+}
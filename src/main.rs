@@ -7,10 +7,29 @@ extern crate tinyfiledialogs as tfd;
 
 extern crate ozy_engine as ozy;
 
+mod analysis;
 mod audio;
+mod audio_backend;
+mod chicken;
+mod decoder;
+mod ecs;
+mod editor;
 mod gadget;
+mod game;
+mod history;
+mod health;
+mod hud;
+mod level;
+mod navigation;
+mod particles;
+mod picking;
+mod playlist;
+mod reverb;
 mod structs;
 mod render;
+mod totoro;
+mod trigger;
+mod volume;
 mod xrutil;
 
 use render::{compute_shadow_cascade_matrices, CascadedShadowMap, FragmentFlag, RenderEntity, SceneData, ViewData};
@@ -26,8 +45,6 @@ use core::ops::RangeInclusive;
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::fs;
-use std::fs::File;
-use std::io::{ErrorKind, Seek, SeekFrom};
 use std::path::Path;
 use std::process::exit;
 use std::mem::size_of;
@@ -39,15 +56,28 @@ use std::time::{Duration, Instant};
 use strum::EnumCount;
 use tfd::MessageBoxIcon;
 use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
-use ozy::{glutil, io};
+use ozy::glutil;
 use ozy::glutil::ColorSpace;
 use ozy::render::{Framebuffer, RenderTarget, ScreenState, TextureKeeper};
-use ozy::structs::OptionVec;
 use ozy::collision::*;
 
-use crate::audio::{AudioCommand};
+use crate::audio::{AudioBus, AudioCommand, AudioStatus, Mixer, SoundHandles, SoundRequest, BGM_SOURCE, CHIME_SOUND, DEFAULT_CROSSFADE, LANDING_SOUND, SHOTGUN_SOUND, TOTORO_SPAWN_SOUND, WATER_SPRAY_SOUND};
+use crate::audio_backend::BackendChoice;
+use crate::chicken::{Chicken, Nest, CHICKEN_PECK_DAMAGE, CHICKEN_PECK_ENERGY_DRAIN, CHICKEN_PECK_KNOCKBACK};
+use crate::ecs::{AudioEmitter, Behaviour, GameObject, RenderMesh, SphereCollider, Transform, World};
+use crate::editor::{GizmoMode, PropEditor};
 use crate::gadget::*;
+use crate::game::{CollectionGame, GameState, COLLECT_BURST};
+use crate::history::{EditCommand, EditHistory, MaterialParams};
+use crate::health::DeathSequence;
+use crate::hud::HudRenderer;
+use crate::level::{decompose_matrix, Level};
+use crate::particles::{ParticleEmitter, ParticleSystem};
+use crate::picking::{pick, PickMeshCache, PickTarget};
+use crate::playlist::RepeatMode;
 use crate::structs::*;
+use crate::totoro::Totoro;
+use crate::trigger::{update_triggers, SpawnKind, TriggerAction};
 
 #[cfg(windows)]
 use winapi::{um::{winuser::GetWindowDC, wingdi::wglGetCurrentContext}};
@@ -100,10 +130,27 @@ fn clamp<T: PartialOrd>(x: T, min: T, max: T) -> T {
     else { x }
 }
 
-fn write_matrix_to_buffer(buffer: &mut [f32], index: usize, matrix: glm::TMat4<f32>) {    
-    for k in 0..16 {
-        buffer[16 * index + k] = matrix[k];
-    }
+//Returns the origin and direction of a world space ray from the camera through the mouse cursor
+fn mouse_ray(screen_state: &ScreenState, camera_position: &glm::TVec3<f32>, screen_space_mouse: &glm::TVec2<f32>) -> (glm::TVec3<f32>, glm::TVec3<f32>) {
+    let fovx_radians = 2.0 * f32::atan(f32::tan(screen_state.get_fov_radians() / 2.0) * screen_state.get_aspect_ratio());
+    let max_coords = glm::vec4(
+        NEAR_DISTANCE * f32::tan(fovx_radians / 2.0),
+        NEAR_DISTANCE * f32::tan(screen_state.get_fov_radians() / 2.0),
+        -NEAR_DISTANCE,
+        1.0
+    );
+    let normalized_coords = glm::vec4(
+        screen_space_mouse.x * 2.0 / screen_state.get_window_size().x as f32 - 1.0,
+        -screen_space_mouse.y * 2.0 / screen_state.get_window_size().y as f32 + 1.0,
+        1.0,
+        1.0
+    );
+    let view_space_mouse = glm::matrix_comp_mult(&normalized_coords, &max_coords);
+    let world_space_mouse = screen_state.get_world_from_view() * view_space_mouse;
+
+    let ray_origin = glm::vec3(camera_position.x, camera_position.y, camera_position.z);
+    let mouse_ray_dir = glm::normalize(&(glm::vec4_to_vec3(&world_space_mouse) - ray_origin));
+    (ray_origin, mouse_ray_dir)
 }
 
 fn main() {
@@ -112,7 +159,7 @@ fn main() {
     //Do a bunch of OpenXR initialization
 
     //Initialize the configuration data
-    let config = {
+    let mut config = {
         //If we can't read from the config file, we create one with the default values
         match Configuration::from_file(Configuration::CONFIG_FILEPATH) {
             Some(cfg) => { cfg }
@@ -122,10 +169,13 @@ fn main() {
                 int_options.insert(String::from(Configuration::WINDOWED_WIDTH), 1280);
                 int_options.insert(String::from(Configuration::WINDOWED_HEIGHT), 720);
                 string_options.insert(String::from(Configuration::LEVEL_NAME), String::from("recreate"));
-                let c = Configuration {
+                string_options.insert(String::from(Configuration::AUDIO_BACKEND), String::from("openal"));
+                let mut c = Configuration {
                     int_options,
+                    float_options: HashMap::new(),
                     string_options
                 };
+                Mixer::new().save_to_config(&mut c);
                 c.to_file(Configuration::CONFIG_FILEPATH);
                 c
             }
@@ -592,6 +642,9 @@ fn main() {
     let shadow_program = compile_shader_or_crash("shaders/shadow.vert", "shaders/shadow.frag");
     let skybox_program = compile_shader_or_crash("shaders/skybox.vert", "shaders/skybox.frag");
     let imgui_program = compile_shader_or_crash("shaders/ui/imgui.vert", "shaders/ui/imgui.frag");
+    let hud_program = compile_shader_or_crash("shaders/ui/hud.vert", "shaders/ui/hud.frag");
+    let collision_program = compile_shader_or_crash("shaders/collision.vert", "shaders/collision.frag");
+    let hud_renderer = HudRenderer::new(hud_program);
     
     //Initialize default framebuffer
     let mut default_framebuffer = Framebuffer {
@@ -701,20 +754,42 @@ fn main() {
         movement_state: MoveState::Falling,
         radius: 0.15,
         jumps_remaining: Player::MAX_JUMPS,
-        was_holding_jump: false
+        was_holding_jump: false,
+        health: Player::MAX_HEALTH,
+        last_damage_time: -Player::INVULNERABILITY_DURATION
     };
+    let mut death_sequence = DeathSequence::new();
 
     //Water gun state
     const MAX_WATER_PRESSURE: f32 = 30.0;
     let mut water_gun_force: glm::TVec3<f32> = glm::zero();
     let mut infinite_ammo = false;
-    let mut remaining_water = Gadget::MAX_ENERGY;
+    let mut gliding = false;
+    let mut gadget_energy = [Gadget::MAX_ENERGY; GadgetType::COUNT];
 
     //Water gun graphics data
     let mut left_water_pillar_scale: glm::TVec3<f32> = glm::zero();
     let mut right_water_pillar_scale: glm::TVec3<f32> = glm::zero();
     let water_cylinder_path = "models/water_cylinder.ozy";
     let water_cylinder_entity_index = scene_data.entities.insert(RenderEntity::from_ozy(water_cylinder_path, standard_program, 2, &mut texture_keeper, &default_tex_params));
+
+    //Shotgun hit markers are drawn as flattened water cylinders at the impact points
+    let hit_marker_entity_index = scene_data.entities.insert(RenderEntity::from_ozy(water_cylinder_path, standard_program, HitMarker::MAX_COUNT, &mut texture_keeper, &default_tex_params));
+
+    //Particle system state
+    const PARTICLE_BUDGET: usize = 1024;
+    let mut particle_system = {
+        let entity_index = scene_data.entities.insert(RenderEntity::from_ozy(water_cylinder_path, standard_program, PARTICLE_BUDGET, &mut texture_keeper, &default_tex_params));
+        ParticleSystem::new(entity_index, PARTICLE_BUDGET, 256)
+    };
+    let mut water_spray_emitters = [
+        ParticleEmitter::new(WATER_SPRAY_SPEED, WATER_SPRAY_RATE, WATER_SPRAY_LIFETIME, WATER_SPRAY_SCALE),
+        ParticleEmitter::new(WATER_SPRAY_SPEED, WATER_SPRAY_RATE, WATER_SPRAY_LIFETIME, WATER_SPRAY_SCALE)
+    ];
+    for emitter in water_spray_emitters.iter_mut() {
+        emitter.collides = true;
+        emitter.splash = Some(WATER_SPLASH);
+    }
     
     //Matrices for relating tracking space and world space
     let mut world_from_tracking = glm::identity();
@@ -756,7 +831,8 @@ fn main() {
     };
     
     //Load terrain data
-    let terrain = {
+    let mut world = World::new();
+    let (mut level, terrain) = {
         let terrain_name = match config.string_options.get(Configuration::LEVEL_NAME) {
             Some(name) => { name }
             None => { "testmap" }
@@ -768,68 +844,77 @@ fn main() {
         };
 
         //Load the scene data from the level file
-        match File::open(&format!("maps/{}.lvl", terrain_name)) {
-            Ok(mut file) => {
-                loop {
-                    //Read ozy name
-                    let ozy_name = match io::read_pascal_strings(&mut file, 1) {
-                        Ok(v) => { v[0].clone() }
-                        Err(e) => {
-                            //We expect this call to eventually return EOF
-                            if e.kind() == ErrorKind::UnexpectedEof {
-                                break;
-                            }
-                            level_load_error(e)
-                        }
-                    };
-
-                    //Read number of matrices
-                    let matrices_count = match io::read_u32(&mut file) {
-                        Ok(count) => { count as usize } 
-                        Err(e) => { panic!("Error reading from level file: {}", e); }
-                    };
-                    let matrix_floats = match io::read_f32_data(&mut file, matrices_count as usize * 16) {
-                        Ok(floats) => { floats }
-                        Err(e) => { panic!("Error reading from level file: {}", e); }
-                    };
+        let level = match Level::load(terrain_name, &mut world, &mut scene_data, standard_program, &mut texture_keeper, &default_tex_params) {
+            Ok(l) => { l }
+            Err(e) => { level_load_error(e) }
+        };
 
-                    let mut entity = RenderEntity::from_ozy(&format!("models/{}", ozy_name), standard_program, matrices_count, &mut texture_keeper, &default_tex_params);
-                    entity.update_buffer(&matrix_floats);                
-                    scene_data.entities.insert(entity);
-                }                
-            }
-            Err(e) => { level_load_error(e); }
-        }
         let t = Terrain::from_ozt(&format!("models/{}.ozt", terrain_name));
         println!("Loaded {} collision triangles from {}.ozt", t.indices.len() / 3, terrain_name);
-        t
+        (level, t)
     };
 
     //Create Totoros    
-    let mut totoros: OptionVec<Totoro> = OptionVec::with_capacity(64);
     let totoro_entity_index = scene_data.entities.insert(RenderEntity::from_ozy("models/totoro.ozy", standard_program, 64, &mut texture_keeper, &default_tex_params));
+    world.manage_mesh(totoro_entity_index);
 
-    //Load gadget models
+    //Load gadget models, which are shared by the gadgets in the hands and the ones in the radial menus
     let gadget_model_map = {
-        let wand_entity = RenderEntity::from_ozy("models/wand.ozy", standard_program, 2, &mut texture_keeper, &default_tex_params);
-        let stick_entity = RenderEntity::from_ozy("models/stick.ozy", standard_program, 2, &mut texture_keeper, &default_tex_params);
         let mut h = HashMap::new();
-        h.insert(GadgetType::Shotgun, wand_entity);
-        h.insert(GadgetType::WaterCannon, stick_entity);
+        for i in 0..GadgetType::COUNT {
+            let gadget = GadgetType::from_usize(i);
+            if let Some(path) = gadget.model_path() {
+                let entity_index = scene_data.entities.insert(RenderEntity::from_ozy(path, standard_program, 4, &mut texture_keeper, &default_tex_params));
+                world.manage_mesh(entity_index);
+                h.insert(gadget, entity_index);
+            }
+        }
         h
     };
+    let gadget_mesh = |gadget: GadgetType| gadget_model_map.get(&gadget).map(|&entity_index| RenderMesh::new(entity_index));
+    let hidden_transform = Transform { scale: glm::zero(), ..Default::default() };
+
+    //Prop editor state
+    let mut prop_editor = PropEditor::new();
+    let mut edit_history = EditHistory::new();
+
+    //Totoro collection game mode
+    let mut collection_game = CollectionGame::new();
+
+    //Chickens hatch from the level's nests
+    let chicken_entity_index = scene_data.entities.insert(RenderEntity::from_ozy("models/chicken.ozy", standard_program, 64, &mut texture_keeper, &default_tex_params));
+    world.manage_mesh(chicken_entity_index);
+    let mut nests: Vec<Nest> = level.nest_spots.iter().map(|spot| Nest::new(*spot, &terrain)).collect();
+    let mut chickens_enabled = true;
 
     //Gadget state setup
+    //The gadgets in the hands and their pillars of water are world objects that follow the controllers
     let mut left_hand_gadget = GadgetType::Shotgun;
     let mut right_hand_gadget = GadgetType::Shotgun;
-    let left_gadget_index = match gadget_model_map.get(&left_hand_gadget) {
-        Some(entity) => { scene_data.entities.insert(entity.clone()) }
-        None => { panic!("No model found for {:?}", left_hand_gadget); }
-    };
-    let right_gadget_index = match gadget_model_map.get(&right_hand_gadget) {
-        Some(entity) => { scene_data.entities.insert(entity.clone()) }
-        None => { panic!("No model found for {:?}", right_hand_gadget); }
-    };
+    let gadget_objects = [
+        world.spawn(GameObject { mesh: gadget_mesh(left_hand_gadget), ..GameObject::new(Transform::default()) }),
+        world.spawn(GameObject { mesh: gadget_mesh(right_hand_gadget), ..GameObject::new(Transform::default()) })
+    ];
+    world.manage_mesh(water_cylinder_entity_index);
+    let mut water_pillar_objects = [0; 2];
+    for object in water_pillar_objects.iter_mut() {
+        *object = world.spawn(GameObject { mesh: Some(RenderMesh::new(water_cylinder_entity_index)), ..GameObject::new(hidden_transform.clone()) });
+    }
+    world.manage_mesh(hit_marker_entity_index);
+
+    //Radial gadget menu state
+    //Each hand's menu has a model and an energy bar for every gadget, which are hidden while the menu is closed
+    let mut gadget_menus = [GadgetMenu::new(), GadgetMenu::new()];
+    let menu_energy_bar_index = scene_data.entities.insert(RenderEntity::from_ozy(water_cylinder_path, standard_program, 2 * GadgetType::COUNT, &mut texture_keeper, &default_tex_params));
+    world.manage_mesh(menu_energy_bar_index);
+    let mut menu_model_objects = [Vec::with_capacity(GadgetType::COUNT), Vec::with_capacity(GadgetType::COUNT)];
+    let mut menu_bar_objects = [Vec::with_capacity(GadgetType::COUNT), Vec::with_capacity(GadgetType::COUNT)];
+    for i in 0..gadget_menus.len() {
+        for j in 0..GadgetType::COUNT {
+            menu_model_objects[i].push(world.spawn(GameObject { mesh: gadget_mesh(GadgetType::from_usize(j)), ..GameObject::new(hidden_transform.clone()) }));
+            menu_bar_objects[i].push(world.spawn(GameObject { mesh: Some(RenderMesh::new(menu_energy_bar_index)), ..GameObject::new(hidden_transform.clone()) }));
+        }
+    }
 
     //Set up global flags lol
     let mut is_fullscreen = false;
@@ -839,6 +924,9 @@ fn main() {
     let mut hmd_pov = false;
     let mut do_vsync = true;
     let mut do_imgui = true;
+    let mut show_hud = true;
+    let mut show_pick_tooltip = false;
+    let mut pick_meshes = PickMeshCache::new();
     let mut screenshot_this_frame = false;
     if let Some(_) = &xr_instance {
         hmd_pov = true;
@@ -853,9 +941,25 @@ fn main() {
     let mut elapsed_time = 0.0;
 
     //Init audio system
-    let mut bgm_volume = 20.0;
+    let mut mixer = Mixer::from_config(&config);
     let (audio_sender, audio_receiver) = mpsc::channel();
-    audio::audio_main(audio_receiver, bgm_volume);
+    let (audio_event_sender, audio_event_receiver) = mpsc::channel();
+    let audio_backend = BackendChoice::from_config_value(config.string_options.get(Configuration::AUDIO_BACKEND));
+    audio::audio_main(audio_receiver, audio_event_sender, audio_backend, mixer);
+    let mut audio_status = AudioStatus::new();
+    let mut crossfade_duration = DEFAULT_CROSSFADE;
+    let mut seek_drag = None;               //Where the seek bar is being dragged to, which is only seeked to once it's let go of
+
+    //Levels can bring their own music
+    if level.music.len() > 0 {
+        send_or_error(&audio_sender, AudioCommand::SetPlaylist(level.music.clone()));
+    }
+    send_or_error(&audio_sender, AudioCommand::SetReverbZones(level.reverb_zones.clone()));
+    let mut sound_handles = SoundHandles::new();
+
+    //Each hand's water cannon has a looping spray sound that plays while it's firing
+    let water_spray_sounds = [sound_handles.next(), sound_handles.next()];
+    let mut water_spray_sounds_playing = [false; 2];
 
     let key_directions = {
         let mut hm = HashMap::new();
@@ -903,7 +1007,7 @@ fn main() {
         for (_, event) in glfw::flush_messages(&events) {
             match event {
                 WindowEvent::Close => { window.set_should_close(true); }
-                WindowEvent::Key(key, _, Action::Press, _) => {
+                WindowEvent::Key(key, _, Action::Press, modifiers) => {
                     match key_directions.get(&key) {
                         Some(dir) => {
                             camera_input += dir;
@@ -911,6 +1015,19 @@ fn main() {
                         None => {
                             match key {
                                 Key::Escape => { do_imgui = !do_imgui; }
+                                Key::Delete => {
+                                    if click_action == ClickAction::EditingProps {
+                                        prop_editor.delete_selected(&mut world, &mut edit_history);
+                                    }
+                                }
+                                Key::Z if modifiers.contains(glfw::Modifiers::Control) => {
+                                    prop_editor.finish_drag(&world, &mut edit_history);
+                                    edit_history.undo(&mut world, &mut scene_data);
+                                }
+                                Key::Y if modifiers.contains(glfw::Modifiers::Control) => {
+                                    prop_editor.finish_drag(&world, &mut edit_history);
+                                    edit_history.redo(&mut world, &mut scene_data);
+                                }
                                 Key::LeftShift => {
                                     camera_speed *= 5.0;
                                 }
@@ -981,6 +1098,11 @@ fn main() {
                 WindowEvent::Scroll(x, y) => {
                     imgui_io.mouse_wheel_h = x as f32;
                     imgui_io.mouse_wheel = y as f32;
+
+                    //Scrolling spins the prop preview
+                    if click_action == ClickAction::PlacingProp && !imgui_io.want_capture_mouse {
+                        prop_editor.placement_rotation += y as f32 * glm::radians(&glm::vec1(15.0)).x;
+                    }
                 }
                 WindowEvent::FramebufferSize(width, height) => {
                     imgui_io.display_size[0] = width as f32;
@@ -1001,7 +1123,8 @@ fn main() {
             const DEADZONE_MAGNITUDE: f32 = 0.1;
 
             //Responding to the player's input movement vector 
-            if let Some(stick_state) = &left_stick_state {
+            //The stick is used for navigating the left hand's gadget menu while it is open
+            if let (Some(stick_state), false) = (&left_stick_state, gadget_menus[0].is_open) {
                 if stick_state.changed_since_last_sync {                            
                     if let Some(pose) = xrutil::locate_space(&left_hand_aim_space, &tracking_space, stick_state.last_change_time) {
                         let hand_space_vec = glm::vec4(stick_state.current_state.x, stick_state.current_state.y, 0.0, 0.0);
@@ -1022,29 +1145,76 @@ fn main() {
                 }
             }
 
-            //Gadget switching
+            //Gadget switching through the radial menu
+            //Holding the switch button opens the menu, and releasing it picks the highlighted gadget
             {
                 let gadgets = [&mut left_hand_gadget, &mut right_hand_gadget];
-                let gadget_indices = [left_gadget_index, right_gadget_index];
                 let states = [left_switch_state, right_switch_state];
+                let grip_spaces = [&left_hand_grip_space, &right_hand_grip_space];
                 for i in 0..states.len() {
                     if let Some(state) = states[i] {
-                        if state.changed_since_last_sync && state.current_state {
-                            let new = (*gadgets[i] as usize + 1) % GadgetType::COUNT;
-                            *gadgets[i] = GadgetType::from_usize(new);
-        
-                            if let Some(ent) = scene_data.entities.get_mut_element(gadget_indices[i]) { unsafe { 
-                                ent.update_single_transform(i, &glm::zero());
-                            }}
-                            if let Some(ent) = gadget_model_map.get(gadgets[i]) {
-                                scene_data.entities.replace(gadget_indices[i], ent.clone());
+                        let menu = &mut gadget_menus[i];
+                        if state.current_state {
+                            if let Some(pose) = xrutil::locate_space(grip_spaces[i], &tracking_space, last_xr_render_time) {
+                                let hand_position = glm::vec3(pose.position.x, pose.position.y, pose.position.z);
+                                if !menu.is_open {
+                                    let head_forward = match xrutil::locate_space(&view_space, &tracking_space, last_xr_render_time) {
+                                        Some(head_pose) => { glm::vec4_to_vec3(&(xrutil::pose_to_mat4(&head_pose, &glm::identity()) * glm::vec4(0.0, 0.0, -1.0, 0.0))) }
+                                        None => { glm::vec3(0.0, 1.0, 0.0) }
+                                    };
+                                    menu.open(&hand_position, &head_forward, *gadgets[i]);
+                                }
+
+                                //The left thumbstick can also be used to pick from the left hand's menu
+                                let stick_direction = match (i, &left_stick_state) {
+                                    (0, Some(stick_state)) => { glm::vec2(stick_state.current_state.x, stick_state.current_state.y) }
+                                    _ => { glm::zero() }
+                                };
+                                if glm::length(&stick_direction) >= GadgetMenu::STICK_DEADZONE {
+                                    menu.highlight_from_direction(&stick_direction, GadgetMenu::STICK_DEADZONE);
+                                } else {
+                                    let hand_offset = menu.local_offset(&hand_position);
+                                    menu.highlight_from_direction(&hand_offset, GadgetMenu::HAND_DEADZONE);
+                                }
+                            }
+                        } else if menu.is_open {
+                            menu.is_open = false;
+                            if menu.highlighted != *gadgets[i] {
+                                *gadgets[i] = menu.highlighted;
+                                world.set_mesh(gadget_objects[i], gadget_mesh(*gadgets[i]));
                             }
                         }
                     }
                 }
             }
 
+            //Place the gadget models and energy bars of the radial menus
+            for i in 0..gadget_menus.len() {
+                let menu = &gadget_menus[i];
+                for j in 0..GadgetType::COUNT {
+                    let (model_transform, bar_transform) = if menu.is_open {
+                        let slot_matrix = world_from_tracking * menu.anchor * glm::translation(&GadgetMenu::slot_offset(j));
+                        let model_scale = if menu.highlighted as usize == j { 0.8 } else { 0.5 };
+                        let energy_fraction = clamp(gadget_energy[j] / Gadget::MAX_ENERGY, 0.0, 1.0);
+
+                        //The water cylinder extends along its local y axis, so rotate it to lie along the menu's x axis
+                        let bar_matrix = slot_matrix *
+                                         glm::translation(&glm::vec3(-0.05, 0.0, -0.06)) *
+                                         glm::rotation(-glm::half_pi::<f32>(), &Z_UP);
+                        (
+                            Transform { scale: glm::vec3(model_scale, model_scale, model_scale), ..decompose_matrix(&slot_matrix) },
+                            Transform { scale: glm::vec3(0.01, 0.1 * energy_fraction, 0.01), ..decompose_matrix(&bar_matrix) }
+                        )
+                    } else {
+                        (hidden_transform.clone(), hidden_transform.clone())
+                    };
+                    world.place(menu_model_objects[i][j], model_transform);
+                    world.place(menu_bar_objects[i][j], bar_transform);
+                }
+            }
+
             //Handle gadget input
+            gliding = false;
             {
                 let trigger_states = [left_trigger_state, right_trigger_state];
                 let aim_spaces = [&left_hand_aim_space, &right_hand_aim_space];
@@ -1052,6 +1222,7 @@ fn main() {
                 let pillar_scales = [&mut left_water_pillar_scale, &mut right_water_pillar_scale];
 
                 for i in 0..trigger_states.len() {
+                    water_spray_emitters[i].active = false;
                     if let Some(state) = trigger_states[i] {
                         match gadgets[i] {
                             GadgetType::Shotgun => {
@@ -1060,14 +1231,64 @@ fn main() {
                                         let hand_transform = xrutil::pose_to_mat4(&pose, &world_from_tracking);
                                         let hand_space_vec = glm::vec4(0.0, 1.0, 0.0, 0.0);
                                         let world_space_vec = hand_transform * hand_space_vec;
+                                        let muzzle_direction = glm::normalize(&glm::vec4_to_vec3(&world_space_vec));
+                                        let muzzle_position = glm::vec3(hand_transform[12], hand_transform[13], hand_transform[14]);
                                         
-                                        player.tracking_velocity += 20.0 * -glm::vec4_to_vec3(&world_space_vec);
+                                        player.tracking_velocity += SHOTGUN_RECOIL * -muzzle_direction;
+                                        send_or_error(&audio_sender, AudioCommand::PlaySound(SoundRequest::one_shot(SHOTGUN_SOUND, vec_to_array(muzzle_position))));
+
+                                        //Raycast each pellet against the terrain and the world's objects, keeping only the closest hit
+                                        for pellet_direction in shotgun_pellet_directions(&muzzle_direction, SHOTGUN_PELLET_COUNT, SHOTGUN_SPREAD_RADIANS) {
+                                            let mut closest_distance = SHOTGUN_RANGE;
+                                            let mut hit_point = None;
+                                            let mut hit_object = None;
+
+                                            if let Some((_, point)) = ray_hit_terrain(&terrain, &muzzle_position, &pellet_direction) {
+                                                let distance = glm::distance(&muzzle_position, &point);
+                                                if distance < closest_distance {
+                                                    closest_distance = distance;
+                                                    hit_point = Some(point);
+                                                }
+                                            }
+
+                                            if let Some((id, distance)) = world.ray_hit_objects(&muzzle_position, &pellet_direction, closest_distance) {
+                                                hit_point = Some(muzzle_position + pellet_direction * distance);
+                                                hit_object = Some(id);
+                                            }
+
+                                            //Knock whatever was hit away
+                                            if let Some(id) = hit_object {
+                                                world.knock_back(id, &(pellet_direction * SHOTGUN_KNOCKBACK));
+                                            }
+
+                                            if let Some(point) = hit_point {
+                                                particle_system.burst(&point, &-pellet_direction, &SHOTGUN_IMPACT_BURST, elapsed_time);
+                                                world.spawn_hit_marker(hit_marker_entity_index, &point, &-pellet_direction, elapsed_time);
+                                            }
+                                        }
+
+                                        //Anything caught in the blast scatters
+                                        world.scare_targeted(&muzzle_position, &muzzle_direction, SHOTGUN_SCARE_RADIANS, SHOTGUN_RANGE, elapsed_time);
                                     }                            
                                 }
                             }
                             GadgetType::StickyHand => {
                                 println!("Sticky hand gadget");
                             }
+                            GadgetType::Glider => {
+                                //The player has to be holding the trigger with their arms spread to glide
+                                let left_grip = xrutil::locate_space(&left_hand_grip_space, &tracking_space, last_xr_render_time);
+                                let right_grip = xrutil::locate_space(&right_hand_grip_space, &tracking_space, last_xr_render_time);
+                                if let (Some(l), Some(r)) = (left_grip, right_grip) {
+                                    let arm_span = glm::distance(
+                                        &glm::vec3(l.position.x, l.position.y, l.position.z),
+                                        &glm::vec3(r.position.x, r.position.y, r.position.z)
+                                    );
+                                    if state.current_state > 0.5 && arm_span >= GLIDER_MIN_ARM_SPAN && player.movement_state == MoveState::Falling && gadget_energy[GadgetType::Glider as usize] > 0.0 {
+                                        gliding = true;
+                                    }
+                                }
+                            }
                             GadgetType::WaterCannon => {
                                 //Calculate the force of shooting the water gun for the left hand
                                 if let Some(pose) = xrutil::locate_space(aim_spaces[i], &tracking_space, last_xr_render_time) {
@@ -1084,15 +1305,28 @@ fn main() {
                                             set_player_falling(&mut player);
                                         }
                                     }
+
+                                    //Attach the spray emitter to the hand
+                                    let emitter = &mut water_spray_emitters[i];
+                                    emitter.position = glm::vec3(hand_transform[12], hand_transform[13], hand_transform[14]);
+                                    emitter.direction = glm::normalize(&glm::vec4_to_vec3(&world_space_vec));
+                                    emitter.inherited_velocity = player.tracking_velocity;
+                                    emitter.active = state.current_state > 0.0 && gadget_energy[GadgetType::WaterCannon as usize] > 0.0;
+
+                                    //Totoros don't like getting sprayed
+                                    if emitter.active {
+                                        world.scare_targeted(&emitter.position, &emitter.direction, WATER_SPRAY_SCARE_RADIANS, WATER_SPRAY_RANGE, elapsed_time);
+                                    }
                                 }
         
                                 //Apply watergun force to player
-                                if water_gun_force != glm::zero() && remaining_water > 0.0 {
+                                let remaining_water = &mut gadget_energy[GadgetType::WaterCannon as usize];
+                                if water_gun_force != glm::zero() && *remaining_water > 0.0 {
                                     let update_force = water_gun_force * delta_time * MAX_WATER_PRESSURE;
                                     if !infinite_ammo {
-                                        remaining_water -= glm::length(&update_force);
+                                        *remaining_water -= glm::length(&update_force);
                                     }
-                                    let xz_scale = remaining_water / Gadget::MAX_ENERGY;
+                                    let xz_scale = *remaining_water / Gadget::MAX_ENERGY;
                                     pillar_scales[i].x = xz_scale;
                                     pillar_scales[i].z = xz_scale;
                                     player.tracking_velocity += update_force;
@@ -1112,8 +1346,27 @@ fn main() {
             }
 
             if player.movement_state != MoveState::Falling {
-                remaining_water = Gadget::MAX_ENERGY;
+                gadget_energy = [Gadget::MAX_ENERGY; GadgetType::COUNT];
+            }
+        }
+
+        //Start and stop the water spray sounds along with the sprays, and keep them at the player's hands
+        for i in 0..water_spray_emitters.len() {
+            let emitter = &water_spray_emitters[i];
+            let position = vec_to_array(emitter.position);
+            if emitter.active && !water_spray_sounds_playing[i] {
+                send_or_error(&audio_sender, AudioCommand::PlaySound(SoundRequest {
+                    handle: Some(water_spray_sounds[i]),
+                    gain: 0.6,
+                    looping: true,
+                    ..SoundRequest::one_shot(WATER_SPRAY_SOUND, position)
+                }));
+            } else if emitter.active {
+                send_or_error(&audio_sender, AudioCommand::SetSourcePosition(position, water_spray_sounds[i]));
+            } else if water_spray_sounds_playing[i] {
+                send_or_error(&audio_sender, AudioCommand::StopSound(water_spray_sounds[i]));
             }
+            water_spray_sounds_playing[i] = emitter.active;
         }
 
         //Apply gravity to the player's velocity
@@ -1126,6 +1379,32 @@ fn main() {
             }
         }
 
+        //Gliding caps the fall speed and redirects the cancelled fall speed along the head direction
+        if gliding && player.tracking_velocity.z < -GLIDER_SINK_SPEED {
+            let head_forward = match xrutil::locate_space(&view_space, &tracking_space, last_xr_render_time) {
+                Some(pose) => {
+                    let forward = xrutil::pose_to_mat4(&pose, &world_from_tracking) * glm::vec4(0.0, 0.0, -1.0, 0.0);
+                    glm::vec3(forward.x, forward.y, 0.0)
+                }
+                None => { glm::zero() }
+            };
+
+            if head_forward != glm::zero() {
+                let excess_fall_speed = -GLIDER_SINK_SPEED - player.tracking_velocity.z;
+                player.tracking_velocity.z = -GLIDER_SINK_SPEED;
+
+                let mut horizontal = glm::vec3(player.tracking_velocity.x, player.tracking_velocity.y, 0.0) + glm::normalize(&head_forward) * excess_fall_speed * GLIDER_EFFICIENCY;
+                if glm::length(&horizontal) > GLIDER_MAX_SPEED {
+                    horizontal = glm::normalize(&horizontal) * GLIDER_MAX_SPEED;
+                }
+                player.tracking_velocity.x = horizontal.x;
+                player.tracking_velocity.y = horizontal.y;
+            }
+        }
+        if gliding && !infinite_ammo {
+            gadget_energy[GadgetType::Glider as usize] -= GLIDER_ENERGY_DRAIN * delta_time;
+        }
+
         //If the user is controlling the camera, force the mouse cursor into the center of the screen
         if mouselook_enabled {
             window.set_cursor_pos(screen_state.get_window_size().x as f64 / 2.0, screen_state.get_window_size().y as f64 / 2.0);
@@ -1134,56 +1413,265 @@ fn main() {
         let camera_velocity = camera_speed * glm::vec4_to_vec3(&(glm::affine_inverse(*screen_state.get_view_from_world()) * glm::vec3_to_vec4(&camera_input)));
         camera_position += camera_velocity * delta_time;
 
+        //Show the prop preview wherever the mouse ray hits the terrain
+        prop_editor.hide_preview(&mut scene_data);
+        if let (ClickAction::PlacingProp, false) = (&click_action, imgui_wants_mouse) {
+            let (ray_origin, mouse_ray_dir) = mouse_ray(&screen_state, &camera_position, &screen_space_mouse);
+            if let Some((_, point)) = ray_hit_terrain(&terrain, &ray_origin, &mouse_ray_dir) {
+                let mut transform = Transform::from_position(point);
+                transform.rotation = glm::quat_angle_axis(prop_editor.placement_rotation, &Z_UP);
+
+                if let Some(preview_index) = prop_editor.preview_entity(&mut scene_data, collision_program, &mut texture_keeper, &default_tex_params) {
+                    if let Some(entity) = scene_data.entities.get_mut_element(preview_index) { unsafe {
+                        entity.should_be_rendered = true;
+                        entity.update_single_transform(0, &transform.to_matrix());
+                    }}
+                }
+
+                //Place the selected prop at clicking position
+                if mouse_clicked && !was_mouse_clicked {
+                    if let Some(model_name) = prop_editor.selected_model_name().map(String::from) {
+                        let entity_index = level.mesh_index(&model_name, &mut scene_data, &mut world, standard_program, &mut texture_keeper, &default_tex_params);
+                        let object = GameObject {
+                            mesh: Some(RenderMesh::new(entity_index)),
+                            editable: true,
+                            ..GameObject::new(transform)
+                        };
+                        let id = world.spawn(object.clone());
+                        if let Some(handle) = world.handle(id) {
+                            edit_history.push(EditCommand::Spawn { handle, object });
+                        }
+                    }
+                }
+            }
+        }
+
+        //Select props and drag their gizmo handles
+        if click_action == ClickAction::EditingProps {
+            //The selected prop might have been removed by an undo
+            if let Some(id) = prop_editor.selected_object {
+                if world.objects[id].is_none() {
+                    prop_editor.end_drag();
+                    prop_editor.selected_object = None;
+                }
+            }
+
+            let (ray_origin, mouse_ray_dir) = mouse_ray(&screen_state, &camera_position, &screen_space_mouse);
+            if !imgui_wants_mouse && mouse_clicked && !was_mouse_clicked {
+                if !prop_editor.begin_drag(&world, &camera_position, &ray_origin, &mouse_ray_dir, &screen_space_mouse) {
+                    prop_editor.selected_object = PropEditor::pick_prop(&world, &scene_data, &terrain, &mut pick_meshes, &ray_origin, &mouse_ray_dir);
+                }
+            }
+
+            if prop_editor.is_dragging() {
+                if mouse_clicked {
+                    if let (Some(id), Some(transform)) = (prop_editor.selected_object, prop_editor.drag(&camera_position, &ray_origin, &mouse_ray_dir, &screen_space_mouse)) {
+                        world.set_transform(id, transform, &mut scene_data);
+                    }
+                } else {
+                    prop_editor.finish_drag(&world, &mut edit_history);
+                }
+            }
+        } else {
+            prop_editor.finish_drag(&world, &mut edit_history);
+            prop_editor.selected_object = None;
+        }
+
         //Place totoro at clicking position
         if !imgui_wants_mouse && click_action == ClickAction::SpawningTotoro && mouse_clicked && !was_mouse_clicked {
-            let fovx_radians = 2.0 * f32::atan(f32::tan(screen_state.get_fov_radians() / 2.0) * screen_state.get_aspect_ratio());
-            let max_coords = glm::vec4(
-                NEAR_DISTANCE * f32::tan(fovx_radians / 2.0),
-                NEAR_DISTANCE * f32::tan(screen_state.get_fov_radians() / 2.0),
-                -NEAR_DISTANCE,
-                1.0
-            );
-            let normalized_coords = glm::vec4(
-                screen_space_mouse.x * 2.0 / screen_state.get_window_size().x as f32 - 1.0,
-                -screen_space_mouse.y * 2.0 / screen_state.get_window_size().y as f32 + 1.0,
-                1.0,
-                1.0
-            );
-            let view_space_mouse = glm::matrix_comp_mult(&normalized_coords, &max_coords);
-            let world_space_mouse = screen_state.get_world_from_view() * view_space_mouse;
-
-            let ray_origin = glm::vec3(camera_position.x, camera_position.y, camera_position.z);
-            let mouse_ray_dir = glm::normalize(&(glm::vec4_to_vec3(&world_space_mouse) - ray_origin));
+            let (ray_origin, mouse_ray_dir) = mouse_ray(&screen_state, &camera_position, &screen_space_mouse);
 
             //Create Totoro if the ray hit
             if let Some((_, point)) = ray_hit_terrain(&terrain, &ray_origin, &mouse_ray_dir) {
-                let tot = Totoro {
-                    position: point,
-                    creation_time: elapsed_time
+                //The first Totoro carries the music around with it
+                let audio_emitter = match world.sound_carrier(BGM_SOURCE) {
+                    Some(_) => { None }
+                    None => { Some(AudioEmitter { sources: vec![BGM_SOURCE] }) }
                 };
-                totoros.insert(tot);
+                let object = GameObject {
+                    transform: Transform::from_position(point),
+                    mesh: Some(RenderMesh::new(totoro_entity_index)),
+                    collider: Some(SphereCollider {
+                        offset: glm::vec3(0.0, 0.0, Totoro::HIT_RADIUS),
+                        radius: Totoro::HIT_RADIUS
+                    }),
+                    audio_emitter,
+                    behaviour: Behaviour::Totoro(Totoro::new(point, elapsed_time)),
+                    editable: false
+                };
+                let id = world.spawn(object.clone());
+                if let Some(handle) = world.handle(id) {
+                    edit_history.push(EditCommand::Spawn { handle, object });
+                }
+
+                //The spawn sound follows the new Totoro around
+                let sound_handle = sound_handles.next();
+                send_or_error(&audio_sender, AudioCommand::PlaySound(SoundRequest {
+                    handle: Some(sound_handle),
+                    ..SoundRequest::one_shot(TOTORO_SPAWN_SOUND, vec_to_array(point))
+                }));
+                world.attach_sound(id, sound_handle);
+            }
+        }
+
+        //The player's body is the tracked capsule in VR and a sphere around the free camera otherwise
+        let player_capsule = match &xr_instance {
+            Some(_) => {
+                Capsule {
+                    segment: LineSegment {
+                        p0: player.tracked_segment.p0,
+                        p1: player.tracked_segment.p1 + glm::vec3(0.0, 0.0, player.radius)
+                    },
+                    radius: player.radius
+                }
+            }
+            None => {
+                Capsule {
+                    segment: LineSegment {
+                        p0: camera_position,
+                        p1: camera_position
+                    },
+                    radius: player.radius
+                }
+            }
+        };
+
+        //Creatures pay attention to the player's feet in VR and to the free camera otherwise
+        let attention_point = match &xr_instance {
+            Some(_) => { player.tracked_segment.p1 }
+            None => { camera_position }
+        };
+
+        //Collect any Totoros the player is touching
+        for position in collection_game.update(&mut world, &player_capsule, &level.name, elapsed_time) {
+            particle_system.burst(&position, &Z_UP, &COLLECT_BURST, elapsed_time);
+            send_or_error(&audio_sender, AudioCommand::PlaySound(SoundRequest {
+                bus: AudioBus::Ui,
+                ..SoundRequest::one_shot(CHIME_SOUND, vec_to_array(position))
+            }));
+        }
+
+        //Hatch chickens and let them peck at the player
+        if chickens_enabled {
+            for nest in nests.iter_mut() {
+                nest.update(&mut world, chicken_entity_index, &attention_point, elapsed_time);
+            }
+        }
+        for peck_position in world.peck_player(&player_capsule, elapsed_time) {
+            let away = attention_point - peck_position;
+            let away = if glm::length(&glm::vec2(away.x, away.y)) > 0.0 { glm::normalize(&glm::vec3(away.x, away.y, 0.0)) } else { glm::vec3(1.0, 0.0, 0.0) };
+            match &xr_instance {
+                Some(_) => {
+                    player.tracking_velocity += away * CHICKEN_PECK_KNOCKBACK + Z_UP * CHICKEN_PECK_KNOCKBACK * 0.5;
+                    player.movement_state = MoveState::Falling;
+                }
+                None => { camera_position += away * CHICKEN_PECK_KNOCKBACK * 0.25; }
+            }
+            player.damage(CHICKEN_PECK_DAMAGE, elapsed_time);
+            if !infinite_ammo {
+                for energy in gadget_energy.iter_mut() {
+                    *energy = f32::max(*energy - CHICKEN_PECK_ENERGY_DRAIN, 0.0);
+                }
+            }
+        }
+
+        //Hurt the player while they're inside a damage volume
+        for damage_volume in level.damage_volumes.iter() {
+            if damage_volume.volume.intersects_capsule(&player_capsule) {
+                player.damage(damage_volume.damage, elapsed_time);
             }
         }
 
-        //Update the GPU transform buffer for the Totoros
-        if let Some(entity) = scene_data.entities.get_mut_element(totoro_entity_index) {
-            let hover_height = 0.5;
-            let excitement = 10.0;
-
-            let mut transform_buffer = vec![0.0; totoros.len() * 16];
-            for i in 0..totoros.len() {
-                if let Some(totoro) = &totoros[i] {
-                    let t = elapsed_time - totoro.creation_time;
-                    let mm = glm::translation(&totoro.position) * glm::translation(&glm::vec3(0.0, 0.0, hover_height * f32::sin(t*excitement) + hover_height)) * glm::rotation(t*excitement/4.0, &Z_UP);                    
-                    if i == 0 {
-                        let pos = [mm[12], mm[13], mm[14]];
-                        send_or_error(&audio_sender, AudioCommand::SetSourcePosition(pos, 0));
+        //Fire the level's triggers as the player moves through them
+        for action in update_triggers(&mut level.triggers, &player_capsule) {
+            match action {
+                TriggerAction::Sound(sound) => {
+                    send_or_error(&audio_sender, AudioCommand::PlaySound(SoundRequest {
+                        bus: AudioBus::Ambience,
+                        ..SoundRequest::one_shot(&sound, vec_to_array(attention_point))
+                    }));
+                }
+                TriggerAction::Bgm(path) => { send_or_error(&audio_sender, AudioCommand::SetBGM(path)); }
+                TriggerAction::Spawn(kind, position) => {
+                    let (entity_index, radius, behaviour) = match kind {
+                        SpawnKind::Totoro => { (totoro_entity_index, Totoro::HIT_RADIUS, Behaviour::Totoro(Totoro::new(position, elapsed_time))) }
+                        SpawnKind::Chicken => { (chicken_entity_index, Chicken::HIT_RADIUS, Behaviour::Chicken(Chicken::new(position, elapsed_time))) }
+                    };
+                    let id = world.spawn(GameObject {
+                        transform: Transform::from_position(position),
+                        mesh: Some(RenderMesh::new(entity_index)),
+                        collider: Some(SphereCollider {
+                            offset: glm::vec3(0.0, 0.0, radius),
+                            radius
+                        }),
+                        audio_emitter: None,
+                        behaviour,
+                        editable: false
+                    });
+                    if kind == SpawnKind::Totoro {
+                        let sound_handle = sound_handles.next();
+                        send_or_error(&audio_sender, AudioCommand::PlaySound(SoundRequest {
+                            handle: Some(sound_handle),
+                            ..SoundRequest::one_shot(TOTORO_SPAWN_SOUND, vec_to_array(position))
+                        }));
+                        world.attach_sound(id, sound_handle);
+                    }
+                }
+                TriggerAction::SetVisibility(name, visible) => {
+                    let entity_index = level.meshes.iter().find(|mesh| mesh.ozy_name == name).map(|mesh| mesh.entity_index);
+                    match entity_index.and_then(|i| scene_data.entities.get_mut_element(i)) {
+                        Some(entity) => { entity.should_be_rendered = visible.unwrap_or(!entity.should_be_rendered); }
+                        None => { println!("Trigger refers to \"{}\", which isn't part of this level", name); }
+                    }
+                }
+                TriggerAction::Teleport(position) => {
+                    match &xr_instance {
+                        Some(_) => {
+                            //Move the tracking space so that the player's feet land on the destination
+                            player.tracking_position += position - player.tracked_segment.p1;
+                            player.tracking_velocity = glm::zero();
+                            player.movement_state = MoveState::Falling;
+                        }
+                        None => {
+                            camera_position = position;
+                            last_camera_position = camera_position;
+                        }
                     }
-                    write_matrix_to_buffer(&mut transform_buffer, i, mm);
                 }
             }
+        }
+
+        //Once the screen has faded out after death, put the player back at the start
+        if death_sequence.update(&player, elapsed_time) {
+            reset_player_position(&mut player);
+            player.health = Player::MAX_HEALTH;
+            gadget_energy = [Gadget::MAX_ENERGY; GadgetType::COUNT];
+            camera_position = glm::vec3(0.0, -8.0, 5.5);
+            last_camera_position = camera_position;
+        }
+
+        //Update the world's objects and upload their transforms
+        {
+            world.update_behaviours(&terrain, &attention_point, &audio_status.music, delta_time, elapsed_time);
+            world.sync_render_entities(&mut scene_data);
+            world.sync_audio_emitters(&audio_sender);
+        }
+
+        //Find out what's under the mouse cursor
+        let hovered = if show_pick_tooltip && !imgui_wants_mouse && !mouselook_enabled && !hmd_pov {
+            let (ray_origin, mouse_ray_dir) = mouse_ray(&screen_state, &camera_position, &screen_space_mouse);
+            pick(&world, &scene_data.entities, &terrain, &mut pick_meshes, &ray_origin, &mouse_ray_dir)
+        } else {
+            None
+        };
 
-            entity.update_buffer(&transform_buffer);
+        //Simulate particles and upload their transforms
+        for emitter in water_spray_emitters.iter_mut() {
+            particle_system.emit(emitter, delta_time, elapsed_time);
+        }
+        particle_system.update(&terrain, delta_time, elapsed_time);
+        if let Some(entity) = scene_data.entities.get_mut_element(particle_system.entity_index) {
+            entity.update_buffer(&particle_system.transform_buffer(elapsed_time));
         }
 
         //Update tracking space location
@@ -1195,6 +1683,9 @@ fn main() {
         //The user is considered to be always standing on the ground in tracking space
         player.tracked_segment = xrutil::tracked_player_segment(&view_space, &tracking_space, last_xr_render_time, &world_from_tracking);
 
+        //Remember how fast the player was falling so that landing can make a sound
+        let fall_speed = -player.tracking_velocity.z;
+
         //We try to do all work related to terrain collision here in order
         //to avoid iterating over all of the triangles more than once
         for i in (0..terrain.indices.len()).step_by(3) {
@@ -1276,7 +1767,7 @@ fn main() {
                             let t = (glm::dot(&triangle.normal, &(triangle.a - capsule_ref)) + player.radius) / dot_z_up;
                             player.tracking_position += Z_UP * t;
                             
-                            ground_player(&mut player, &mut remaining_water);
+                            ground_player(&mut player, &mut gadget_energy, elapsed_time);
                         } else {                        
                             player.tracking_position += triangle.normal * (player.radius - dist);
                         }
@@ -1287,7 +1778,7 @@ fn main() {
                             let push_dir = glm::normalize(&(capsule_ref - best_point));
                             player.tracking_position += push_dir * (player.radius - best_dist);
                             if glm::dot(&push_dir, &Z_UP) >= MIN_NORMAL_LIKENESS {
-                                ground_player(&mut player, &mut remaining_water);
+                                ground_player(&mut player, &mut gadget_energy, elapsed_time);
                             }
                         }
                     }
@@ -1304,6 +1795,15 @@ fn main() {
 
         player.last_tracked_segment = player.tracked_segment.clone();
 
+        //Thud when the player hits the ground hard enough
+        const MIN_LANDING_SOUND_SPEED: f32 = 3.0;
+        if fall_speed > MIN_LANDING_SOUND_SPEED && player.tracking_velocity == glm::zero() {
+            send_or_error(&audio_sender, AudioCommand::PlaySound(SoundRequest {
+                gain: f32::min(fall_speed / Player::SAFE_FALL_SPEED, 1.0),
+                ..SoundRequest::one_shot(LANDING_SOUND, vec_to_array(player.tracked_segment.p1))
+            }));
+        }
+
         //Tell the audio thread about the listener's current state
         {
             //Just doing the match here to determine if the listener should be the HMD or the free camera
@@ -1334,6 +1834,11 @@ fn main() {
             send_or_error(&audio_sender, AudioCommand::SetListenerOrientation((listener_forward, listener_up)));
         }
 
+        //Keep up with what the audio thread has been doing
+        while let Ok(event) = audio_event_receiver.try_recv() {
+            audio_status.handle_event(event);
+        }
+
         last_camera_position = camera_position;
         was_mouse_clicked = mouse_clicked;
 
@@ -1352,6 +1857,8 @@ fn main() {
                 imgui_ui.checkbox(im_str!("TRUE wireframe view"), &mut true_wireframe);
                 imgui_ui.checkbox(im_str!("Complex normals"), &mut scene_data.complex_normals);
                 imgui_ui.checkbox(im_str!("Camera collision"), &mut camera_collision);
+                imgui_ui.checkbox(im_str!("Show HUD"), &mut show_hud);
+                imgui_ui.checkbox(im_str!("Show what's under the cursor"), &mut show_pick_tooltip);
                 if let Some(_) = &xr_instance {
                     imgui_ui.checkbox(im_str!("HMD Point-of-view"), &mut hmd_pov);
                     imgui_ui.checkbox(im_str!("Infinite ammo"), &mut infinite_ammo);
@@ -1371,8 +1878,149 @@ fn main() {
                 imgui_ui.separator();
 
                 imgui_ui.text(im_str!("What does a mouse click do?"));
-                do_radio_option(&imgui_ui, im_str!("Places the dragon"), &mut click_action, ClickAction::PlacingDragon);
+                do_radio_option(&imgui_ui, im_str!("Places props"), &mut click_action, ClickAction::PlacingProp);
+                do_radio_option(&imgui_ui, im_str!("Edits props"), &mut click_action, ClickAction::EditingProps);
                 do_radio_option(&imgui_ui, im_str!("Give life to a new Totoro"), &mut click_action, ClickAction::SpawningTotoro);
+                match click_action {
+                    ClickAction::PlacingProp => {
+                        imgui_ui.text(im_str!("Prop to place (scroll to rotate):"));
+                        for i in 0..prop_editor.models.len() {
+                            let label = im_str!("{}", prop_editor.models[i]);
+                            imgui_ui.radio_button(&label, &mut prop_editor.selected_model, i);
+                        }
+                    }
+                    ClickAction::EditingProps => {
+                        do_radio_option(&imgui_ui, im_str!("Translate"), &mut prop_editor.gizmo_mode, GizmoMode::Translate);
+                        do_radio_option(&imgui_ui, im_str!("Rotate"), &mut prop_editor.gizmo_mode, GizmoMode::Rotate);
+                        do_radio_option(&imgui_ui, im_str!("Scale"), &mut prop_editor.gizmo_mode, GizmoMode::Scale);
+                        let mut selected_entity = None;
+                        if let Some(id) = prop_editor.selected_object {
+                            if let Some(object) = &world.objects[id] {
+                                let p = object.transform.position;
+                                let sc = object.transform.scale;
+                                imgui_ui.text(im_str!("Position: ({:.2}, {:.2}, {:.2})", p.x, p.y, p.z));
+                                imgui_ui.text(im_str!("Scale: ({:.2}, {:.2}, {:.2})", sc.x, sc.y, sc.z));
+                                selected_entity = object.mesh.as_ref().map(|mesh| mesh.entity_index);
+                            }
+                            if imgui_ui.button(im_str!("Delete prop"), [0.0, 32.0]) {
+                                prop_editor.delete_selected(&mut world, &mut edit_history);
+                            }
+                        }
+
+                        //Material parameters are shared by every instance of the prop's model
+                        if let Some(entity_index) = selected_entity {
+                            if let Some(entity) = scene_data.entities.get_mut_element(entity_index) {
+                                let before = MaterialParams::from_entity(entity);
+                                let mut changed = Slider::new(im_str!("UV scale")).range(RangeInclusive::new(0.1, 10.0)).build_array(&imgui_ui, entity.uv_scale.as_mut_slice());
+                                changed |= Slider::new(im_str!("UV offset")).range(RangeInclusive::new(-1.0, 1.0)).build_array(&imgui_ui, entity.uv_offset.as_mut_slice());
+                                if changed && prop_editor.material_edit_start.is_none() {
+                                    prop_editor.material_edit_start = Some((entity_index, before));
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                //A material edit is finished once the slider is let go
+                if !mouse_clicked {
+                    if let Some((entity_index, before)) = prop_editor.material_edit_start.take() {
+                        if let Some(entity) = scene_data.entities.get_mut_element(entity_index) {
+                            let after = MaterialParams::from_entity(entity);
+                            if after != before {
+                                edit_history.push(EditCommand::Material { entity_index, before, after });
+                            }
+                        }
+                    }
+                }
+                if imgui_ui.button(im_str!("Save level"), [0.0, 32.0]) {
+                    if let Err(e) = level.save(&world) {
+                        tfd::message_box_ok("Error saving level", &format!("Error writing level {}: {}", level.name, e), MessageBoxIcon::Error);
+                    }
+                }
+                {
+                    let occupied_triggers = level.triggers.iter().filter(|trigger| trigger.is_occupied()).count();
+                    imgui_ui.text(im_str!("Triggers: {} ({} occupied)\tDamage volumes: {}\tReverb zones: {}", level.triggers.len(), occupied_triggers, level.damage_volumes.len(), level.reverb_zones.len()));
+                }
+                imgui_ui.separator();
+
+                //Totoro collection game mode controls
+                imgui_ui.text(im_str!("Totoro collection ({} spots in this level):", level.totoro_spots.len()));
+                match collection_game.state {
+                    GameState::Running { .. } => {
+                        imgui_ui.text(im_str!("Collected {}/{}\tTime: {:.2}s", collection_game.collected, collection_game.total, collection_game.run_time(elapsed_time)));
+                        if imgui_ui.button(im_str!("Give up"), [0.0, 32.0]) {
+                            collection_game.stop(&mut world);
+                        }
+                    }
+                    _ => {
+                        if imgui_ui.button(im_str!("Start collecting"), [0.0, 32.0]) {
+                            collection_game.start(&mut world, &level.totoro_spots, totoro_entity_index, elapsed_time);
+                        }
+                    }
+                }
+                if let Some(best) = collection_game.best_times.get(&level.name) {
+                    imgui_ui.text(im_str!("Best time: {:.2}s", best));
+                }
+                if imgui_ui.button(im_str!("Add Totoro spot below camera"), [0.0, 32.0]) {
+                    if let Some((_, point)) = ray_hit_terrain(&terrain, &camera_position, &-Z_UP) {
+                        level.totoro_spots.push(point);
+                    }
+                }
+                imgui_ui.same_line(0.0);
+                if imgui_ui.button(im_str!("Remove last spot"), [0.0, 32.0]) {
+                    level.totoro_spots.pop();
+                }
+                imgui_ui.separator();
+
+                //Chicken controls
+                imgui_ui.text(im_str!("Chickens ({} nests in this level):", nests.len()));
+                imgui_ui.checkbox(im_str!("Nests hatch chickens"), &mut chickens_enabled);
+                if imgui_ui.button(im_str!("Add nest below camera"), [0.0, 32.0]) {
+                    if let Some((_, point)) = ray_hit_terrain(&terrain, &camera_position, &-Z_UP) {
+                        level.nest_spots.push(point);
+                        nests.push(Nest::new(point, &terrain));
+                    }
+                }
+                imgui_ui.same_line(0.0);
+                if imgui_ui.button(im_str!("Remove last nest"), [0.0, 32.0]) {
+                    level.nest_spots.pop();
+                    if let Some(mut nest) = nests.pop() {
+                        nest.clear_chickens(&mut world);
+                    }
+                }
+                if imgui_ui.button(im_str!("Clear chickens"), [0.0, 32.0]) {
+                    for nest in nests.iter_mut() {
+                        nest.clear_chickens(&mut world);
+                    }
+                }
+                imgui_ui.separator();
+
+                //Edit history section
+                imgui_ui.text(im_str!("Edit history (Ctrl+Z / Ctrl+Y):"));
+                if imgui_ui.button(im_str!("Undo"), [0.0, 32.0]) {
+                    prop_editor.finish_drag(&world, &mut edit_history);
+                    edit_history.undo(&mut world, &mut scene_data);
+                }
+                imgui_ui.same_line(0.0);
+                if imgui_ui.button(im_str!("Redo"), [0.0, 32.0]) {
+                    prop_editor.finish_drag(&world, &mut edit_history);
+                    edit_history.redo(&mut world, &mut scene_data);
+                }
+                let mut history_jump = None;
+                if imgui::Selectable::new(im_str!("<start>")).selected(edit_history.position() == 0).build(&imgui_ui) {
+                    history_jump = Some(0);
+                }
+                for (i, command) in edit_history.commands().iter().enumerate() {
+                    let label = im_str!("{}: {}", i + 1, command.description());
+                    if imgui::Selectable::new(&label).selected(edit_history.position() == i + 1).build(&imgui_ui) {
+                        history_jump = Some(i + 1);
+                    }
+                }
+                if let Some(position) = history_jump {
+                    prop_editor.finish_drag(&world, &mut edit_history);
+                    edit_history.jump_to(position, &mut world, &mut scene_data);
+                }
                 imgui_ui.separator();
 
                 imgui_ui.text(im_str!("Lighting controls:"));
@@ -1383,24 +2031,113 @@ fn main() {
 
                 imgui_ui.separator();
 
+                //Mixer section
+                //Changes are saved once a slider is let go of, rather than on every frame of the drag
+                imgui_ui.text(im_str!("Mixer"));
+                for i in 0..AudioBus::COUNT {
+                    let bus = AudioBus::from_usize(i);
+                    let mut save_mixer = false;
+                    if Slider::new(&im_str!("{} volume", bus.name())).range(RangeInclusive::new(0.0, 100.0)).build(&imgui_ui, &mut mixer.buses[i].volume) {
+                        send_or_error(&audio_sender, AudioCommand::SetBus(bus, mixer.buses[i]));
+                    }
+                    if imgui_ui.is_item_deactivated_after_edit() {
+                        save_mixer = true;
+                    }
+                    imgui_ui.same_line(0.0);
+                    if imgui_ui.checkbox(&im_str!("Mute##{}", bus.name()), &mut mixer.buses[i].muted) {
+                        send_or_error(&audio_sender, AudioCommand::SetBus(bus, mixer.buses[i]));
+                        save_mixer = true;
+                    }
+                    if save_mixer {
+                        mixer.save_to_config(&mut config);
+                        config.to_file(Configuration::CONFIG_FILEPATH);
+                    }
+                }
+
+                imgui_ui.separator();
+
                 //Music controls section
                 imgui_ui.text(im_str!("Music controls"));
-                if Slider::new(im_str!("Master Volume")).range(RangeInclusive::new(0.0, 100.0)).build(&imgui_ui, &mut bgm_volume) {
-                    send_or_error(&audio_sender, AudioCommand::SetListenerGain(bgm_volume));
+
+                if let Some(Err(reason)) = &audio_status.initialized {
+                    imgui_ui.text_colored([1.0, 0.4, 0.4, 1.0], &im_str!("Audio unavailable: {}", reason));
+                }
+                match &audio_status.track {
+                    Some(name) => {
+                        match audio_status.track_length {
+                            Some(length) => {
+                                imgui_ui.text(im_str!("Now playing: {} ({:.0}s / {:.0}s)", name, audio_status.track_position, length));
+                                let mut position = seek_drag.unwrap_or(f32::min(audio_status.track_position, length));
+                                if Slider::new(im_str!("##Seek")).range(RangeInclusive::new(0.0, length)).build(&imgui_ui, &mut position) {
+                                    seek_drag = Some(position);
+                                }
+                                if imgui_ui.is_item_deactivated_after_edit() {
+                                    if let Some(seconds) = seek_drag.take() {
+                                        send_or_error(&audio_sender, AudioCommand::SeekBGM(seconds));
+                                    }
+                                }
+                            }
+                            None => { imgui_ui.text(im_str!("Now playing: {} ({:.0}s)", name, audio_status.track_position)); }
+                        }
+                    }
+                    None => { imgui_ui.text(im_str!("Nothing playing")); }
+                }
+                if let Some(points) = &audio_status.track_loop {
+                    match points.end {
+                        Some(end) => { imgui_ui.text(im_str!("Looping from {:.2}s to {:.2}s", points.start, end)); }
+                        None => { imgui_ui.text(im_str!("Looping from {:.2}s to the end", points.start)); }
+                    }
+                }
+                imgui::ProgressBar::new(audio_status.music.loudness).overlay_text(im_str!("Loudness")).build(&imgui_ui);
+                if let Some(e) = &audio_status.last_error {
+                    imgui_ui.text_colored([1.0, 0.8, 0.3, 1.0], &im_str!("Last audio error: {}", e));
                 }
 
+                if imgui_ui.button(im_str!("Previous"), [0.0, 32.0]) {
+                    send_or_error(&audio_sender, AudioCommand::PreviousTrack);
+                }
+                imgui_ui.same_line(0.0);
                 if imgui_ui.button(im_str!("Play/Pause"), [0.0, 32.0]) {
                     send_or_error(&audio_sender, AudioCommand::PlayPause);
                 }
                 imgui_ui.same_line(0.0);
+                if imgui_ui.button(im_str!("Next"), [0.0, 32.0]) {
+                    send_or_error(&audio_sender, AudioCommand::NextTrack);
+                }
+                imgui_ui.same_line(0.0);
                 if imgui_ui.button(im_str!("Restart"), [0.0, 32.0]) {
                     send_or_error(&audio_sender, AudioCommand::RestartBGM);
                 }
                 imgui_ui.same_line(0.0);
-                if imgui_ui.button(im_str!("Choose mp3"), [0.0, 32.0]) {
+                if imgui_ui.button(im_str!("Choose track"), [0.0, 32.0]) {
                     send_or_error(&audio_sender, AudioCommand::SelectNewBGM);
                 }
 
+                let mut shuffle = audio_status.playlist.shuffle;
+                if imgui_ui.checkbox(im_str!("Shuffle"), &mut shuffle) {
+                    send_or_error(&audio_sender, AudioCommand::SetShuffle(shuffle));
+                }
+                imgui_ui.same_line(0.0);
+                imgui_ui.text(im_str!("Repeat:"));
+                for (mode, label) in [(RepeatMode::Off, im_str!("Off")), (RepeatMode::All, im_str!("All")), (RepeatMode::One, im_str!("One"))].iter() {
+                    imgui_ui.same_line(0.0);
+                    if imgui_ui.radio_button_bool(label, audio_status.playlist.repeat == *mode) {
+                        send_or_error(&audio_sender, AudioCommand::SetRepeat(*mode));
+                    }
+                }
+                if Slider::new(im_str!("Crossfade (seconds)")).range(RangeInclusive::new(0.0, 10.0)).build(&imgui_ui, &mut crossfade_duration) {
+                    send_or_error(&audio_sender, AudioCommand::SetCrossfade(crossfade_duration));
+                }
+
+                //The queue, in play order. Clicking a track jumps to it
+                imgui_ui.text(im_str!("Queue:"));
+                for i in 0..audio_status.playlist.queue.len() {
+                    let label = im_str!("{}. {}", i + 1, audio_status.playlist.queue[i]);
+                    if imgui::Selectable::new(&label).selected(audio_status.playlist.current == Some(i)).build(&imgui_ui) {
+                        send_or_error(&audio_sender, AudioCommand::PlayQueuePosition(i));
+                    }
+                }
+
                 imgui_ui.separator();
                 
                 //Reset player position button
@@ -1445,6 +2182,54 @@ fn main() {
                 win_token.end(&imgui_ui);
             }
 
+            //Results screen for the collection game mode
+            if let GameState::Finished { time, previous_best } = collection_game.state {
+                let win = imgui::Window::new(im_str!("Results"));
+                if let Some(win_token) = win.begin(&imgui_ui) {
+                    imgui_ui.text(im_str!("Collected all {} Totoros in {:.2}s", collection_game.total, time));
+                    match previous_best {
+                        Some(best) if best <= time => { imgui_ui.text(im_str!("Best time: {:.2}s", best)); }
+                        Some(best) => { imgui_ui.text(im_str!("New best time! (previously {:.2}s)", best)); }
+                        None => { imgui_ui.text(im_str!("New best time!")); }
+                    }
+
+                    if imgui_ui.button(im_str!("Play again"), [0.0, 32.0]) {
+                        collection_game.start(&mut world, &level.totoro_spots, totoro_entity_index, elapsed_time);
+                    }
+                    imgui_ui.same_line(0.0);
+                    if imgui_ui.button(im_str!("Close"), [0.0, 32.0]) {
+                        collection_game.stop(&mut world);
+                    }
+                    win_token.end(&imgui_ui);
+                }
+            }
+
+            //Tooltip describing what's under the cursor
+            if let Some(result) = &hovered {
+                let description = match result.target {
+                    PickTarget::Terrain => { String::from("Terrain") }
+                    PickTarget::Object(id) => {
+                        let owner = match &world.objects[id] {
+                            Some(GameObject { behaviour: Behaviour::Totoro(_), .. }) => { format!("Totoro #{}", id) }
+                            Some(GameObject { behaviour: Behaviour::Chicken(_), .. }) => { format!("Chicken #{}", id) }
+                            _ => { format!("Object #{}", id) }
+                        };
+                        match world.objects[id].as_ref().and_then(|object| object.mesh.as_ref()) {
+                            Some(mesh) => {
+                                let mesh_name = match &scene_data.entities[mesh.entity_index] {
+                                    Some(entity) => { entity.source_path.clone() }
+                                    None => { String::new() }
+                                };
+                                format!("{}\nEntity {}, instance {}\n{}", mesh_name, mesh.entity_index, mesh.instance_index, owner)
+                            }
+                            None => { owner }
+                        }
+                    }
+                };
+                let p = result.point;
+                imgui_ui.tooltip_text(format!("{}\nHit point: ({:.2}, {:.2}, {:.2})", description, p.x, p.y, p.z));
+            }
+
             //Shadow cascade viewer
             /*
             let win = imgui::Window::new(im_str!("Shadow map"));
@@ -1465,6 +2250,23 @@ fn main() {
             screen_state.update_view(new_view_matrix);
         }
 
+        //Lay out the player status HUD
+        let hud_quads = {
+            let status = hud::PlayerStatus {
+                gadget_energy: &gadget_energy,
+                active_gadgets: [left_hand_gadget, right_hand_gadget],
+                jumps_remaining: player.jumps_remaining,
+                health: player.health,
+                speed: glm::length(&player.tracking_velocity),
+                collection_progress: match collection_game.state {
+                    GameState::Running { .. } => { Some((collection_game.collected, collection_game.total)) }
+                    _ => { None }
+                }
+            };
+            hud::status_quads(&status, elapsed_time)
+        };
+        let screen_fade = death_sequence.screen_fade(&player, elapsed_time);
+
         //Render
         unsafe {
             //Setting up OpenGL state for 3D rendering
@@ -1492,14 +2294,10 @@ fn main() {
 
                             //Right here is where we want to update the controller objects' transforms
                             {
-                                if let Some(pose) = &left_grip_pose {
-                                    if let Some(entity) = scene_data.entities.get_mut_element(left_gadget_index) {
-                                        entity.update_single_transform(0, &xrutil::pose_to_mat4(pose, &world_from_tracking))
-                                    }
-                                }
-                                if let Some(pose) = &right_grip_pose {
-                                    if let Some(entity) = scene_data.entities.get_mut_element(right_gadget_index) {
-                                        entity.update_single_transform(1, &xrutil::pose_to_mat4(pose, &world_from_tracking))
+                                let poses = [left_grip_pose, right_grip_pose];
+                                for i in 0..poses.len() {
+                                    if let Some(pose) = &poses[i] {
+                                        world.set_transform(gadget_objects[i], decompose_matrix(&xrutil::pose_to_mat4(pose, &world_from_tracking)), &mut scene_data);
                                     }
                                 }
                             }
@@ -1510,10 +2308,8 @@ fn main() {
                                 let scales = [&left_water_pillar_scale, &right_water_pillar_scale];
                                 for i in 0..poses.len() {
                                     if let Some(p) = poses[i] {
-                                        if let Some(entity) = scene_data.entities.get_mut_element(water_cylinder_entity_index) {
-                                            let mm = xrutil::pose_to_mat4(&p, &world_from_tracking) * glm::scaling(scales[i]);
-                                            entity.update_single_transform(i, &mm);
-                                        }
+                                        let transform = Transform { scale: *scales[i], ..decompose_matrix(&xrutil::pose_to_mat4(&p, &world_from_tracking)) };
+                                        world.set_transform(water_pillar_objects[i], transform, &mut scene_data);
                                     }
                                 }
                             }
@@ -1559,6 +2355,26 @@ fn main() {
                                         perspective
                                     );
                                     render::main_scene(&scene_data, &view_data);
+
+                                    //Draw the HUD on top of the left wrist
+                                    if let (true, Some(pose)) = (show_hud, &left_grip_pose) {
+                                        let wrist_matrix = xrutil::pose_to_mat4(pose, &world_from_tracking) *
+                                                           glm::translation(&glm::vec3(-0.06, 0.05, 0.08)) *
+                                                           glm::rotation(-glm::half_pi::<f32>(), &glm::vec3(1.0, 0.0, 0.0)) *
+                                                           glm::scaling(&glm::vec3(0.12, 0.12, 0.12));
+                                        gl::Disable(gl::DEPTH_TEST);
+                                        gl::Disable(gl::CULL_FACE);
+                                        hud_renderer.draw(&hud_quads, &(view_data.view_projection * wrist_matrix));
+                                        gl::Enable(gl::DEPTH_TEST);
+                                        gl::Enable(gl::CULL_FACE);
+                                    }
+
+                                    //Fade the eye's view when the player is hurt or dying
+                                    if let Some((color, alpha)) = &screen_fade {
+                                        gl::Disable(gl::DEPTH_TEST);
+                                        hud_renderer.draw_fade(color, *alpha);
+                                        gl::Enable(gl::DEPTH_TEST);
+                                    }
     
                                     //Blit the MSAA image into the swapchain image
                                     let color_texture = sc_images[i][image_index as usize];
@@ -1687,6 +2503,27 @@ fn main() {
             gl::Viewport(0, 0, default_framebuffer.size.0, default_framebuffer.size.1);
             if true_wireframe { gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE); }
 
+            //Render the screen-space HUD in the bottom-left corner
+            if show_hud {
+                const HUD_PIXEL_SIZE: f32 = 300.0;
+                let window_height = screen_state.get_window_size().y as f32;
+                let clipping_from_hud = screen_state.get_clipping_from_screen() *
+                                        glm::translation(&glm::vec3(20.0, window_height - 20.0, 0.0)) *
+                                        glm::scaling(&glm::vec3(HUD_PIXEL_SIZE, -HUD_PIXEL_SIZE, 1.0));
+                hud_renderer.draw(&hud_quads, &clipping_from_hud);
+            }
+
+            //Render the prop editor's gizmo on top of the scene
+            if !hmd_pov {
+                let view_projection = screen_state.get_clipping_from_view() * screen_state.get_view_from_world();
+                prop_editor.draw_gizmo(&world, &hud_renderer, &view_projection, &camera_position);
+            }
+
+            //Fade the screen when the player is hurt or dying
+            if let Some((color, alpha)) = &screen_fade {
+                hud_renderer.draw_fade(color, *alpha);
+            }
+
             //Render Dear ImGui
             gl::UseProgram(imgui_program);
             glutil::bind_matrix4(imgui_program, "projection", screen_state.get_clipping_from_screen());
@@ -0,0 +1,171 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use ozy::collision::*;
+
+//Heights of the walkable ground sampled from the terrain on a square grid around some point
+//Each cell only knows about the topmost ground inside it, so walking under overhangs or through tunnels isn't possible
+pub struct NavGrid {
+    origin: glm::TVec2<f32>,                //World position of the corner of cell (0, 0)
+    size: usize,                            //Number of cells along each side
+    max_step: f32,                          //How far up the ground may rise from one cell to the next
+    heights: Vec<Option<f32>>
+}
+
+//A cell waiting to be expanded by the search, ordered so that the heap pops the cheapest one first
+struct OpenCell {
+    estimate: f32,
+    index: usize
+}
+
+impl PartialEq for OpenCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for OpenCell {}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl NavGrid {
+    pub const CELL_SIZE: f32 = 1.0;
+    const PROBE_HEIGHT: f32 = 50.0;         //How far above the center the ground is looked for
+
+    pub fn sample(terrain: &Terrain, center: &glm::TVec3<f32>, half_extent: f32, max_step: f32) -> Self {
+        let size = (2.0 * half_extent / Self::CELL_SIZE).ceil() as usize;
+        let origin = glm::vec2(center.x - half_extent, center.y - half_extent);
+        let mut heights = Vec::with_capacity(size * size);
+        for y in 0..size {
+            for x in 0..size {
+                let probe_origin = glm::vec3(
+                    origin.x + (x as f32 + 0.5) * Self::CELL_SIZE,
+                    origin.y + (y as f32 + 0.5) * Self::CELL_SIZE,
+                    center.z + Self::PROBE_HEIGHT
+                );
+                heights.push(ray_hit_terrain(terrain, &probe_origin, &glm::vec3(0.0, 0.0, -1.0)).map(|(_, ground)| ground.z));
+            }
+        }
+
+        NavGrid {
+            origin,
+            size,
+            max_step,
+            heights
+        }
+    }
+
+    //Returns the index of the cell containing a point, clamped to the edge of the grid
+    fn cell_index(&self, point: &glm::TVec3<f32>) -> usize {
+        let clamp = |coord: f32| { f32::min(f32::max(coord / Self::CELL_SIZE, 0.0), (self.size - 1) as f32) as usize };
+        clamp(point.y - self.origin.y) * self.size + clamp(point.x - self.origin.x)
+    }
+
+    fn cell_center(&self, index: usize) -> Option<glm::TVec3<f32>> {
+        let height = self.heights[index]?;
+        Some(glm::vec3(
+            self.origin.x + ((index % self.size) as f32 + 0.5) * Self::CELL_SIZE,
+            self.origin.y + ((index / self.size) as f32 + 0.5) * Self::CELL_SIZE,
+            height
+        ))
+    }
+
+    //Moving between cells is allowed when the ground doesn't rise too steeply. Any drop is fine
+    fn can_step(&self, from: usize, to: usize) -> bool {
+        match (self.heights[from], self.heights[to]) {
+            (Some(from), Some(to)) => { to - from < self.max_step }
+            _ => { false }
+        }
+    }
+
+    //Distance in cells assuming nothing is in the way, with diagonal moves allowed
+    fn heuristic(&self, from: usize, to: usize) -> f32 {
+        let dx = ((from % self.size) as f32 - (to % self.size) as f32).abs();
+        let dy = ((from / self.size) as f32 - (to / self.size) as f32).abs();
+        f32::max(dx, dy) + (std::f32::consts::SQRT_2 - 1.0) * f32::min(dx, dy)
+    }
+
+    //A* search from one point to another over the walkable cells
+    //When the goal can't be reached, the path leads to the reachable cell closest to it
+    //The returned waypoints are in reverse order, so the next one to walk to is at the end
+    pub fn find_path(&self, from: &glm::TVec3<f32>, to: &glm::TVec3<f32>) -> Option<Vec<glm::TVec3<f32>>> {
+        let start = self.cell_index(from);
+        let goal = self.cell_index(to);
+        if self.heights[start].is_none() {
+            return None;
+        }
+
+        let mut costs = vec![f32::INFINITY; self.heights.len()];
+        let mut came_from = vec![usize::MAX; self.heights.len()];
+        let mut open = BinaryHeap::new();
+        let mut closest = start;
+        let mut closest_estimate = self.heuristic(start, goal);
+        costs[start] = 0.0;
+        open.push(OpenCell { estimate: closest_estimate, index: start });
+
+        while let Some(OpenCell { estimate, index }) = open.pop() {
+            if index == goal {
+                closest = goal;
+                break;
+            }
+            if estimate - costs[index] > self.heuristic(index, goal) + 0.001 {
+                continue;                   //A cheaper way to this cell was already expanded
+            }
+            let remaining = self.heuristic(index, goal);
+            if remaining < closest_estimate {
+                closest = index;
+                closest_estimate = remaining;
+            }
+
+            let (x, y) = ((index % self.size) as isize, (index / self.size) as isize);
+            for dy in -1..=1isize {
+                for dx in -1..=1isize {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if (dx == 0 && dy == 0) || nx < 0 || ny < 0 || nx >= self.size as isize || ny >= self.size as isize {
+                        continue;
+                    }
+                    let neighbour = ny as usize * self.size + nx as usize;
+                    if !self.can_step(index, neighbour) {
+                        continue;
+                    }
+
+                    //Diagonal moves can't cut the corner of a cell that can't be walked through
+                    let step = if dx != 0 && dy != 0 {
+                        let side_x = y as usize * self.size + nx as usize;
+                        let side_y = ny as usize * self.size + x as usize;
+                        if !self.can_step(index, side_x) || !self.can_step(index, side_y) {
+                            continue;
+                        }
+                        std::f32::consts::SQRT_2
+                    } else {
+                        1.0
+                    };
+
+                    let cost = costs[index] + step;
+                    if cost < costs[neighbour] {
+                        costs[neighbour] = cost;
+                        came_from[neighbour] = index;
+                        open.push(OpenCell { estimate: cost + self.heuristic(neighbour, goal), index: neighbour });
+                    }
+                }
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut current = closest;
+        while current != start {
+            path.push(self.cell_center(current)?);
+            current = came_from[current];
+        }
+        Some(path)
+    }
+}
@@ -0,0 +1,199 @@
+use ozy::collision::*;
+use rand::Rng;
+
+//Describes the burst of secondary particles spawned when a particle hits the terrain
+#[derive(Copy, Clone, Debug)]
+pub struct SplashSettings {
+    pub count: usize,
+    pub speed: f32,
+    pub lifetime: f32,
+    pub scale: f32
+}
+
+pub struct Particle {
+    pub position: glm::TVec3<f32>,
+    pub velocity: glm::TVec3<f32>,
+    pub spawn_time: f32,
+    pub lifetime: f32,
+    pub scale: f32,
+    pub collides: bool,
+    pub splash: Option<SplashSettings>
+}
+
+//A source of particles. Its position and direction are expected to be updated every frame by whatever it is attached to
+pub struct ParticleEmitter {
+    pub position: glm::TVec3<f32>,
+    pub direction: glm::TVec3<f32>,
+    pub inherited_velocity: glm::TVec3<f32>,
+    pub spread_radians: f32,
+    pub speed: f32,
+    pub rate: f32,                          //Particles per second
+    pub lifetime: f32,
+    pub scale: f32,
+    pub collides: bool,
+    pub splash: Option<SplashSettings>,
+    pub active: bool,
+    spawn_accumulator: f32
+}
+
+impl ParticleEmitter {
+    pub fn new(speed: f32, rate: f32, lifetime: f32, scale: f32) -> Self {
+        ParticleEmitter {
+            position: glm::zero(),
+            direction: glm::vec3(0.0, 0.0, 1.0),
+            inherited_velocity: glm::zero(),
+            spread_radians: 0.05,
+            speed,
+            rate,
+            lifetime,
+            scale,
+            collides: false,
+            splash: None,
+            active: false,
+            spawn_accumulator: 0.0
+        }
+    }
+}
+
+pub struct ParticleSystem {
+    pub particles: Vec<Particle>,
+    pub gravity: glm::TVec3<f32>,
+    pub budget: usize,                      //Maximum number of live particles
+    pub max_spawns_per_frame: usize,
+    pub entity_index: usize,                //Index of the RenderEntity the particles are drawn with
+    spawned_this_frame: usize
+}
+
+impl ParticleSystem {
+    pub fn new(entity_index: usize, budget: usize, max_spawns_per_frame: usize) -> Self {
+        ParticleSystem {
+            particles: Vec::with_capacity(budget),
+            gravity: glm::vec3(0.0, 0.0, -9.8),
+            budget,
+            max_spawns_per_frame,
+            entity_index,
+            spawned_this_frame: 0
+        }
+    }
+
+    //Spawns a particle if the budget allows it
+    fn spawn(&mut self, particle: Particle) {
+        if self.particles.len() < self.budget && self.spawned_this_frame < self.max_spawns_per_frame {
+            self.particles.push(particle);
+            self.spawned_this_frame += 1;
+        }
+    }
+
+    //Spawns the particles an emitter has accumulated over this frame
+    pub fn emit(&mut self, emitter: &mut ParticleEmitter, delta_time: f32, elapsed_time: f32) {
+        if !emitter.active {
+            emitter.spawn_accumulator = 0.0;
+            return;
+        }
+
+        emitter.spawn_accumulator += emitter.rate * delta_time;
+        let mut rng = rand::thread_rng();
+        while emitter.spawn_accumulator >= 1.0 {
+            emitter.spawn_accumulator -= 1.0;
+            let direction = random_cone_direction(&mut rng, &emitter.direction, emitter.spread_radians);
+            self.spawn(Particle {
+                position: emitter.position,
+                velocity: direction * emitter.speed + emitter.inherited_velocity,
+                spawn_time: elapsed_time,
+                lifetime: emitter.lifetime * rng.gen_range(0.75, 1.25),
+                scale: emitter.scale,
+                collides: emitter.collides,
+                splash: emitter.splash
+            });
+        }
+    }
+
+    //Spawns a one-off burst of particles spraying out from a point
+    pub fn burst(&mut self, position: &glm::TVec3<f32>, normal: &glm::TVec3<f32>, settings: &SplashSettings, elapsed_time: f32) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..settings.count {
+            let direction = random_cone_direction(&mut rng, normal, glm::quarter_pi());
+            self.spawn(Particle {
+                position: *position,
+                velocity: direction * settings.speed * rng.gen_range(0.5, 1.0),
+                spawn_time: elapsed_time,
+                lifetime: settings.lifetime * rng.gen_range(0.75, 1.25),
+                scale: settings.scale,
+                collides: false,
+                splash: None
+            });
+        }
+    }
+
+    //Simulates all live particles for one frame, colliding them against the terrain
+    pub fn update(&mut self, terrain: &Terrain, delta_time: f32, elapsed_time: f32) {
+        self.spawned_this_frame = 0;
+
+        let mut splashes = Vec::new();
+        let gravity = self.gravity;
+        self.particles.retain(|particle| {
+            if elapsed_time - particle.spawn_time > particle.lifetime {
+                return false;
+            }
+
+            if particle.collides {
+                let step = particle.velocity * delta_time;
+                let step_length = glm::length(&step);
+                if step_length > 0.0 {
+                    let step_direction = step / step_length;
+                    if let Some((_, point)) = ray_hit_terrain(terrain, &particle.position, &step_direction) {
+                        if glm::distance(&particle.position, &point) <= step_length {
+                            if let Some(settings) = particle.splash {
+                                splashes.push((point, -step_direction, settings));
+                            }
+                            return false;
+                        }
+                    }
+                }
+            }
+            true
+        });
+
+        for particle in self.particles.iter_mut() {
+            particle.velocity += gravity * delta_time;
+            particle.position += particle.velocity * delta_time;
+        }
+
+        //Spawn the splash sub-emitter bursts
+        for (point, normal, settings) in splashes.iter() {
+            let reflected = glm::normalize(&(normal + glm::vec3(0.0, 0.0, 1.0)));
+            self.burst(point, &reflected, settings, elapsed_time);
+        }
+    }
+
+    //Computes the per-instance transform buffer for the particles, stretching each one along its velocity
+    pub fn transform_buffer(&self, elapsed_time: f32) -> Vec<f32> {
+        let mut buffer = vec![0.0; self.particles.len() * 16];
+        for i in 0..self.particles.len() {
+            let particle = &self.particles[i];
+            let speed = glm::length(&particle.velocity);
+            let orientation = if speed > 0.0 {
+                glm::quat_rotation(&glm::vec3(0.0, 1.0, 0.0), &(particle.velocity / speed))
+            } else {
+                glm::quat_identity()
+            };
+            let fade = 1.0 - (elapsed_time - particle.spawn_time) / particle.lifetime;
+            let scale = particle.scale * f32::max(fade, 0.0);
+            let mm = glm::translation(&particle.position) * glm::quat_to_mat4(&orientation) * glm::scaling(&glm::vec3(scale, scale * (1.0 + speed * 0.1), scale));
+            for k in 0..16 {
+                buffer[16 * i + k] = mm[k];
+            }
+        }
+        buffer
+    }
+}
+
+//Returns a random unit vector within a cone around the given direction
+pub fn random_cone_direction<R: Rng>(rng: &mut R, direction: &glm::TVec3<f32>, spread_radians: f32) -> glm::TVec3<f32> {
+    let helper = if f32::abs(direction.z) < 0.99 { glm::vec3(0.0, 0.0, 1.0) } else { glm::vec3(1.0, 0.0, 0.0) };
+    let right = glm::normalize(&glm::cross(direction, &helper));
+    let up = glm::cross(&right, direction);
+    let angle = rng.gen_range(0.0, glm::two_pi::<f32>());
+    let deviation = f32::tan(spread_radians) * f32::sqrt(rng.gen_range(0.0, 1.0f32));
+    glm::normalize(&(direction + (right * f32::cos(angle) + up * f32::sin(angle)) * deviation))
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use ozy::collision::*;
+use ozy::io::OzyMesh;
+use ozy::structs::OptionVec;
+use crate::ecs::World;
+use crate::render::RenderEntity;
+
+//CPU-side copy of a mesh's triangles for ray picking
+#[derive(Debug)]
+pub struct PickMesh {
+    pub positions: Vec<glm::TVec3<f32>>,
+    pub indices: Vec<u16>,
+    pub center: glm::TVec3<f32>,            //Model space bounding sphere
+    pub radius: f32
+}
+
+impl PickMesh {
+    //Pulls the positions out of an interleaved vertex array, which are assumed to be the first attribute
+    pub fn new(vertices: &[f32], indices: &[u16], attribute_sizes: &[usize]) -> Self {
+        let stride = attribute_sizes.iter().sum::<usize>();
+        let mut positions = Vec::with_capacity(vertices.len() / stride);
+        for i in 0..(vertices.len() / stride) {
+            positions.push(glm::vec3(vertices[stride * i], vertices[stride * i + 1], vertices[stride * i + 2]));
+        }
+
+        //The bounding sphere is centered on the bounding box
+        let mut min = glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = -min;
+        for position in positions.iter() {
+            min = glm::min2(&min, position);
+            max = glm::max2(&max, position);
+        }
+        let center = if positions.len() > 0 { (min + max) * 0.5 } else { glm::zero() };
+        let mut radius = 0.0;
+        for position in positions.iter() {
+            radius = f32::max(radius, glm::distance(&center, position));
+        }
+
+        PickMesh {
+            positions,
+            indices: Vec::from(indices),
+            center,
+            radius
+        }
+    }
+
+    //Returns the ray parameter of the closest triangle hit, with the ray in model space
+    fn ray_hit(&self, origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>) -> Option<f32> {
+        let mut closest = None;
+        let mut closest_t = f32::INFINITY;
+        for i in 0..(self.indices.len() / 3) {
+            let a = self.positions[self.indices[3 * i] as usize];
+            let b = self.positions[self.indices[3 * i + 1] as usize];
+            let c = self.positions[self.indices[3 * i + 2] as usize];
+            if let Some(t) = ray_hit_triangle(origin, direction, &a, &b, &c) {
+                if t < closest_t {
+                    closest_t = t;
+                    closest = Some(t);
+                }
+            }
+        }
+        closest
+    }
+}
+
+//Meshes are only picked against once something is in their bounding sphere, so they're read back from disk the first time they're needed
+pub struct PickMeshCache {
+    meshes: HashMap<String, Option<PickMesh>>
+}
+
+impl PickMeshCache {
+    pub fn new() -> Self {
+        PickMeshCache {
+            meshes: HashMap::new()
+        }
+    }
+
+    pub fn get(&mut self, path: &str) -> Option<&PickMesh> {
+        if !self.meshes.contains_key(path) {
+            let mesh = match OzyMesh::load(path) {
+                Some(meshdata) => {
+                    let attribute_sizes: Vec<usize> = meshdata.vertex_array.attribute_offsets.iter().map(|&size| size as usize).collect();
+                    Some(PickMesh::new(&meshdata.vertex_array.vertices, &meshdata.vertex_array.indices, &attribute_sizes))
+                }
+                None => {
+                    println!("Unable to load {} for picking", path);
+                    None
+                }
+            };
+            self.meshes.insert(String::from(path), mesh);
+        }
+        self.meshes[path].as_ref()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PickTarget {
+    Terrain,
+    Object(usize)
+}
+
+#[derive(Clone, Debug)]
+pub struct PickResult {
+    pub target: PickTarget,
+    pub point: glm::TVec3<f32>,
+    pub distance: f32
+}
+
+//Finds the closest thing hit by the ray
+//The terrain and any objects with colliders are tested against their collision data,
+//while every rendered object is tested against its bounding sphere and then refined against its triangles
+pub fn pick(world: &World, entities: &OptionVec<RenderEntity>, terrain: &Terrain, pick_meshes: &mut PickMeshCache, origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>) -> Option<PickResult> {
+    let mut closest = match ray_hit_terrain(terrain, origin, direction) {
+        Some((_, point)) => {
+            Some(PickResult {
+                target: PickTarget::Terrain,
+                point,
+                distance: glm::distance(origin, &point)
+            })
+        }
+        None => { None }
+    };
+
+    let max_distance = closest.as_ref().map(|result| result.distance).unwrap_or(f32::INFINITY);
+    if let Some((id, distance)) = world.ray_hit_objects(origin, direction, max_distance) {
+        closest = Some(PickResult {
+            target: PickTarget::Object(id),
+            point: origin + direction * distance,
+            distance
+        });
+    }
+
+    for id in 0..world.objects.len() {
+        let (transform, mesh) = match &world.objects[id] {
+            Some(object) => {
+                match &object.mesh {
+                    Some(mesh) => { (&object.transform, mesh) }
+                    None => { continue; }
+                }
+            }
+            _ => { continue; }
+        };
+        let path = match &entities[mesh.entity_index] {
+            Some(entity) if entity.should_be_rendered => { entity.source_path.as_str() }
+            _ => { continue; }
+        };
+        let pick_mesh = match pick_meshes.get(path) {
+            Some(pick_mesh) => { pick_mesh }
+            None => { continue; }
+        };
+        let matrix = transform.to_matrix() * mesh.local_transform;
+
+        //Bounding sphere test
+        let max_scale = f32::max(
+            glm::length(&glm::vec3(matrix[0], matrix[1], matrix[2])),
+            f32::max(glm::length(&glm::vec3(matrix[4], matrix[5], matrix[6])), glm::length(&glm::vec3(matrix[8], matrix[9], matrix[10])))
+        );
+        if max_scale == 0.0 {
+            continue;                       //Scaled down to nothing, which is how hidden objects are kept out of sight
+        }
+        let center = glm::vec4_to_vec3(&(matrix * glm::vec3_to_vec4(&pick_mesh.center))) + glm::vec3(matrix[12], matrix[13], matrix[14]);
+        let radius = pick_mesh.radius * max_scale;
+        let to_origin = origin - center;
+        let b = glm::dot(&to_origin, direction);
+        let discriminant = b * b - glm::dot(&to_origin, &to_origin) + radius * radius;
+        if discriminant < 0.0 || -b + f32::sqrt(discriminant) < 0.0 {
+            continue;
+        }
+        if let Some(result) = &closest {
+            if -b - f32::sqrt(discriminant) > result.distance {
+                continue;
+            }
+        }
+
+        //Triangle refine step in model space
+        let model_from_world = glm::affine_inverse(matrix);
+        let model_origin = glm::vec4_to_vec3(&(model_from_world * glm::vec4(origin.x, origin.y, origin.z, 1.0)));
+        let model_direction = glm::vec4_to_vec3(&(model_from_world * glm::vec3_to_vec4(direction)));
+        if let Some(t) = pick_mesh.ray_hit(&model_origin, &model_direction) {
+            let model_point = model_origin + model_direction * t;
+            let point = glm::vec4_to_vec3(&(matrix * glm::vec4(model_point.x, model_point.y, model_point.z, 1.0)));
+            let distance = glm::distance(origin, &point);
+            let is_closer = match &closest {
+                Some(result) => { distance < result.distance }
+                None => { true }
+            };
+            if is_closer {
+                closest = Some(PickResult {
+                    target: PickTarget::Object(id),
+                    point,
+                    distance
+                });
+            }
+        }
+    }
+    closest
+}
+
+//Möller-Trumbore ray/triangle intersection. Hits from either side of the triangle count
+fn ray_hit_triangle(origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>, a: &glm::TVec3<f32>, b: &glm::TVec3<f32>, c: &glm::TVec3<f32>) -> Option<f32> {
+    const EPSILON: f32 = 0.000001;
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = glm::cross(direction, &edge2);
+    let determinant = glm::dot(&edge1, &p);
+    if f32::abs(determinant) < EPSILON {
+        return None;
+    }
+
+    let inverse_determinant = 1.0 / determinant;
+    let s = origin - a;
+    let u = glm::dot(&s, &p) * inverse_determinant;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+
+    let q = glm::cross(&s, &edge1);
+    let v = glm::dot(direction, &q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = glm::dot(&edge2, &q) * inverse_determinant;
+    if t > EPSILON { Some(t) }
+    else { None }
+}
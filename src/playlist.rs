@@ -0,0 +1,272 @@
+use std::fs;
+use std::path::Path;
+use rand::seq::SliceRandom;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RepeatMode {
+    Off,
+    All,
+    One
+}
+
+//What the music panel needs to know about the playlist
+#[derive(Clone, Debug)]
+pub struct PlaylistStatus {
+    pub queue: Vec<String>,             //Track names in the order they'll be played
+    pub current: Option<usize>,         //Position of the playing track in the queue
+    pub shuffle: bool,
+    pub repeat: RepeatMode
+}
+
+impl PlaylistStatus {
+    pub fn new() -> Self {
+        PlaylistStatus {
+            queue: Vec::new(),
+            current: None,
+            shuffle: false,
+            repeat: RepeatMode::All
+        }
+    }
+}
+
+//The background music tracks and the order they're played in
+pub struct Playlist {
+    tracks: Vec<String>,                //Paths of the audio files
+    order: Vec<usize>,                  //Indices into tracks in play order, which is only different from the track order when shuffling
+    position: Option<usize>,            //Index into order of the current track
+    shuffle: bool,
+    pub repeat: RepeatMode
+}
+
+impl Playlist {
+    pub const EXTENSIONS: [&'static str; 4] = ["mp3", "wav", "ogg", "flac"];
+
+    pub fn new(tracks: Vec<String>) -> Self {
+        Playlist {
+            order: (0..tracks.len()).collect(),
+            tracks,
+            position: None,
+            shuffle: false,
+            repeat: RepeatMode::All
+        }
+    }
+
+    //Every audio file in the directory, in alphabetical order
+    pub fn from_directory(directory: &str) -> Self {
+        let mut tracks = Vec::new();
+        match fs::read_dir(directory) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    let is_audio = match path.extension().and_then(|ext| ext.to_str()) {
+                        Some(ext) => { Self::EXTENSIONS.contains(&ext.to_lowercase().as_str()) }
+                        None => { false }
+                    };
+                    if is_audio {
+                        tracks.push(path.to_string_lossy().into_owned());
+                    }
+                }
+            }
+            Err(e) => { println!("Unable to read music directory \"{}\": {}", directory, e); }
+        }
+        tracks.sort();
+        Self::new(tracks)
+    }
+
+    //Swaps in a new set of tracks, keeping the shuffle and repeat settings
+    pub fn set_tracks(&mut self, tracks: Vec<String>) {
+        let shuffle = self.shuffle;
+        let repeat = self.repeat;
+        *self = Self::new(tracks);
+        self.repeat = repeat;
+        self.set_shuffle(shuffle);
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        self.position.map(move |p| self.tracks[self.order[p]].as_str())
+    }
+
+    //Whether a track will follow the current one when it ends on its own
+    pub fn has_next(&self) -> bool {
+        match self.position {
+            _ if self.order.len() == 0 => { false }
+            Some(p) => { self.repeat != RepeatMode::Off || p + 1 < self.order.len() }
+            None => { true }
+        }
+    }
+
+    //Moves on to the next track when the current one ends or is skipped, returning the track to play, if any
+    pub fn advance(&mut self, skipped: bool) -> Option<&str> {
+        if self.order.len() == 0 {
+            return None;
+        }
+
+        self.position = match self.position {
+            None => { Some(0) }
+            Some(p) if self.repeat == RepeatMode::One && !skipped => { Some(p) }
+            Some(p) if p + 1 < self.order.len() => { Some(p + 1) }
+            Some(_) => {
+                if self.repeat == RepeatMode::Off && !skipped {
+                    None
+                } else {
+                    //Shuffle again each time around the list
+                    if self.shuffle {
+                        self.order.shuffle(&mut rand::thread_rng());
+                    }
+                    Some(0)
+                }
+            }
+        };
+        self.current()
+    }
+
+    pub fn previous(&mut self) -> Option<&str> {
+        if self.order.len() == 0 {
+            return None;
+        }
+
+        self.position = match self.position {
+            Some(p) if p > 0 => { Some(p - 1) }
+            _ => { Some(self.order.len() - 1) }
+        };
+        self.current()
+    }
+
+    //Jumps to a position in the play order
+    pub fn jump_to(&mut self, position: usize) -> Option<&str> {
+        if position < self.order.len() {
+            self.position = Some(position);
+        }
+        self.current()
+    }
+
+    //Jumps to the given file, adding it to the end of the playlist if it isn't already in it
+    pub fn play_path(&mut self, path: &str) -> Option<&str> {
+        let track = match self.tracks.iter().position(|t| t == path) {
+            Some(track) => { track }
+            None => {
+                self.tracks.push(String::from(path));
+                self.order.push(self.tracks.len() - 1);
+                self.tracks.len() - 1
+            }
+        };
+        self.position = self.order.iter().position(|&t| t == track);
+        self.current()
+    }
+
+    //Turning shuffle on puts the current track at the front of a shuffled order, so it keeps playing
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        let current_track = self.position.map(|p| self.order[p]);
+        self.shuffle = shuffle;
+        if shuffle {
+            self.order.shuffle(&mut rand::thread_rng());
+            if let Some(track) = current_track {
+                let i = self.order.iter().position(|&t| t == track).unwrap();
+                self.order.swap(0, i);
+            }
+        } else {
+            self.order = (0..self.tracks.len()).collect();
+        }
+        self.position = current_track.and_then(|track| self.order.iter().position(|&t| t == track));
+    }
+
+    pub fn status(&self) -> PlaylistStatus {
+        let queue = self.order.iter().map(|&t| track_name(&self.tracks[t])).collect();
+
+        PlaylistStatus {
+            queue,
+            current: self.position,
+            shuffle: self.shuffle,
+            repeat: self.repeat
+        }
+    }
+}
+
+//The name to show for a track, which is its file name without the extension
+pub fn track_name(path: &str) -> String {
+    match Path::new(path).file_stem() {
+        Some(stem) => { stem.to_string_lossy().into_owned() }
+        None => { String::from(path) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(track_count: usize) -> Playlist {
+        Playlist::new((0..track_count).map(|i| format!("music/{}.mp3", i)).collect())
+    }
+
+    fn advance(playlist: &mut Playlist, skipped: bool) -> Option<String> {
+        playlist.advance(skipped).map(String::from)
+    }
+
+    #[test]
+    fn repeat_off_stops_after_the_last_track() {
+        let mut playlist = playlist(3);
+        playlist.repeat = RepeatMode::Off;
+        assert_eq!(advance(&mut playlist, false).as_deref(), Some("music/0.mp3"));
+        assert_eq!(advance(&mut playlist, false).as_deref(), Some("music/1.mp3"));
+        assert_eq!(advance(&mut playlist, false).as_deref(), Some("music/2.mp3"));
+        assert!(!playlist.has_next());
+        assert_eq!(advance(&mut playlist, false), None);
+    }
+
+    #[test]
+    fn skipping_past_the_last_track_wraps_around() {
+        let mut playlist = playlist(2);
+        playlist.repeat = RepeatMode::Off;
+        playlist.jump_to(1);
+        assert_eq!(advance(&mut playlist, true).as_deref(), Some("music/0.mp3"));
+    }
+
+    #[test]
+    fn repeat_all_wraps_around() {
+        let mut playlist = playlist(2);
+        playlist.repeat = RepeatMode::All;
+        let played: Vec<Option<String>> = (0..5).map(|_| advance(&mut playlist, false)).collect();
+        let expected: Vec<Option<String>> = [0, 1, 0, 1, 0].iter().map(|i| Some(format!("music/{}.mp3", i))).collect();
+        assert_eq!(played, expected);
+    }
+
+    #[test]
+    fn repeat_one_replays_the_track_unless_skipped() {
+        let mut playlist = playlist(3);
+        playlist.repeat = RepeatMode::One;
+        assert_eq!(advance(&mut playlist, false).as_deref(), Some("music/0.mp3"));
+        assert_eq!(advance(&mut playlist, false).as_deref(), Some("music/0.mp3"));
+        assert_eq!(advance(&mut playlist, true).as_deref(), Some("music/1.mp3"));
+        assert_eq!(advance(&mut playlist, false).as_deref(), Some("music/1.mp3"));
+    }
+
+    #[test]
+    fn shuffle_plays_every_track_once_per_pass() {
+        let mut playlist = playlist(8);
+        playlist.repeat = RepeatMode::All;
+        playlist.set_shuffle(true);
+        for _ in 0..3 {
+            let mut played: Vec<String> = (0..8).map(|_| advance(&mut playlist, false).unwrap()).collect();
+            played.sort();
+            let mut expected: Vec<String> = (0..8).map(|i| format!("music/{}.mp3", i)).collect();
+            expected.sort();
+            assert_eq!(played, expected);
+        }
+    }
+
+    #[test]
+    fn turning_shuffle_on_and_off_keeps_the_current_track() {
+        let mut playlist = playlist(8);
+        playlist.jump_to(5);
+        playlist.set_shuffle(true);
+        assert_eq!(playlist.current(), Some("music/5.mp3"));
+        assert_eq!(playlist.status().current, Some(0));
+        playlist.set_shuffle(false);
+        assert_eq!(playlist.current(), Some("music/5.mp3"));
+        assert_eq!(playlist.status().current, Some(5));
+    }
+}
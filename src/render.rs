@@ -19,6 +19,7 @@ pub const TEXTURE_MAP_COUNT: usize = 3;
 #[derive(Clone, Debug)]
 pub struct RenderEntity {
     pub should_be_rendered: bool,
+    pub casts_shadow: bool,
     pub vao: GLuint,
     pub transform_buffer: GLuint,       //GPU buffer with one 4x4 homogenous transform per instance
     pub index_count: GLint,
@@ -28,7 +29,8 @@ pub struct RenderEntity {
     pub uv_offset: glm::TVec2<f32>,
     pub uv_scale: glm::TVec2<f32>,
     pub textures: [GLuint; TEXTURE_MAP_COUNT],
-    pub color: glm::TVec3<f32>
+    pub color: glm::TVec3<f32>,
+    pub source_path: String             //The .ozy the mesh was loaded from
 }
 
 impl RenderEntity {
@@ -71,6 +73,7 @@ impl RenderEntity {
                 let transform_buffer = glutil::create_instanced_transform_buffer(vao, instances, INSTANCED_ATTRIBUTE);
                 RenderEntity {
                     should_be_rendered: true,
+                    casts_shadow: true,
                     vao,
                     transform_buffer,
                     index_count: meshdata.vertex_array.indices.len() as GLint,
@@ -80,7 +83,8 @@ impl RenderEntity {
                     textures: [albedo, normal, roughness],
                     uv_scale: glm::vec2(1.0, 1.0),
                     uv_offset: glm::vec2(0.0, 0.0),
-                    color: glm::zero()
+                    color: glm::zero(),
+                    source_path: String::from(path)
                 }
             }
             None => {
@@ -100,6 +104,7 @@ impl RenderEntity {
 
         //Update GPU buffer storing transforms
 		unsafe {
+            //Grow the buffer when it's too small, after which later updates fit inside it
             if self.max_instances < self.active_instances as usize {
                 gl::BindBuffer(gl::ARRAY_BUFFER, self.transform_buffer);
                 gl::BufferData(
@@ -108,6 +113,7 @@ impl RenderEntity {
                     &transforms[0] as *const GLfloat as *const c_void,
                     gl::DYNAMIC_DRAW
                 );
+                self.max_instances = self.active_instances as usize;
             } else if transforms.len() > 0 {
                 gl::BindBuffer(gl::ARRAY_BUFFER, self.transform_buffer);
                 gl::BufferSubData(
@@ -254,6 +260,7 @@ pub unsafe fn main_scene(scene_data: &SceneData, view_data: &ViewData) {
             glutil::bind_vector3(p, "view_position", &view_data.view_position);
             glutil::bind_vector2(p, "uv_scale", &entity.uv_scale);
             glutil::bind_vector2(p, "uv_offset", &entity.uv_offset);
+            glutil::bind_vector3(p, "color", &entity.color);
 
             //fragment flag stuff
             let flag_names = ["visualize_normals", "visualize_lod", "visualize_shadowed", "visualize_cascade_zone"];
@@ -303,6 +310,10 @@ pub unsafe fn cascaded_shadow_map(shadow_map: &CascadedShadowMap, entities: &[Op
 
         for opt_entity in entities.iter() {
             if let Some(entity) = opt_entity {
+                if !entity.casts_shadow {
+                    continue;
+                }
+
                 gl::BindVertexArray(entity.vao);
                 gl::DrawElementsInstanced(gl::TRIANGLES, entity.index_count, gl::UNSIGNED_SHORT, ptr::null(), entity.active_instances);
             }
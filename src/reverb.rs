@@ -0,0 +1,155 @@
+use crate::volume::Volume;
+
+//Distance in meters over which a zone's reverb fades in after the listener crosses its edge
+const BLEND_DISTANCE: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReverbPreset {
+    Room,
+    Hall,
+    Cave,
+    Forest,
+    Plain
+}
+
+impl ReverbPreset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "room" => { Some(ReverbPreset::Room) }
+            "hall" => { Some(ReverbPreset::Hall) }
+            "cave" => { Some(ReverbPreset::Cave) }
+            "forest" => { Some(ReverbPreset::Forest) }
+            "plain" => { Some(ReverbPreset::Plain) }
+            _ => { None }
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReverbPreset::Room => { "room" }
+            ReverbPreset::Hall => { "hall" }
+            ReverbPreset::Cave => { "cave" }
+            ReverbPreset::Forest => { "forest" }
+            ReverbPreset::Plain => { "plain" }
+        }
+    }
+
+    //Values from the EFX reverb presets
+    fn params(self) -> ReverbParams {
+        let (density, diffusion, gain_hf, decay_time, decay_hf_ratio, reflections_gain, reflections_delay, late_reverb_gain, late_reverb_delay) = match self {
+            ReverbPreset::Room => { (0.4287, 1.0, 0.5929, 0.4, 0.83, 0.1503, 0.002, 1.0629, 0.003) }
+            ReverbPreset::Hall => { (1.0, 1.0, 0.5623, 3.92, 0.7, 0.2427, 0.02, 0.9977, 0.029) }
+            ReverbPreset::Cave => { (1.0, 1.0, 1.0, 2.91, 1.3, 0.5, 0.015, 0.7063, 0.022) }
+            ReverbPreset::Forest => { (1.0, 0.3, 0.0224, 1.49, 0.54, 0.0525, 0.162, 0.7682, 0.088) }
+            ReverbPreset::Plain => { (1.0, 0.21, 0.1, 1.49, 0.5, 0.0585, 0.179, 0.1089, 0.1) }
+        };
+        ReverbParams {
+            density,
+            diffusion,
+            gain_hf,
+            decay_time,
+            decay_hf_ratio,
+            reflections_gain,
+            reflections_delay,
+            late_reverb_gain,
+            late_reverb_delay,
+            wet: 1.0
+        }
+    }
+}
+
+//Settings for the reverb effect, named after the EFX reverb properties
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReverbParams {
+    pub density: f32,
+    pub diffusion: f32,
+    pub gain_hf: f32,
+    pub decay_time: f32,
+    pub decay_hf_ratio: f32,
+    pub reflections_gain: f32,
+    pub reflections_delay: f32,
+    pub late_reverb_gain: f32,
+    pub late_reverb_delay: f32,
+    pub wet: f32                        //Gain of the effect slot, where 0 is no reverb at all
+}
+
+impl ReverbParams {
+    pub fn dry() -> Self {
+        ReverbParams {
+            wet: 0.0,
+            ..ReverbPreset::Plain.params()
+        }
+    }
+
+    //Adds weight * other to each parameter
+    fn accumulate(&mut self, other: &ReverbParams, weight: f32) {
+        self.density += other.density * weight;
+        self.diffusion += other.diffusion * weight;
+        self.gain_hf += other.gain_hf * weight;
+        self.decay_time += other.decay_time * weight;
+        self.decay_hf_ratio += other.decay_hf_ratio * weight;
+        self.reflections_gain += other.reflections_gain * weight;
+        self.reflections_delay += other.reflections_delay * weight;
+        self.late_reverb_gain += other.late_reverb_gain * weight;
+        self.late_reverb_delay += other.late_reverb_delay * weight;
+        self.wet += other.wet * weight;
+    }
+}
+
+//A level-authored region that gives sounds inside it a reverb
+#[derive(Clone, Debug)]
+pub struct ReverbZone {
+    pub volume: Volume,
+    pub preset: ReverbPreset,
+    pub size: f32,                      //Scales the decay time and delays, so bigger spaces ring longer
+    pub wet: f32
+}
+
+impl ReverbZone {
+    //The preset's values adjusted for the zone's size and wet level, kept within the ranges EFX accepts
+    pub fn params(&self) -> ReverbParams {
+        let base = self.preset.params();
+        ReverbParams {
+            decay_time: f32::max(0.1, f32::min(base.decay_time * self.size, 20.0)),
+            reflections_delay: f32::min(base.reflections_delay * self.size, 0.3),
+            late_reverb_delay: f32::min(base.late_reverb_delay * self.size, 0.1),
+            wet: f32::max(0.0, f32::min(self.wet, 1.0)),
+            ..base
+        }
+    }
+}
+
+//Mixes the zones the listener is in, weighted by how far inside each one they are
+//Wherever the zones add up to less than full weight, the rest is dry
+pub fn blend_zones(zones: &[ReverbZone], listener_position: &glm::TVec3<f32>) -> ReverbParams {
+    let mut weighted = Vec::new();
+    let mut total_weight = 0.0;
+    for zone in zones.iter() {
+        let weight = f32::min(zone.volume.depth(listener_position) / BLEND_DISTANCE, 1.0);
+        if weight > 0.0 {
+            weighted.push((zone.params(), weight));
+            total_weight += weight;
+        }
+    }
+    if total_weight == 0.0 {
+        return ReverbParams::dry();
+    }
+
+    let mut blended = ReverbParams {
+        density: 0.0,
+        diffusion: 0.0,
+        gain_hf: 0.0,
+        decay_time: 0.0,
+        decay_hf_ratio: 0.0,
+        reflections_gain: 0.0,
+        reflections_delay: 0.0,
+        late_reverb_gain: 0.0,
+        late_reverb_delay: 0.0,
+        wet: 0.0
+    };
+    for (params, weight) in weighted.iter() {
+        blended.accumulate(params, weight / total_weight);
+    }
+    blended.wet *= f32::min(total_weight, 1.0);
+    blended
+}
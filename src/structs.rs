@@ -14,7 +14,8 @@ pub enum MoveState {
 #[derive(PartialEq, Eq)]
 pub enum ClickAction {
     None,
-    PlacingDragon,
+    PlacingProp,
+    EditingProps,
     SpawningTotoro
 }
 
@@ -58,17 +59,45 @@ pub struct Player {
     pub movement_state: MoveState,
     pub radius: f32,
     pub jumps_remaining: usize,
-    pub was_holding_jump: bool
+    pub was_holding_jump: bool,
+    pub health: f32,
+    pub last_damage_time: f32
 }
 
 impl Player {
     pub const MAX_JUMPS: usize = 2;
+    pub const MAX_HEALTH: f32 = 100.0;
+    pub const INVULNERABILITY_DURATION: f32 = 1.0;
+    pub const SAFE_FALL_SPEED: f32 = 12.0;
+    pub const FALL_DAMAGE_PER_SPEED: f32 = 8.0;     //Damage per m/s of impact speed above the safe speed
+
+    //Hurts the player unless they were hurt too recently. Returns true if the damage was taken
+    pub fn damage(&mut self, amount: f32, elapsed_time: f32) -> bool {
+        if self.is_dead() || elapsed_time - self.last_damage_time < Self::INVULNERABILITY_DURATION {
+            return false;
+        }
+        self.health = f32::max(self.health - amount, 0.0);
+        self.last_damage_time = elapsed_time;
+        true
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.0
+    }
 }
 
-pub fn ground_player(player: &mut Player, max_energy: &mut f32) {    
+pub fn ground_player(player: &mut Player, gadget_energy: &mut [f32], elapsed_time: f32) {
+    //Landing too hard hurts
+    let impact_speed = -player.tracking_velocity.z;
+    if impact_speed > Player::SAFE_FALL_SPEED {
+        player.damage((impact_speed - Player::SAFE_FALL_SPEED) * Player::FALL_DAMAGE_PER_SPEED, elapsed_time);
+    }
+
     player.tracking_velocity = glm::zero();
     player.jumps_remaining = Player::MAX_JUMPS;
-    *max_energy = Gadget::MAX_ENERGY;
+    for energy in gadget_energy.iter_mut() {
+        *energy = Gadget::MAX_ENERGY;
+    }
 }
 
 pub fn set_player_falling(player: &mut Player) {
@@ -76,10 +105,39 @@ pub fn set_player_falling(player: &mut Player) {
     player.movement_state = MoveState::Falling;
 }
 
-//But what _is_ a Totoro?
-pub struct Totoro {
-    pub position: glm::TVec3<f32>,
-    pub creation_time: f32
+//Marks the point where a projectile made contact with something
+#[derive(Clone, Debug)]
+pub struct HitMarker {
+    pub spawn_time: f32
+}
+
+impl HitMarker {
+    pub const LIFETIME: f32 = 0.75;
+    pub const MAX_COUNT: usize = 64;            //Instances allocated for the markers. The oldest marker is dropped to make room past this
+
+    //Size of a fresh marker, which shrinks away to nothing over its lifetime
+    pub fn full_scale() -> glm::TVec3<f32> {
+        glm::vec3(0.15, 0.02, 0.15)
+    }
+}
+
+//Returns the distance along the ray to the first intersection with the sphere
+//A ray that starts inside the sphere hits it right away
+pub fn ray_hit_sphere(origin: &glm::TVec3<f32>, direction: &glm::TVec3<f32>, sphere: &Sphere) -> Option<f32> {
+    let to_origin = origin - sphere.focus;
+    let b = glm::dot(&to_origin, direction);
+    let c = glm::dot(&to_origin, &to_origin) - sphere.radius * sphere.radius;
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let t = -b - f32::sqrt(discriminant);
+    if t >= 0.0 { Some(t) }
+    else { None }
 }
 
 #[derive(PartialEq, Eq)]
@@ -91,21 +149,36 @@ enum TokenType {
 
 pub struct Configuration {
     pub int_options: HashMap<String, u32>,
+    pub float_options: HashMap<String, f32>,
     pub string_options: HashMap<String, String>
 }
 
 impl Configuration {
     pub const WINDOWED_WIDTH: &'static str = "windowed_width";
     pub const WINDOWED_HEIGHT: &'static str = "windowed_height";
-    const INTS: [&'static str; 2] = [Self::WINDOWED_WIDTH, Self::WINDOWED_HEIGHT];
+    pub const MASTER_MUTED: &'static str = "master_muted";
+    pub const MUSIC_MUTED: &'static str = "music_muted";
+    pub const SFX_MUTED: &'static str = "sfx_muted";
+    pub const UI_MUTED: &'static str = "ui_muted";
+    pub const AMBIENCE_MUTED: &'static str = "ambience_muted";
+    const INTS: [&'static str; 7] = [Self::WINDOWED_WIDTH, Self::WINDOWED_HEIGHT, Self::MASTER_MUTED, Self::MUSIC_MUTED, Self::SFX_MUTED, Self::UI_MUTED, Self::AMBIENCE_MUTED];
+
+    pub const MASTER_VOLUME: &'static str = "master_volume";
+    pub const MUSIC_VOLUME: &'static str = "music_volume";
+    pub const SFX_VOLUME: &'static str = "sfx_volume";
+    pub const UI_VOLUME: &'static str = "ui_volume";
+    pub const AMBIENCE_VOLUME: &'static str = "ambience_volume";
+    const FLOATS: [&'static str; 5] = [Self::MASTER_VOLUME, Self::MUSIC_VOLUME, Self::SFX_VOLUME, Self::UI_VOLUME, Self::AMBIENCE_VOLUME];
 
     pub const LEVEL_NAME: &'static str = "level_name";
-    const STRS: [&'static str; 1] = [Self::LEVEL_NAME];
+    pub const AUDIO_BACKEND: &'static str = "audio_backend";         //openal, null, or the path of a .wav file to record into
+    const STRS: [&'static str; 2] = [Self::LEVEL_NAME, Self::AUDIO_BACKEND];
 
     pub const CONFIG_FILEPATH: &'static str = "settings.cfg";
 
     pub fn from_file(filepath: &str) -> Option<Self> {
         let mut int_options = HashMap::with_capacity(Self::INTS.len());
+        let mut float_options = HashMap::with_capacity(Self::FLOATS.len());
         let mut string_options = HashMap::with_capacity(Self::STRS.len());
 
         match File::open(filepath) {
@@ -156,7 +229,10 @@ impl Configuration {
                                     int_options.insert(String::from(tokens[0]), int);
                                 }
                                 TokenType::Float => {
-
+                                    match tokens[2].parse::<f32>() {
+                                        Ok(float) => { float_options.insert(String::from(tokens[0]), float); }
+                                        Err(e) => { println!("Couldn't parse {} as a float: {}", tokens[2], e); }
+                                    }
                                 }
                                 TokenType::String => {
                                     string_options.insert(String::from(tokens[0]), String::from(tokens[2]));
@@ -179,6 +255,7 @@ impl Configuration {
         Some(
             Configuration {
                 int_options,
+                float_options,
                 string_options
             }
         )
@@ -187,19 +264,36 @@ impl Configuration {
     pub fn to_file(&self, filepath: &str) {
         match File::create(filepath) {
             Ok(mut file) => {
+                //Options that were never set are left out of the file
+                let mut lines = Vec::with_capacity(Self::INTS.len() + Self::FLOATS.len() + Self::STRS.len());
+
                 //Write int options
                 for label in &Self::INTS {
-                    let string = format!("{} = {}\n", label, self.int_options.get(*label).unwrap());
-                    if let Err(e) = file.write(string.as_bytes()) {
-                        println!("Error writing configuration file: {}", e);
-                        return;
+                    if let Some(int) = self.int_options.get(*label) {
+                        lines.push(format!("{} = {}\n", label, int));
+                    }
+                }
+
+                //Write float options at full precision, always with a decimal point so they're read back as floats
+                for label in &Self::FLOATS {
+                    if let Some(float) = self.float_options.get(*label) {
+                        let mut float = format!("{}", float);
+                        if !float.contains('.') {
+                            float.push_str(".0");
+                        }
+                        lines.push(format!("{} = {}\n", label, float));
                     }
                 }
     
                 //Write string options
                 for label in &Self::STRS {
-                    let string = format!("{} = {}\n", label, self.string_options.get(*label).unwrap());
-                    if let Err(e) = file.write(string.as_bytes()) {
+                    if let Some(string) = self.string_options.get(*label) {
+                        lines.push(format!("{} = {}\n", label, string));
+                    }
+                }
+
+                for line in lines.iter() {
+                    if let Err(e) = file.write(line.as_bytes()) {
                         println!("Error writing configuration file: {}", e);
                         return;
                     }
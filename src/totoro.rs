@@ -0,0 +1,146 @@
+use ozy::collision::*;
+use rand::Rng;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TotoroState {
+    Idle { until: f32 },
+    Wandering { target: glm::TVec3<f32> },
+    Following,
+    Scattering { until: f32, direction: glm::TVec3<f32> }
+}
+
+//But what _is_ a Totoro?
+//The Totoro's position lives in the transform of the object that owns this behaviour
+#[derive(Clone, Debug)]
+pub struct Totoro {
+    pub velocity: glm::TVec3<f32>,          //Velocity from external forces like knockback, which decays over time
+    pub home: glm::TVec3<f32>,
+    pub heading: f32,                       //Rotation about the z-axis
+    pub state: TotoroState,
+    pub hover_phase: f32                    //Angle through the bobbing motion, which speeds up and slows down with the music
+}
+
+impl Totoro {
+    pub const HIT_RADIUS: f32 = 0.6;
+    pub const FRICTION: f32 = 3.0;
+    pub const WANDER_RADIUS: f32 = 4.0;
+    pub const WANDER_SPEED: f32 = 1.0;
+    pub const FOLLOW_SPEED: f32 = 2.5;
+    pub const SCATTER_SPEED: f32 = 6.0;
+    pub const SCATTER_DURATION: f32 = 2.0;
+    pub const FOLLOW_START_DISTANCE: f32 = 6.0;
+    pub const FOLLOW_STOP_DISTANCE: f32 = 1.5;
+    pub const FOLLOW_LOSE_DISTANCE: f32 = 10.0;
+    const GROUND_PROBE_HEIGHT: f32 = 2.0;
+    const MAX_STEP_HEIGHT: f32 = 0.5;
+
+    pub fn new(position: glm::TVec3<f32>, creation_time: f32) -> Self {
+        Totoro {
+            velocity: glm::zero(),
+            home: position,
+            heading: 0.0,
+            state: TotoroState::Idle { until: creation_time + 1.0 },
+            hover_phase: 0.0
+        }
+    }
+
+    //Makes the Totoro run away from a point
+    pub fn scare(&mut self, position: &glm::TVec3<f32>, source: &glm::TVec3<f32>, elapsed_time: f32) {
+        let away = position - source;
+        let away = glm::vec3(away.x, away.y, 0.0);
+        let direction = if away == glm::zero() { glm::vec3(1.0, 0.0, 0.0) } else { glm::normalize(&away) };
+        self.state = TotoroState::Scattering {
+            until: elapsed_time + Self::SCATTER_DURATION,
+            direction
+        };
+    }
+
+    //Advances the Totoro's state machine and moves it along the terrain
+    pub fn update(&mut self, position: &mut glm::TVec3<f32>, terrain: &Terrain, player_position: &glm::TVec3<f32>, delta_time: f32, elapsed_time: f32) {
+        let mut rng = rand::thread_rng();
+        let to_player = glm::vec3(player_position.x - position.x, player_position.y - position.y, 0.0);
+        let player_distance = glm::length(&to_player);
+
+        //State transitions
+        self.state = match self.state {
+            TotoroState::Idle { until } => {
+                if player_distance < Self::FOLLOW_START_DISTANCE {
+                    TotoroState::Following
+                } else if elapsed_time > until {
+                    let angle = rng.gen_range(0.0, glm::two_pi::<f32>());
+                    let distance = rng.gen_range(0.0, Self::WANDER_RADIUS);
+                    TotoroState::Wandering { target: self.home + glm::vec3(f32::cos(angle), f32::sin(angle), 0.0) * distance }
+                } else {
+                    self.state
+                }
+            }
+            TotoroState::Wandering { target } => {
+                let remaining = glm::vec3(target.x - position.x, target.y - position.y, 0.0);
+                if player_distance < Self::FOLLOW_START_DISTANCE {
+                    TotoroState::Following
+                } else if glm::length(&remaining) < 0.1 {
+                    TotoroState::Idle { until: elapsed_time + rng.gen_range(1.0, 4.0) }
+                } else {
+                    self.state
+                }
+            }
+            TotoroState::Following => {
+                if player_distance > Self::FOLLOW_LOSE_DISTANCE {
+                    TotoroState::Idle { until: elapsed_time + 1.0 }
+                } else {
+                    self.state
+                }
+            }
+            TotoroState::Scattering { until, .. } => {
+                if elapsed_time > until {
+                    //Settle down wherever the Totoro ended up
+                    self.home = *position;
+                    TotoroState::Idle { until: elapsed_time + 1.0 }
+                } else {
+                    self.state
+                }
+            }
+        };
+
+        //Compute this frame's movement from the current state
+        let walk_velocity = match self.state {
+            TotoroState::Idle { .. } => { glm::zero() }
+            TotoroState::Wandering { target } => {
+                let remaining = glm::vec3(target.x - position.x, target.y - position.y, 0.0);
+                glm::normalize(&remaining) * Self::WANDER_SPEED
+            }
+            TotoroState::Following => {
+                if player_distance > Self::FOLLOW_STOP_DISTANCE {
+                    to_player / player_distance * Self::FOLLOW_SPEED
+                } else {
+                    glm::zero()
+                }
+            }
+            TotoroState::Scattering { direction, .. } => { direction * Self::SCATTER_SPEED }
+        };
+
+        if walk_velocity != glm::zero() {
+            self.heading = f32::atan2(walk_velocity.y, walk_velocity.x);
+        }
+
+        //Apply any knockback the Totoro has received
+        let step = (walk_velocity + self.velocity) * delta_time;
+        self.velocity -= self.velocity * f32::min(Self::FRICTION * delta_time, 1.0);
+
+        //Only take the step if there is ground underneath the new position
+        let new_position = *position + glm::vec3(step.x, step.y, 0.0);
+        let probe_origin = new_position + glm::vec3(0.0, 0.0, Self::GROUND_PROBE_HEIGHT);
+        let mut stepped = false;
+        if let Some((_, ground)) = ray_hit_terrain(terrain, &probe_origin, &glm::vec3(0.0, 0.0, -1.0)) {
+            if ground.z - position.z < Self::MAX_STEP_HEIGHT {
+                *position = ground;
+                stepped = true;
+            }
+        }
+
+        //Give up on a wander target that can't be reached
+        if let (false, TotoroState::Wandering { .. }) = (stepped, self.state) {
+            self.state = TotoroState::Idle { until: elapsed_time + 1.0 };
+        }
+    }
+}
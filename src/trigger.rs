@@ -0,0 +1,114 @@
+use ozy::collision::*;
+use crate::volume::Volume;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpawnKind {
+    Totoro,
+    Chicken
+}
+
+//Something a trigger does when the player enters or leaves it
+#[derive(Clone, Debug)]
+pub enum TriggerAction {
+    Sound(String),                                      //Path of a sound effect, or the name of a synthesized one
+    Bgm(String),                                        //Path to the new background music
+    Spawn(SpawnKind, glm::TVec3<f32>),
+    SetVisibility(String, Option<bool>),                //Name of a level .ozy, and whether to show it or None to toggle it
+    Teleport(glm::TVec3<f32>)
+}
+
+impl TriggerAction {
+    //Builds an action from the tokens after "enter" or "exit" in a level's gameplay file
+    //  sound path
+    //  bgm path
+    //  spawn totoro|chicken x y z
+    //  show|hide|toggle name.ozy
+    //  teleport x y z
+    pub fn parse(tokens: &[&str]) -> Option<Self> {
+        let position = |tokens: &[&str]| -> Option<glm::TVec3<f32>> {
+            match tokens {
+                [x, y, z] => {
+                    match (x.parse(), y.parse(), z.parse()) {
+                        (Ok(x), Ok(y), Ok(z)) => { Some(glm::vec3(x, y, z)) }
+                        _ => { None }
+                    }
+                }
+                _ => { None }
+            }
+        };
+
+        match tokens {
+            ["sound", sound] => { Some(TriggerAction::Sound(String::from(*sound))) }
+            ["bgm", path] => { Some(TriggerAction::Bgm(String::from(*path))) }
+            ["spawn", "totoro", rest @ ..] => { position(rest).map(|p| TriggerAction::Spawn(SpawnKind::Totoro, p)) }
+            ["spawn", "chicken", rest @ ..] => { position(rest).map(|p| TriggerAction::Spawn(SpawnKind::Chicken, p)) }
+            ["show", name] => { Some(TriggerAction::SetVisibility(String::from(*name), Some(true))) }
+            ["hide", name] => { Some(TriggerAction::SetVisibility(String::from(*name), Some(false))) }
+            ["toggle", name] => { Some(TriggerAction::SetVisibility(String::from(*name), None)) }
+            ["teleport", rest @ ..] => { position(rest).map(TriggerAction::Teleport) }
+            _ => { None }
+        }
+    }
+
+    //The inverse of parse()
+    pub fn to_gameplay_string(&self) -> String {
+        match self {
+            TriggerAction::Sound(sound) => { format!("sound {}", sound) }
+            TriggerAction::Bgm(path) => { format!("bgm {}", path) }
+            TriggerAction::Spawn(kind, p) => {
+                let kind = match kind {
+                    SpawnKind::Totoro => { "totoro" }
+                    SpawnKind::Chicken => { "chicken" }
+                };
+                format!("spawn {} {} {} {}", kind, p.x, p.y, p.z)
+            }
+            TriggerAction::SetVisibility(name, visible) => {
+                match visible {
+                    Some(true) => { format!("show {}", name) }
+                    Some(false) => { format!("hide {}", name) }
+                    None => { format!("toggle {}", name) }
+                }
+            }
+            TriggerAction::Teleport(p) => { format!("teleport {} {} {}", p.x, p.y, p.z) }
+        }
+    }
+}
+
+//A level-authored region that fires actions when the player walks into or out of it
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    pub volume: Volume,
+    pub on_enter: Vec<TriggerAction>,
+    pub on_exit: Vec<TriggerAction>,
+    occupied: bool
+}
+
+impl Trigger {
+    pub fn new(volume: Volume) -> Self {
+        Trigger {
+            volume,
+            on_enter: Vec::new(),
+            on_exit: Vec::new(),
+            occupied: false
+        }
+    }
+
+    pub fn is_occupied(&self) -> bool {
+        self.occupied
+    }
+}
+
+//Tests every trigger against the player and returns the actions of the ones that were entered or exited this frame, in order
+pub fn update_triggers(triggers: &mut [Trigger], player_capsule: &Capsule) -> Vec<TriggerAction> {
+    let mut fired = Vec::new();
+    for trigger in triggers.iter_mut() {
+        let occupied = trigger.volume.intersects_capsule(player_capsule);
+        if occupied && !trigger.occupied {
+            fired.extend_from_slice(&trigger.on_enter);
+        } else if !occupied && trigger.occupied {
+            fired.extend_from_slice(&trigger.on_exit);
+        }
+        trigger.occupied = occupied;
+    }
+    fired
+}
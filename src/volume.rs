@@ -0,0 +1,75 @@
+use ozy::collision::*;
+
+//A simple region of space that level data can refer to
+#[derive(Clone, Debug)]
+pub enum Volume {
+    Box { center: glm::TVec3<f32>, half_extents: glm::TVec3<f32> },        //Axis-aligned
+    Sphere { center: glm::TVec3<f32>, radius: f32 }
+}
+
+impl Volume {
+    //Builds a volume from its shape name and numbers in a level's gameplay file
+    //  box cx cy cz hx hy hz
+    //  sphere cx cy cz r
+    //Returns the volume and the numbers that were left over
+    pub fn parse<'a>(shape: &str, numbers: &'a [f32]) -> Option<(Self, &'a [f32])> {
+        match shape {
+            "box" if numbers.len() >= 6 => {
+                let volume = Volume::Box {
+                    center: glm::vec3(numbers[0], numbers[1], numbers[2]),
+                    half_extents: glm::vec3(numbers[3], numbers[4], numbers[5])
+                };
+                Some((volume, &numbers[6..]))
+            }
+            "sphere" if numbers.len() >= 4 => {
+                let volume = Volume::Sphere {
+                    center: glm::vec3(numbers[0], numbers[1], numbers[2]),
+                    radius: numbers[3]
+                };
+                Some((volume, &numbers[4..]))
+            }
+            _ => { None }
+        }
+    }
+
+    //The inverse of parse()
+    pub fn to_gameplay_string(&self) -> String {
+        match self {
+            Volume::Box { center, half_extents } => { format!("box {} {} {} {} {} {}", center.x, center.y, center.z, half_extents.x, half_extents.y, half_extents.z) }
+            Volume::Sphere { center, radius } => { format!("sphere {} {} {} {}", center.x, center.y, center.z, radius) }
+        }
+    }
+
+    //How far inside the volume a point is, which is negative when it's outside
+    pub fn depth(&self, point: &glm::TVec3<f32>) -> f32 {
+        match self {
+            Volume::Sphere { center, radius } => { radius - glm::distance(point, center) }
+            Volume::Box { center, half_extents } => {
+                let offset = glm::abs(&(point - center));
+                let inside = half_extents - offset;
+                f32::min(inside.x, f32::min(inside.y, inside.z))
+            }
+        }
+    }
+
+    pub fn intersects_capsule(&self, capsule: &Capsule) -> bool {
+        match self {
+            Volume::Sphere { center, radius } => {
+                let closest = closest_point_on_line_segment(center, &capsule.segment.p0, &capsule.segment.p1);
+                glm::distance(&closest, center) < radius + capsule.radius
+            }
+            Volume::Box { center, half_extents } => {
+                //Alternate between the closest point on the box and on the segment until they settle
+                let min = center - half_extents;
+                let max = center + half_extents;
+                let mut segment_point = closest_point_on_line_segment(center, &capsule.segment.p0, &capsule.segment.p1);
+                let mut box_point = glm::clamp_vec(&segment_point, &min, &max);
+                for _ in 0..3 {
+                    segment_point = closest_point_on_line_segment(&box_point, &capsule.segment.p0, &capsule.segment.p1);
+                    box_point = glm::clamp_vec(&segment_point, &min, &max);
+                }
+                glm::distance(&segment_point, &box_point) < capsule.radius
+            }
+        }
+    }
+}